@@ -0,0 +1,228 @@
+//! Overhead of the multiplexer compared with calling the services directly
+//!
+//! Everything runs in-process: requests are handed to the services without any
+//! connection, so the numbers only contain the routing, the per-connection make
+//! and the body encapsulation costs.
+
+use std::{
+	convert::Infallible,
+	fmt::Debug,
+	task::{Context, Poll},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::task::noop_waker_ref;
+use hello_world_tonic::{
+	hello_world::{greeter_server::GreeterServer, HelloRequest, HelloStreamRequest},
+	server::MyGreeter,
+};
+use hyper::{
+	body::{Buf, Bytes, HttpBody},
+	header::CONTENT_TYPE,
+	service::service_fn,
+	Body, Request, Response,
+};
+use prost::Message;
+use tokio::runtime::Runtime;
+use tower::{make::Shared, Service};
+
+use multiplex_tonic_hyper::{MakeMultiplexer, Multiplexer};
+
+static CHUNK: [u8; 16 * 1024] = [0; 16 * 1024];
+
+fn runtime() -> Runtime {
+	tokio::runtime::Builder::new_current_thread()
+		.enable_all()
+		.build()
+		.unwrap()
+}
+
+/// Polls the service once, which is enough for the always ready services used here
+fn ready<S: Service<R>, R>(service: &mut S)
+where
+	S::Error: Debug,
+{
+	let mut cx = Context::from_waker(noop_waker_ref());
+	match service.poll_ready(&mut cx) {
+		Poll::Ready(result) => result.unwrap(),
+		Poll::Pending => panic!("benchmarked services must always be ready"),
+	}
+}
+
+/// Reads the whole body, returning its length
+async fn consume<B: HttpBody>(body: B) -> usize
+where
+	B::Error: Debug,
+{
+	let mut body = Box::pin(body);
+	let mut len = 0;
+	while let Some(chunk) = body.data().await {
+		len += chunk.unwrap().remaining();
+	}
+	body.trailers().await.unwrap();
+	len
+}
+
+async fn empty(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	Ok(Response::new(Body::empty()))
+}
+
+fn request(content_type: Option<&str>) -> Request<Body> {
+	let mut request = Request::post("/helloworld.Greeter/SayHello");
+	if let Some(content_type) = content_type {
+		request = request.header(CONTENT_TYPE, content_type);
+	}
+	request.body(Body::empty()).unwrap()
+}
+
+fn grpc_request(path: &str, message: impl Message) -> Request<Body> {
+	let message = message.encode_to_vec();
+	let mut frame = vec![0];
+	frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+	frame.extend_from_slice(&message);
+	Request::post(path)
+		.header(CONTENT_TYPE, "application/grpc")
+		.header("te", "trailers")
+		.body(Body::from(frame))
+		.unwrap()
+}
+
+/// Cost of the routing decision on a single request
+fn routing(c: &mut Criterion) {
+	let runtime = runtime();
+	let mut group = c.benchmark_group("routing");
+
+	let mut direct = service_fn(empty);
+	group.bench_function("direct", |b| {
+		b.to_async(&runtime).iter(|| {
+			ready(&mut direct);
+			direct.call(request(None))
+		})
+	});
+
+	let mut multiplexer = Multiplexer::new(service_fn(empty), service_fn(empty));
+	for (name, content_type) in [
+		("web", None),
+		("grpc", Some("application/grpc")),
+		("grpc-web", Some("application/grpc-web+proto")),
+	] {
+		group.bench_function(BenchmarkId::new("multiplexer", name), |b| {
+			b.to_async(&runtime).iter(|| {
+				ready(&mut multiplexer);
+				multiplexer.call(request(content_type))
+			})
+		});
+	}
+	group.finish();
+}
+
+/// Cost of making the services of a new connection
+fn make(c: &mut Criterion) {
+	let runtime = runtime();
+	let mut group = c.benchmark_group("make");
+
+	let mut shared = Shared::new(service_fn(empty));
+	group.bench_function("shared", |b| {
+		b.to_async(&runtime).iter(|| {
+			ready::<_, ()>(&mut shared);
+			shared.call(())
+		})
+	});
+
+	let mut make_multiplexer = MakeMultiplexer::new(
+		Shared::new(service_fn(empty)),
+		Shared::new(service_fn(empty)),
+	);
+	group.bench_function("make_multiplexer", |b| {
+		b.to_async(&runtime).iter(|| {
+			ready::<_, ()>(&mut make_multiplexer);
+			make_multiplexer.call(())
+		})
+	});
+	group.finish();
+}
+
+/// Body throughput of web responses, bare and through `EncapsulatedBody`
+fn body(c: &mut Criterion) {
+	let runtime = runtime();
+	let mut group = c.benchmark_group("body");
+
+	for (name, chunks, size) in [("unary", 1, 64), ("streaming", 256, CHUNK.len())] {
+		let chunked = move |_req: Request<Body>| async move {
+			let chunks =
+				(0..chunks).map(move |_| Ok::<_, Infallible>(Bytes::from_static(&CHUNK[..size])));
+			Ok::<_, Infallible>(Response::new(Body::wrap_stream(futures::stream::iter(
+				chunks,
+			))))
+		};
+		group.throughput(Throughput::Bytes((chunks * size) as u64));
+
+		let mut direct = service_fn(chunked);
+		group.bench_function(BenchmarkId::new("direct", name), |b| {
+			b.to_async(&runtime).iter(|| {
+				ready(&mut direct);
+				let response = direct.call(request(None));
+				async move { consume(response.await.unwrap().into_body()).await }
+			})
+		});
+
+		let mut multiplexer = Multiplexer::new(service_fn(empty), service_fn(chunked));
+		group.bench_function(BenchmarkId::new("multiplexer", name), |b| {
+			b.to_async(&runtime).iter(|| {
+				ready(&mut multiplexer);
+				let response = multiplexer.call(request(None));
+				async move { consume(response.await.unwrap().into_body()).await }
+			})
+		});
+	}
+	group.finish();
+}
+
+/// Whole tonic calls, bare and behind the multiplexer
+fn tonic(c: &mut Criterion) {
+	let runtime = runtime();
+	let mut group = c.benchmark_group("tonic");
+
+	let unary = || {
+		grpc_request(
+			"/helloworld.Greeter/SayHelloToAll",
+			HelloRequest { name: "Ana".into() },
+		)
+	};
+	let streaming = || {
+		grpc_request(
+			"/helloworld.Greeter/SayHelloStream",
+			HelloStreamRequest {
+				name: "Ana".into(),
+				count: 1000,
+			},
+		)
+	};
+	let calls: [(&str, &dyn Fn() -> Request<Body>); 2] =
+		[("unary", &unary), ("streaming", &streaming)];
+
+	for (name, request) in calls {
+		let mut direct = GreeterServer::new(MyGreeter::default());
+		group.bench_function(BenchmarkId::new("direct", name), |b| {
+			b.to_async(&runtime).iter(|| {
+				ready::<_, Request<Body>>(&mut direct);
+				let response = direct.call(request());
+				async move { consume(response.await.unwrap().into_body()).await }
+			})
+		});
+
+		let mut multiplexer =
+			Multiplexer::new(GreeterServer::new(MyGreeter::default()), service_fn(empty));
+		group.bench_function(BenchmarkId::new("multiplexer", name), |b| {
+			b.to_async(&runtime).iter(|| {
+				ready(&mut multiplexer);
+				let response = multiplexer.call(request());
+				async move { consume(response.await.unwrap().into_body()).await }
+			})
+		});
+	}
+	group.finish();
+}
+
+criterion_group!(benches, routing, make, body, tonic);
+criterion_main!(benches);
@@ -3,11 +3,10 @@ use std::convert::Infallible;
 use hello_world_tonic::hello_world::greeter_server::GreeterServer;
 use hello_world_tonic::server::MyGreeter;
 use hyper::server::conn::AddrStream;
-use hyper::service::make_service_fn;
-use hyper::{service::service_fn, Body, Response};
+use hyper::{service::service_fn, Body, Request, Response};
 use tower::make::Shared;
 
-use multiplex_tonic_hyper::MakeMultiplexer;
+use multiplex_tonic_hyper::{AccessLog, Multiplexer};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -15,25 +14,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 	let greeter = MyGreeter::default();
 
 	let greeter_service = Shared::new(GreeterServer::new(greeter));
-	let web_service = make_service_fn(move |conn: &AddrStream| {
-		let addr = conn.remote_addr();
-		let service = service_fn(move |req| {
-			let info = format!(
-				"Got web request from: {}, method: {}, path: {}, version: {:?}",
-				addr,
-				req.method(),
-				req.uri(),
-				req.version()
-			);
-			println!("{info}");
-			let response = format!("Hello World\n\n\n\n{req:#?}");
-			let res: Result<Response<Body>, Infallible> = Ok(Response::new(Body::from(response)));
-			async { res }
-		});
-		async move { Ok::<_, Infallible>(service) }
-	});
+	let web_service = Shared::new(service_fn(|req: Request<Body>| {
+		let response = format!("Hello World\n\n\n\n{req:#?}");
+		let res: Result<Response<Body>, Infallible> = Ok(Response::new(Body::from(response)));
+		async { res }
+	}));
 
-	let make_multiplexer = MakeMultiplexer::new(greeter_service, web_service);
+	//Log every request, from both services, in the Combined Log Format
+	let make_multiplexer = Multiplexer::builder()
+		.access_log(AccessLog::combined(std::io::stdout()))
+		.build_make(greeter_service, web_service)
+		.with_remote_addr(|conn: &&AddrStream| conn.remote_addr());
 
 	let server = hyper::Server::bind(&addr).serve(make_multiplexer);
 	println!(
@@ -0,0 +1,34 @@
+//! Arbitrary `content-type` values on a plain multiplexer must be routed as
+//! the reference model says.
+#![no_main]
+
+use arbitrary::Arbitrary;
+use hyper::{
+	header::{HeaderValue, CONTENT_TYPE},
+	Body, Request,
+};
+use libfuzzer_sys::fuzz_target;
+
+use multiplex_tonic_hyper::{Branch, Multiplexer};
+use multiplex_tonic_hyper_fuzz::{expected_branch, route, Inert};
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+	content_types: Vec<Vec<u8>>,
+	path: String,
+}
+
+fuzz_target!(|input: Input| {
+	let mut request = Request::new(Body::empty());
+	if let Ok(uri) = input.path.parse() {
+		*request.uri_mut() = uri;
+	}
+	for content_type in input.content_types {
+		if let Ok(value) = HeaderValue::from_bytes(&content_type) {
+			request.headers_mut().append(CONTENT_TYPE, value);
+		}
+	}
+	let expected = expected_branch(&request);
+	let mut multiplexer = Multiplexer::new(Inert(Branch::Grpc), Inert(Branch::Web));
+	assert_eq!(route(&mut multiplexer, request), Some(expected));
+});
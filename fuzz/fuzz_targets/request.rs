@@ -0,0 +1,30 @@
+//! Arbitrary requests on a multiplexer with every header parsing option
+//! enabled must never panic, and requests reaching an inner service must reach
+//! the one chosen by the reference model.
+#![no_main]
+
+use std::time::Duration;
+
+use libfuzzer_sys::fuzz_target;
+
+use multiplex_tonic_hyper::{BodyLimit, Branch, CorsPolicy, Multiplexer, Timeout};
+use multiplex_tonic_hyper_fuzz::{expected_branch, route, FuzzRequest, Inert};
+
+fuzz_target!(|input: FuzzRequest| {
+	let request = input.into_request();
+	let expected = expected_branch(&request);
+	let mut multiplexer = Multiplexer::builder()
+		.timeout(Branch::Grpc, Timeout::new(Duration::from_secs(60)))
+		.timeout(Branch::Web, Timeout::new(Duration::from_secs(60)))
+		.body_limit(Branch::Grpc, BodyLimit::new(64).path("/upload", 16))
+		.body_limit(Branch::Web, BodyLimit::new(64))
+		.cors(Branch::Grpc, CorsPolicy::grpc_web())
+		.cors(
+			Branch::Web,
+			CorsPolicy::new().allow_origin("https://example.com"),
+		)
+		.build(Inert(Branch::Grpc), Inert(Branch::Web));
+	if let Some(branch) = route(&mut multiplexer, request) {
+		assert_eq!(branch, expected);
+	}
+});
@@ -0,0 +1,143 @@
+//! Shared pieces of the fuzz targets: arbitrary requests, inert services and
+//! the reference model of the routing classifier.
+
+use std::{
+	convert::Infallible,
+	future::{ready, Ready},
+	task::{Context, Poll},
+};
+
+use arbitrary::Arbitrary;
+use hyper::{
+	header::{HeaderName, HeaderValue, CONTENT_TYPE},
+	Body, Method, Request, Response, Uri, Version,
+};
+use tokio::runtime::Runtime;
+use tower::Service;
+
+use multiplex_tonic_hyper::{Branch, Multiplexer};
+
+/// Header added by the inert services with the branch that answered
+const BRANCH_HEADER: &str = "x-fuzz-branch";
+
+/// Answers every request with an empty response naming its branch
+#[derive(Clone, Copy)]
+pub struct Inert(pub Branch);
+
+impl Service<Request<Body>> for Inert {
+	type Response = Response<Body>;
+	type Error = Infallible;
+	type Future = Ready<Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn call(&mut self, _req: Request<Body>) -> Self::Future {
+		let name = match self.0 {
+			Branch::Grpc => "grpc",
+			Branch::Web => "web",
+		};
+		let response = Response::builder()
+			.header(BRANCH_HEADER, name)
+			.body(Body::empty())
+			.unwrap();
+		ready(Ok(response))
+	}
+}
+
+/// HTTP version picked by the fuzzer
+#[derive(Arbitrary, Debug, Clone, Copy)]
+pub enum FuzzVersion {
+	Http09,
+	Http10,
+	Http11,
+	Http2,
+	Http3,
+}
+
+impl From<FuzzVersion> for Version {
+	fn from(version: FuzzVersion) -> Self {
+		match version {
+			FuzzVersion::Http09 => Version::HTTP_09,
+			FuzzVersion::Http10 => Version::HTTP_10,
+			FuzzVersion::Http11 => Version::HTTP_11,
+			FuzzVersion::Http2 => Version::HTTP_2,
+			FuzzVersion::Http3 => Version::HTTP_3,
+		}
+	}
+}
+
+/// Raw request parts, invalid methods, URIs or headers are replaced or skipped
+#[derive(Arbitrary, Debug)]
+pub struct FuzzRequest {
+	pub method: Vec<u8>,
+	pub uri: Vec<u8>,
+	pub version: FuzzVersion,
+	pub headers: Vec<(Vec<u8>, Vec<u8>)>,
+	pub body: Vec<u8>,
+}
+
+impl FuzzRequest {
+	/// Build the request, keeping every part hyper would accept
+	pub fn into_request(self) -> Request<Body> {
+		let mut request = Request::new(Body::from(self.body));
+		*request.method_mut() = Method::from_bytes(&self.method).unwrap_or(Method::GET);
+		*request.uri_mut() = Uri::try_from(self.uri).unwrap_or_else(|_| Uri::from_static("/"));
+		*request.version_mut() = self.version.into();
+		for (name, value) in self.headers {
+			if let (Ok(name), Ok(value)) = (
+				HeaderName::from_bytes(&name),
+				HeaderValue::from_bytes(&value),
+			) {
+				request.headers_mut().append(name, value);
+			}
+		}
+		request
+	}
+}
+
+/// Reference model of the classifier, written independently from the crate
+pub fn expected_branch(request: &Request<Body>) -> Branch {
+	let content_type = request
+		.headers()
+		.get_all(CONTENT_TYPE)
+		.iter()
+		.next()
+		.map(HeaderValue::as_bytes)
+		.unwrap_or_default();
+	let prefix = b"application/grpc";
+	if content_type.len() >= prefix.len() && &content_type[..prefix.len()] == prefix {
+		Branch::Grpc
+	} else {
+		Branch::Web
+	}
+}
+
+thread_local! {
+	static RUNTIME: Runtime = tokio::runtime::Builder::new_current_thread()
+		.enable_time()
+		.build()
+		.unwrap();
+}
+
+/// Call the multiplexer, returning the branch of the inner service that
+/// answered, or `None` when the multiplexer answered by itself
+pub fn route(
+	multiplexer: &mut Multiplexer<Inert, Inert>,
+	request: Request<Body>,
+) -> Option<Branch> {
+	RUNTIME.with(|runtime| {
+		runtime.block_on(async {
+			std::future::poll_fn(|cx| multiplexer.poll_ready(cx))
+				.await
+				.unwrap();
+			let response = multiplexer.call(request).await.ok()?;
+			match response.headers().get(BRANCH_HEADER)?.as_bytes() {
+				b"grpc" => Some(Branch::Grpc),
+				b"web" => Some(Branch::Web),
+				other => panic!("unknown branch {other:?}"),
+			}
+		})
+	})
+}
@@ -1,4 +1,9 @@
+use std::{env, path::PathBuf};
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-	tonic_build::compile_protos("proto/helloworld.proto")?;
+	let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+	tonic_build::configure()
+		.file_descriptor_set_path(out_dir.join("helloworld_descriptor.bin"))
+		.compile(&["proto/helloworld.proto"], &["proto"])?;
 	Ok(())
 }
@@ -1,15 +1,70 @@
 pub mod hello_world {
 	tonic::include_proto!("helloworld");
+
+	/// Encoded descriptors of helloworld.proto and its imports
+	pub const FILE_DESCRIPTOR_SET: &[u8] =
+		tonic::include_file_descriptor_set!("helloworld_descriptor");
 }
 
 pub mod server {
+	use std::pin::Pin;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::sync::Arc;
+
 	use super::hello_world;
+	use futures::{Stream, StreamExt, TryStreamExt};
 	use hello_world::greeter_server::Greeter;
-	use hello_world::{HelloReply, HelloRequest};
-	use tonic::{Request, Response, Status};
+	use hello_world::{HelloReply, HelloRequest, HelloStreamRequest};
+	use tonic::{Request, Response, Status, Streaming};
+
+	type ReplyStream = Pin<Box<dyn Stream<Item = Result<HelloReply, Status>> + Send>>;
 
 	#[derive(Debug, Default)]
-	pub struct MyGreeter {}
+	pub struct MyGreeter {
+		stats: StreamStats,
+	}
+
+	impl MyGreeter {
+		/// Counters of the streams of SayHelloStream, shared with this greeter
+		pub fn stats(&self) -> StreamStats {
+			self.stats.clone()
+		}
+	}
+
+	/// Counters of the replies produced by SayHelloStream
+	#[derive(Clone, Debug, Default)]
+	pub struct StreamStats {
+		sent: Arc<AtomicUsize>,
+		open: Arc<AtomicUsize>,
+	}
+
+	impl StreamStats {
+		/// Replies taken from the streams so far
+		pub fn sent(&self) -> usize {
+			self.sent.load(Ordering::SeqCst)
+		}
+
+		/// Streams that were not dropped yet
+		pub fn open(&self) -> usize {
+			self.open.load(Ordering::SeqCst)
+		}
+	}
+
+	/// Counts a stream as open until dropped
+	struct OpenStream(Arc<AtomicUsize>);
+
+	impl OpenStream {
+		fn new(open: Arc<AtomicUsize>) -> Self {
+			open.fetch_add(1, Ordering::SeqCst);
+			OpenStream(open)
+		}
+	}
+
+	impl Drop for OpenStream {
+		fn drop(&mut self) {
+			self.0.fetch_sub(1, Ordering::SeqCst);
+		}
+	}
 
 	#[tonic::async_trait]
 	impl Greeter for MyGreeter {
@@ -29,5 +84,53 @@ pub mod server {
 
 			Ok(Response::new(reply))
 		}
+
+		type ChatStream = ReplyStream;
+
+		async fn chat(
+			&self,
+			request: Request<Streaming<HelloRequest>>,
+		) -> Result<Response<Self::ChatStream>, Status> {
+			let replies = request.into_inner().map(|request| {
+				request.map(|request| HelloReply {
+					message: format!("Hello {}!", request.name),
+				})
+			});
+			Ok(Response::new(Box::pin(replies)))
+		}
+
+		type SayHelloStreamStream = ReplyStream;
+
+		async fn say_hello_stream(
+			&self,
+			request: Request<HelloStreamRequest>,
+		) -> Result<Response<Self::SayHelloStreamStream>, Status> {
+			let HelloStreamRequest { name, count } = request.into_inner();
+			let sent = self.stats.sent.clone();
+			let open = OpenStream::new(self.stats.open.clone());
+			let replies = futures::stream::iter(1..=count).map(move |number| {
+				let _open = &open;
+				sent.fetch_add(1, Ordering::SeqCst);
+				Ok(HelloReply {
+					message: format!("Hello {name} #{number}!"),
+				})
+			});
+			Ok(Response::new(Box::pin(replies)))
+		}
+
+		async fn say_hello_to_all(
+			&self,
+			request: Request<Streaming<HelloRequest>>,
+		) -> Result<Response<HelloReply>, Status> {
+			let names: Vec<String> = request
+				.into_inner()
+				.map_ok(|request| request.name)
+				.try_collect()
+				.await?;
+			let reply = HelloReply {
+				message: format!("Hello {}!", names.join(", ")),
+			};
+			Ok(Response::new(reply))
+		}
 	}
 }
@@ -0,0 +1,520 @@
+use std::{
+	fmt::Write as _,
+	io::Write,
+	net::SocketAddr,
+	sync::{Arc, Mutex},
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use hyper::{header, Body, Method, Request, StatusCode, Version};
+
+use crate::{grpc, Branch};
+
+/// Information about one finished request, passed to an [AccessLogFormat]
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct AccessRecord {
+	///When the multiplexer received the request
+	pub time: SystemTime,
+	///Address of the client, when known. See [Multiplexer::with_remote_addr][crate::Multiplexer::with_remote_addr]
+	pub remote_addr: Option<SocketAddr>,
+	///Branch that handled the request
+	pub branch: Branch,
+	///HTTP method of the request
+	pub method: Method,
+	///Path and query of the request
+	pub path: String,
+	///HTTP version of the request
+	pub version: Version,
+	///gRPC method, as `package.Service/Method`, for requests sent to the gRPC branch
+	pub grpc_method: Option<String>,
+	///Status of the response, if the inner service returned one
+	pub status: Option<StatusCode>,
+	///gRPC status from the response headers or trailers
+	pub grpc_status: Option<i32>,
+	///Response body bytes sent to the client
+	pub bytes_sent: u64,
+	///Time from the call until the end of the response body
+	pub duration: Duration,
+	///Whether the response body was sent until the end
+	pub completed: bool,
+	///Value of the `Referer` request header
+	pub referer: Option<String>,
+	///Value of the `User-Agent` request header
+	pub user_agent: Option<String>,
+}
+
+/// Formats an [AccessRecord] as a single line
+///
+/// Implemented by [CombinedLogFormat], [JsonFormat], and by closures.
+pub trait AccessLogFormat: Send + Sync + 'static {
+	/// Format the record, without a trailing newline
+	fn format(&self, record: &AccessRecord) -> String;
+}
+
+impl<F> AccessLogFormat for F
+where
+	F: Fn(&AccessRecord) -> String + Send + Sync + 'static,
+{
+	fn format(&self, record: &AccessRecord) -> String {
+		self(record)
+	}
+}
+
+/// Destination of the formatted access log lines
+///
+/// Implemented for [Stdout][std::io::Stdout], [Stderr][std::io::Stderr], any
+/// writer inside a [Mutex], and closures.
+pub trait AccessLogWriter: Send + Sync + 'static {
+	/// Write one line. Errors are ignored, the log must not break requests
+	fn write_line(&self, line: &str);
+}
+
+impl<F> AccessLogWriter for F
+where
+	F: Fn(&str) + Send + Sync + 'static,
+{
+	fn write_line(&self, line: &str) {
+		self(line)
+	}
+}
+
+impl<W: Write + Send + 'static> AccessLogWriter for Mutex<W> {
+	fn write_line(&self, line: &str) {
+		if let Ok(mut writer) = self.lock() {
+			let _ = writeln!(writer, "{line}");
+		}
+	}
+}
+
+impl AccessLogWriter for std::io::Stdout {
+	fn write_line(&self, line: &str) {
+		let _ = writeln!(self.lock(), "{line}");
+	}
+}
+
+impl AccessLogWriter for std::io::Stderr {
+	fn write_line(&self, line: &str) {
+		let _ = writeln!(self.lock(), "{line}");
+	}
+}
+
+/// Access log configuration for the [Builder][crate::Builder]
+///
+/// Emits one record for each request, when its response body ends or is dropped.
+///
+/// # Examples:
+/// ```
+/// use multiplex_tonic_hyper::{AccessLog, Multiplexer};
+/// # use hyper::{service::service_fn, Body, Request, Response};
+/// # async fn handle(_: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+/// # 	Ok(Response::new(Body::empty()))
+/// # }
+///
+/// let multiplexer = Multiplexer::builder()
+/// 	.access_log(AccessLog::combined(std::io::stdout()))
+/// 	.build(service_fn(handle), service_fn(handle));
+/// ```
+#[derive(Clone)]
+pub struct AccessLog {
+	format: Arc<dyn AccessLogFormat>,
+	writer: Arc<dyn AccessLogWriter>,
+}
+
+impl AccessLog {
+	/// Access log with a custom format and writer
+	pub fn new(format: impl AccessLogFormat, writer: impl AccessLogWriter) -> Self {
+		AccessLog {
+			format: Arc::new(format),
+			writer: Arc::new(writer),
+		}
+	}
+
+	/// Access log in the Combined Log Format used by Apache and nginx
+	pub fn combined(writer: impl AccessLogWriter) -> Self {
+		Self::new(CombinedLogFormat, writer)
+	}
+
+	/// Access log with one JSON object per line
+	pub fn json(writer: impl AccessLogWriter) -> Self {
+		Self::new(JsonFormat, writer)
+	}
+
+	fn log(&self, record: &AccessRecord) {
+		self.writer.write_line(&self.format.format(record));
+	}
+}
+
+/// The Combined Log Format, used by Apache and nginx
+///
+/// `127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET /index.html HTTP/1.1" 200 2326 "-" "curl/7.85.0"`
+///
+/// Requests sent to the gRPC branch have their gRPC status appended, or `-`
+/// when the response had none. The path, referer and user agent are escaped
+/// like Apache does, `"` and `\` with a backslash and control characters as `\xhh`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CombinedLogFormat;
+
+impl AccessLogFormat for CombinedLogFormat {
+	fn format(&self, record: &AccessRecord) -> String {
+		const MONTHS: [&str; 12] = [
+			"Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+		];
+		let time = DateTime::from(record.time);
+		let mut line = String::new();
+		match record.remote_addr {
+			Some(addr) => write!(line, "{}", addr.ip()),
+			None => write!(line, "-"),
+		}
+		.unwrap();
+		write!(
+			line,
+			" - - [{:02}/{}/{}:{:02}:{:02}:{:02} +0000] \"{} ",
+			time.day,
+			MONTHS[time.month as usize - 1],
+			time.year,
+			time.hour,
+			time.minute,
+			time.second,
+			record.method,
+		)
+		.unwrap();
+		escaped(&mut line, &record.path);
+		write!(line, " {:?}\" ", record.version).unwrap();
+		match record.status {
+			Some(status) => write!(line, "{}", status.as_u16()),
+			None => write!(line, "-"),
+		}
+		.unwrap();
+		write!(line, " {} \"", record.bytes_sent).unwrap();
+		escaped(&mut line, record.referer.as_deref().unwrap_or("-"));
+		line.push_str("\" \"");
+		escaped(&mut line, record.user_agent.as_deref().unwrap_or("-"));
+		line.push('"');
+		if record.branch == Branch::Grpc {
+			match record.grpc_status {
+				Some(status) => write!(line, " {status}"),
+				None => write!(line, " -"),
+			}
+			.unwrap();
+		}
+		line
+	}
+}
+
+/// Write `value` escaped as Apache does, so it can not end the quoted field or the line
+fn escaped(line: &mut String, value: &str) {
+	for c in value.chars() {
+		match c {
+			'"' => line.push_str("\\\""),
+			'\\' => line.push_str("\\\\"),
+			c if c.is_ascii_control() => write!(line, "\\x{:02x}", c as u32).unwrap(),
+			c => line.push(c),
+		}
+	}
+}
+
+/// One JSON object per record
+///
+/// Missing values are written as `null`, and the duration is in milliseconds.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonFormat;
+
+impl AccessLogFormat for JsonFormat {
+	fn format(&self, record: &AccessRecord) -> String {
+		let time = DateTime::from(record.time);
+		let mut line = String::from("{");
+		write!(
+			line,
+			"\"time\":\"{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z\"",
+			time.year, time.month, time.day, time.hour, time.minute, time.second
+		)
+		.unwrap();
+		line.push_str(",\"remote_addr\":");
+		json_option(&mut line, record.remote_addr.map(|addr| addr.to_string()));
+		line.push_str(",\"branch\":");
+		json_string(&mut line, record.branch.as_str());
+		line.push_str(",\"method\":");
+		json_string(&mut line, record.method.as_str());
+		line.push_str(",\"path\":");
+		json_string(&mut line, &record.path);
+		line.push_str(",\"version\":");
+		json_string(&mut line, &format!("{:?}", record.version));
+		line.push_str(",\"grpc_method\":");
+		json_option(&mut line, record.grpc_method.as_deref());
+		line.push_str(",\"status\":");
+		json_number(&mut line, record.status.map(|status| status.as_u16()));
+		line.push_str(",\"grpc_status\":");
+		json_number(&mut line, record.grpc_status);
+		write!(
+			line,
+			",\"bytes_sent\":{},\"duration_ms\":{:.3},\"completed\":{}",
+			record.bytes_sent,
+			record.duration.as_secs_f64() * 1000.0,
+			record.completed
+		)
+		.unwrap();
+		line.push_str(",\"referer\":");
+		json_option(&mut line, record.referer.as_deref());
+		line.push_str(",\"user_agent\":");
+		json_option(&mut line, record.user_agent.as_deref());
+		line.push('}');
+		line
+	}
+}
+
+fn json_string(line: &mut String, value: &str) {
+	line.push('"');
+	for c in value.chars() {
+		match c {
+			'"' => line.push_str("\\\""),
+			'\\' => line.push_str("\\\\"),
+			'\n' => line.push_str("\\n"),
+			'\r' => line.push_str("\\r"),
+			'\t' => line.push_str("\\t"),
+			c if c.is_control() => write!(line, "\\u{:04x}", c as u32).unwrap(),
+			c => line.push(c),
+		}
+	}
+	line.push('"');
+}
+
+fn json_option<T: AsRef<str>>(line: &mut String, value: Option<T>) {
+	match value {
+		Some(value) => json_string(line, value.as_ref()),
+		None => line.push_str("null"),
+	}
+}
+
+fn json_number<T: std::fmt::Display>(line: &mut String, value: Option<T>) {
+	match value {
+		Some(value) => write!(line, "{value}").unwrap(),
+		None => line.push_str("null"),
+	}
+}
+
+/// UTC date and time, without depending on a date crate
+struct DateTime {
+	year: i64,
+	month: u32,
+	day: u32,
+	hour: u64,
+	minute: u64,
+	second: u64,
+}
+
+impl From<SystemTime> for DateTime {
+	fn from(time: SystemTime) -> Self {
+		let seconds = time
+			.duration_since(UNIX_EPOCH)
+			.map(|duration| duration.as_secs())
+			.unwrap_or_default();
+		// Civil date from days since epoch, by Howard Hinnant
+		let days = (seconds / 86400) as i64 + 719468;
+		let era = days.div_euclid(146097);
+		let day_of_era = days.rem_euclid(146097);
+		let year_of_era =
+			(day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+		let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+		let shifted_month = (5 * day_of_year + 2) / 153;
+		let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+		let month = if shifted_month < 10 {
+			shifted_month + 3
+		} else {
+			shifted_month - 9
+		} as u32;
+		let year = year_of_era + era * 400 + i64::from(month <= 2);
+		DateTime {
+			year,
+			month,
+			day,
+			hour: seconds % 86400 / 3600,
+			minute: seconds % 3600 / 60,
+			second: seconds % 60,
+		}
+	}
+}
+
+/// Record being filled while the request is in flight
+pub(crate) struct AccessEntry {
+	log: AccessLog,
+	start: Instant,
+	record: AccessRecord,
+}
+
+impl AccessEntry {
+	pub(crate) fn new(
+		log: &AccessLog,
+		branch: Branch,
+		request: &Request<Body>,
+		remote_addr: Option<SocketAddr>,
+	) -> Self {
+		let header = |name| {
+			request
+				.headers()
+				.get(name)
+				.and_then(|value: &header::HeaderValue| value.to_str().ok())
+				.map(str::to_owned)
+		};
+		let path = request
+			.uri()
+			.path_and_query()
+			.map(|path| path.as_str())
+			.unwrap_or("/");
+		let grpc_method = match branch {
+			Branch::Grpc => grpc::split_path(request.uri().path())
+				.map(|(service, method)| format!("{service}/{method}")),
+			Branch::Web => None,
+		};
+		AccessEntry {
+			log: log.clone(),
+			start: Instant::now(),
+			record: AccessRecord {
+				time: SystemTime::now(),
+				remote_addr,
+				branch,
+				method: request.method().clone(),
+				path: path.to_owned(),
+				version: request.version(),
+				grpc_method,
+				status: None,
+				grpc_status: None,
+				bytes_sent: 0,
+				duration: Duration::ZERO,
+				completed: false,
+				referer: header(header::REFERER),
+				user_agent: header(header::USER_AGENT),
+			},
+		}
+	}
+
+	pub(crate) fn on_response(&mut self, status: StatusCode) {
+		self.record.status = Some(status);
+	}
+
+	pub(crate) fn on_data(&mut self, len: usize) {
+		self.record.bytes_sent += len as u64;
+	}
+
+	pub(crate) fn on_end(&mut self, completed: bool, grpc_status: Option<i32>) {
+		self.record.duration = self.start.elapsed();
+		self.record.completed = completed;
+		self.record.grpc_status = grpc_status;
+		self.log.log(&self.record);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::{Duration, UNIX_EPOCH};
+
+	use hyper::{Method, StatusCode, Version};
+
+	use super::{AccessLogFormat, AccessRecord, CombinedLogFormat, JsonFormat};
+	use crate::Branch;
+
+	fn record() -> AccessRecord {
+		AccessRecord {
+			//2000-10-10T13:55:36Z
+			time: UNIX_EPOCH + Duration::from_secs(971186136),
+			remote_addr: Some("127.0.0.1:5000".parse().unwrap()),
+			branch: Branch::Web,
+			method: Method::GET,
+			path: "/index.html?q=1".into(),
+			version: Version::HTTP_11,
+			grpc_method: None,
+			status: Some(StatusCode::OK),
+			grpc_status: None,
+			bytes_sent: 2326,
+			duration: Duration::from_millis(12),
+			completed: true,
+			referer: None,
+			user_agent: Some("curl/7.85.0".into()),
+		}
+	}
+
+	#[test]
+	fn combined_log_format() {
+		assert_eq!(
+			CombinedLogFormat.format(&record()),
+			"127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /index.html?q=1 HTTP/1.1\" 200 2326 \"-\" \"curl/7.85.0\""
+		);
+	}
+
+	#[test]
+	fn combined_log_format_without_response() {
+		let record = AccessRecord {
+			remote_addr: None,
+			status: None,
+			bytes_sent: 0,
+			..record()
+		};
+		let line = CombinedLogFormat.format(&record);
+		assert!(line.starts_with("- - - ["), "{line}");
+		assert!(line.contains("\" - 0 \""), "{line}");
+	}
+
+	#[test]
+	fn combined_log_format_for_grpc() {
+		let record = AccessRecord {
+			branch: Branch::Grpc,
+			grpc_status: Some(3),
+			..record()
+		};
+		let line = CombinedLogFormat.format(&record);
+		assert!(line.ends_with("\"curl/7.85.0\" 3"), "{line}");
+
+		let record = AccessRecord {
+			grpc_status: None,
+			..record
+		};
+		let line = CombinedLogFormat.format(&record);
+		assert!(line.ends_with("\"curl/7.85.0\" -"), "{line}");
+	}
+
+	#[test]
+	fn combined_log_format_escapes_headers() {
+		let record = AccessRecord {
+			referer: Some("a\"b\\c".into()),
+			user_agent: Some("x\" 200 0\n127.0.0.1\u{7f}".into()),
+			..record()
+		};
+		let line = CombinedLogFormat.format(&record);
+		assert!(
+			line.ends_with("\"a\\\"b\\\\c\" \"x\\\" 200 0\\x0a127.0.0.1\\x7f\""),
+			"{line}"
+		);
+	}
+
+	#[test]
+	fn json_format() {
+		let record = AccessRecord {
+			branch: Branch::Grpc,
+			method: Method::POST,
+			path: "/helloworld.Greeter/SayHello".into(),
+			version: Version::HTTP_2,
+			grpc_method: Some("helloworld.Greeter/SayHello".into()),
+			grpc_status: Some(0),
+			user_agent: Some("grpc \"quoted\"".into()),
+			..record()
+		};
+		assert_eq!(
+			JsonFormat.format(&record),
+			concat!(
+				"{\"time\":\"2000-10-10T13:55:36Z\",\"remote_addr\":\"127.0.0.1:5000\",",
+				"\"branch\":\"grpc\",\"method\":\"POST\",\"path\":\"/helloworld.Greeter/SayHello\",",
+				"\"version\":\"HTTP/2.0\",\"grpc_method\":\"helloworld.Greeter/SayHello\",",
+				"\"status\":200,\"grpc_status\":0,\"bytes_sent\":2326,\"duration_ms\":12.000,",
+				"\"completed\":true,\"referer\":null,\"user_agent\":\"grpc \\\"quoted\\\"\"}"
+			)
+		);
+	}
+
+	#[test]
+	fn date_before_march() {
+		//2024-02-29T23:59:59Z
+		let time = super::DateTime::from(UNIX_EPOCH + Duration::from_secs(1709251199));
+		assert_eq!((time.year, time.month, time.day), (2024, 2, 29));
+		assert_eq!((time.hour, time.minute, time.second), (23, 59, 59));
+	}
+}
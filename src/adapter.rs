@@ -0,0 +1,181 @@
+//! Requests of other protocols, converted to calls of the gRPC service
+
+use std::{
+	pin::Pin,
+	task::{Context, Poll},
+};
+
+use hyper::{
+	body::{Bytes, HttpBody},
+	http::response::Parts,
+	Body, HeaderMap, Request, Response,
+};
+
+use crate::{
+	builder::Config,
+	grpc::{self, code},
+	into_data,
+	local::{LocalBody, Once},
+	BodyLimit, BoxedError, Branch,
+};
+
+/// Converted request, and the state to convert its response
+pub(crate) type Converted = (Request<Body>, Box<dyn Adapted>);
+
+/// Converts the requests of another protocol to calls of the gRPC service
+pub(crate) trait Adapter: Send + Sync {
+	/// Convert `request` if it uses this protocol, reading at most `limit` of
+	/// its body, otherwise give it back
+	#[allow(clippy::result_large_err)]
+	fn adapt(
+		&self,
+		request: Request<Body>,
+		limit: Option<&BodyLimit>,
+	) -> Result<Converted, Request<Body>>;
+}
+
+/// Converts the gRPC response of one converted request
+pub(crate) trait Adapted: Send {
+	/// Convert the headers of the response, or replace a response without messages
+	fn head(&mut self, parts: &mut Parts) -> Option<Response<LocalBody>>;
+
+	/// Convert a part of the response body
+	fn data(&mut self, data: Bytes) -> Result<Option<Bytes>, BoxedError>;
+
+	/// Last part of the converted body, from the trailers of the response
+	fn end(&mut self, trailers: Option<&HeaderMap>) -> Result<Option<Bytes>, BoxedError>;
+
+	/// Whether the whole response is read before it is converted, so that an
+	/// error in its trailers still gets its own response
+	///
+	/// The whole converted body of these responses is returned by [end][Adapted::end].
+	fn buffered(&self) -> bool;
+}
+
+/// Convert a web request with the first adapter of `config` that accepts it
+#[allow(clippy::result_large_err)]
+pub(crate) fn adapt(config: &Config, request: Request<Body>) -> Result<Converted, Request<Body>> {
+	let limit = config.body_limit(Branch::Grpc);
+	let adapters = std::iter::empty::<&dyn Adapter>();
+	#[cfg(feature = "connect")]
+	let adapters = adapters.chain(config.connect.as_ref().map(|c| c as &dyn Adapter));
+	#[cfg(feature = "transcoding")]
+	let adapters = adapters.chain(config.transcoder.as_ref().map(|t| t as &dyn Adapter));
+	let mut request = request;
+	for adapter in adapters {
+		match adapter.adapt(request, limit) {
+			Ok(adapted) => return Ok(adapted),
+			Err(given_back) => request = given_back,
+		}
+	}
+	Err(request)
+}
+
+/// Body of a converted response
+pub(crate) struct AdaptedBody {
+	adapted: Box<dyn Adapted>,
+	done: bool,
+}
+
+impl AdaptedBody {
+	pub(crate) fn new(adapted: Box<dyn Adapted>) -> Self {
+		AdaptedBody {
+			adapted,
+			done: false,
+		}
+	}
+
+	pub(crate) fn poll_data<B>(
+		&mut self,
+		mut body: Pin<&mut B>,
+		cx: &mut Context<'_>,
+	) -> Poll<Option<Result<Bytes, BoxedError>>>
+	where
+		B: HttpBody,
+		B::Data: Into<Bytes>,
+		B::Error: Into<BoxedError>,
+	{
+		while !self.done {
+			let converted = match futures::ready!(body.as_mut().poll_data(cx)) {
+				Some(data) => data
+					.map_err(Into::into)
+					.and_then(|data| self.adapted.data(into_data(data))),
+				None => {
+					let trailers = futures::ready!(body.as_mut().poll_trailers(cx));
+					self.done = true;
+					trailers
+						.map_err(Into::into)
+						.and_then(|trailers| self.adapted.end(trailers.as_ref()))
+				}
+			};
+			match converted {
+				Ok(Some(data)) => return Poll::Ready(Some(Ok(data))),
+				Ok(None) => {}
+				Err(error) => {
+					self.done = true;
+					return Poll::Ready(Some(Err(error)));
+				}
+			}
+		}
+		Poll::Ready(None)
+	}
+
+	pub(crate) fn is_end(&self) -> bool {
+		self.done
+	}
+}
+
+/// Read the whole gRPC response `body` with `adapted`, then convert the response
+pub(crate) fn poll_buffered<B>(
+	adapted: &mut dyn Adapted,
+	parts: &mut Option<Parts>,
+	mut body: Pin<&mut B>,
+	cx: &mut Context<'_>,
+) -> Poll<Result<Response<LocalBody>, BoxedError>>
+where
+	B: HttpBody,
+	B::Data: Into<Bytes>,
+	B::Error: Into<BoxedError>,
+{
+	let converted = loop {
+		match futures::ready!(body.as_mut().poll_data(cx)) {
+			Some(data) => {
+				let data = into_data(data.map_err(Into::into)?);
+				if let Err(error) = adapted.data(data) {
+					break Err((code::RESOURCE_EXHAUSTED, error.to_string()));
+				}
+			}
+			None => {
+				let trailers =
+					futures::ready!(body.as_mut().poll_trailers(cx)).map_err(Into::into)?;
+				let headers = &parts.as_ref().expect(BUFFERED).headers;
+				let status = trailers
+					.as_ref()
+					.and_then(grpc::parse_status)
+					.or_else(|| grpc::parse_status(headers));
+				break match status {
+					Some((code::OK, _)) => adapted
+						.end(trailers.as_ref())
+						.map_err(|error| (code::INTERNAL, error.to_string())),
+					Some(status) => Err(status),
+					None => Err((code::INTERNAL, "call ended without a gRPC status".into())),
+				};
+			}
+		}
+	};
+	let mut parts = parts.take().expect(BUFFERED);
+	//Failed calls are converted from the status in the headers
+	if let Err((code, message)) = &converted {
+		parts.headers.extend(grpc::status_trailers(*code, message));
+	}
+	let response = match (adapted.head(&mut parts), converted) {
+		(Some(replaced), _) => replaced,
+		(None, Ok(body)) => {
+			Response::from_parts(parts, Once::new(body.unwrap_or_default(), None).boxed())
+		}
+		(None, Err((code, message))) => grpc::status_response(code, &message),
+	};
+	Poll::Ready(Ok(response))
+}
+
+const BUFFERED: &str = "buffered response polled after completion";
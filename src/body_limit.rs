@@ -0,0 +1,240 @@
+use std::{
+	pin::Pin,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+	task::{Context, Poll},
+};
+
+use futures::Stream;
+use hyper::{
+	body::{Bytes, HttpBody},
+	header::CONTENT_LENGTH,
+	Body, HeaderMap, Request, Response, StatusCode,
+};
+
+use crate::{
+	grpc::{self, code},
+	local::{self, LocalBody},
+	BoxedError, Branch,
+};
+
+/// Maximum size of the request bodies on one branch of a [Multiplexer][crate::Multiplexer]
+///
+/// Requests with a larger `content-length` are rejected without calling the
+/// inner service. Other request bodies are wrapped, and return an error to the
+/// inner service when they exceed the limit. In both cases, the response is
+/// generated by the Multiplexer: gRPC calls get the `RESOURCE_EXHAUSTED` status,
+/// and web requests get `413 Payload Too Large`.
+///
+/// Wrapped bodies do not forward the request trailers.
+///
+/// # Examples:
+/// ```
+/// use multiplex_tonic_hyper::{BodyLimit, Branch, Multiplexer};
+/// # use hyper::{service::service_fn, Body, Request, Response};
+/// # async fn handle(_: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+/// # 	Ok(Response::new(Body::empty()))
+/// # }
+/// # let grpc = service_fn(handle);
+/// # let web = service_fn(handle);
+///
+/// const MIB: u64 = 1024 * 1024;
+/// let multiplexer = Multiplexer::builder()
+/// 	.body_limit(Branch::Grpc, BodyLimit::new(4 * MIB))
+/// 	.body_limit(Branch::Web, BodyLimit::new(MIB).path("/upload/", 100 * MIB))
+/// 	.build(grpc, web);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BodyLimit {
+	max_bytes: u64,
+	//Sorted from the longest prefix
+	paths: Vec<(String, u64)>,
+}
+
+impl BodyLimit {
+	/// Allow request bodies up to `max_bytes`
+	pub fn new(max_bytes: u64) -> Self {
+		BodyLimit {
+			max_bytes,
+			paths: Vec::new(),
+		}
+	}
+
+	/// Allow request bodies up to `max_bytes` on paths starting with `prefix`
+	///
+	/// When more than one prefix matches, the longest is used.
+	pub fn path(mut self, prefix: impl Into<String>, max_bytes: u64) -> Self {
+		let prefix = prefix.into();
+		self.paths.retain(|(path, _)| *path != prefix);
+		self.paths.push((prefix, max_bytes));
+		self.paths
+			.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+		self
+	}
+
+	pub(crate) fn max_bytes(&self, path: &str) -> u64 {
+		self.paths
+			.iter()
+			.find(|(prefix, _)| path.starts_with(prefix.as_str()))
+			.map_or(self.max_bytes, |(_, max_bytes)| *max_bytes)
+	}
+
+	/// Reject the request if its `content_length` is too large, otherwise limit its body
+	///
+	/// The length is read before Connect and transcoded requests are converted,
+	/// as the converted requests have none.
+	#[allow(clippy::result_large_err)]
+	pub(crate) fn apply(
+		&self,
+		branch: Branch,
+		request: Request<Body>,
+		content_length: Option<u64>,
+	) -> Result<(Request<Body>, Exceeded), Response<LocalBody>> {
+		let max_bytes = self.max_bytes(request.uri().path());
+		let exceeded = Exceeded {
+			flag: Default::default(),
+			branch,
+			max_bytes,
+		};
+		if content_length.is_some_and(|length| length > max_bytes) {
+			return Err(exceeded.response());
+		}
+		let request = request.map(|body| {
+			Body::wrap_stream(Limited {
+				inner: body,
+				remaining: max_bytes,
+				exceeded: exceeded.flag.clone(),
+			})
+		});
+		Ok((request, exceeded))
+	}
+}
+
+/// Length of a request body, from its `content-length` header
+pub(crate) fn content_length(headers: &HeaderMap) -> Option<u64> {
+	headers
+		.get(CONTENT_LENGTH)
+		.and_then(|value| value.to_str().ok())
+		.and_then(|value| value.parse::<u64>().ok())
+}
+
+/// Tracks if the request body of a request exceeded its limit
+pub(crate) struct Exceeded {
+	flag: Arc<AtomicBool>,
+	branch: Branch,
+	max_bytes: u64,
+}
+
+impl Exceeded {
+	pub(crate) fn is_exceeded(&self) -> bool {
+		self.flag.load(Ordering::Acquire)
+	}
+
+	fn message(&self) -> String {
+		format!("request body is larger than {} bytes", self.max_bytes)
+	}
+
+	/// Response that replaces the response of the inner service
+	pub(crate) fn response(&self) -> Response<LocalBody> {
+		match self.branch {
+			Branch::Grpc => grpc::status_response(code::RESOURCE_EXHAUSTED, &self.message()),
+			Branch::Web => {
+				local::text(StatusCode::PAYLOAD_TOO_LARGE, "request body is too large\n")
+			}
+		}
+	}
+
+	/// Trailers that replace the trailers of a gRPC stream
+	pub(crate) fn trailers(&self) -> Option<HeaderMap> {
+		(self.branch == Branch::Grpc)
+			.then(|| grpc::status_trailers(code::RESOURCE_EXHAUSTED, &self.message()))
+	}
+}
+
+/// Request body that fails after `remaining` bytes
+struct Limited {
+	inner: Body,
+	remaining: u64,
+	exceeded: Arc<AtomicBool>,
+}
+
+impl Stream for Limited {
+	type Item = Result<Bytes, BoxedError>;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		let data = match futures::ready!(Pin::new(&mut self.inner).poll_data(cx)) {
+			Some(Ok(data)) => data,
+			Some(Err(error)) => return Poll::Ready(Some(Err(error.into()))),
+			None => return Poll::Ready(None),
+		};
+		match self.remaining.checked_sub(data.len() as u64) {
+			Some(remaining) => {
+				self.remaining = remaining;
+				Poll::Ready(Some(Ok(data)))
+			}
+			None => {
+				self.exceeded.store(true, Ordering::Release);
+				Poll::Ready(Some(Err("request body is too large".into())))
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use hyper::{Body, Request};
+
+	use super::{content_length, BodyLimit};
+	use crate::Branch;
+
+	#[test]
+	fn longest_matching_prefix_wins() {
+		let limit = BodyLimit::new(1)
+			.path("/upload/", 100)
+			.path("/upload/small/", 10)
+			.path("/up", 50);
+		assert_eq!(limit.max_bytes("/index.html"), 1);
+		assert_eq!(limit.max_bytes("/upload/file"), 100);
+		assert_eq!(limit.max_bytes("/upload/small/file"), 10);
+		assert_eq!(limit.max_bytes("/update"), 50);
+	}
+
+	#[test]
+	fn rejects_large_content_length_early() {
+		let limit = BodyLimit::new(4);
+		let request = Request::post("/")
+			.header("content-length", "5")
+			.body(Body::from("12345"))
+			.unwrap();
+		let length = content_length(request.headers());
+		let response = limit.apply(Branch::Web, request, length).err().unwrap();
+		assert_eq!(response.status(), 413);
+
+		let request = Request::post("/")
+			.header("content-length", "5")
+			.body(Body::from("12345"))
+			.unwrap();
+		let length = content_length(request.headers());
+		let response = limit.apply(Branch::Grpc, request, length).err().unwrap();
+		assert_eq!(response.headers()["grpc-status"], "8");
+	}
+
+	#[tokio::test]
+	async fn wrapped_body_fails_after_the_limit() {
+		let limit = BodyLimit::new(4);
+		let request = Request::post("/").body(Body::from("1234")).unwrap();
+		let (request, exceeded) = limit.apply(Branch::Web, request, None).ok().unwrap();
+		assert_eq!(
+			hyper::body::to_bytes(request.into_body()).await.unwrap(),
+			"1234"
+		);
+		assert!(!exceeded.is_exceeded());
+
+		let request = Request::post("/").body(Body::from("12345")).unwrap();
+		let (request, exceeded) = limit.apply(Branch::Web, request, None).ok().unwrap();
+		assert!(hyper::body::to_bytes(request.into_body()).await.is_err());
+		assert!(exceeded.is_exceeded());
+	}
+}
@@ -0,0 +1,108 @@
+use std::{
+	fmt,
+	sync::{Mutex, PoisonError},
+	task::{Context, Poll},
+};
+
+use futures::future::BoxFuture;
+use http_body::combinators::UnsyncBoxBody;
+use hyper::{
+	body::{Bytes, HttpBody},
+	Body, Request, Response,
+};
+use tower::{
+	util::{BoxCloneService, ServiceExt},
+	Service,
+};
+
+use crate::{BoxedError, Multiplexer};
+
+type BoxResponse = Response<UnsyncBoxBody<Bytes, BoxedError>>;
+
+/// [Multiplexer] with the types of its inner services erased, see [Multiplexer::boxed]
+///
+/// It is `Send`, `Sync` and `Clone`, so it can also be shared by reference,
+/// like in the state of an application.
+pub struct BoxMultiplexer {
+	//BoxCloneService is not Sync, it is only locked to be cloned
+	inner: Mutex<BoxCloneService<Request<Body>, BoxResponse, BoxedError>>,
+}
+
+impl BoxMultiplexer {
+	fn inner(&mut self) -> &mut BoxCloneService<Request<Body>, BoxResponse, BoxedError> {
+		self.inner.get_mut().unwrap_or_else(PoisonError::into_inner)
+	}
+}
+
+impl Clone for BoxMultiplexer {
+	fn clone(&self) -> Self {
+		let inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+		BoxMultiplexer {
+			inner: Mutex::new(inner.clone()),
+		}
+	}
+}
+
+impl fmt::Debug for BoxMultiplexer {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("BoxMultiplexer").finish_non_exhaustive()
+	}
+}
+
+impl Service<Request<Body>> for BoxMultiplexer {
+	type Response = BoxResponse;
+	type Error = BoxedError;
+	type Future = BoxFuture<'static, Result<BoxResponse, BoxedError>>;
+
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		self.inner().poll_ready(cx)
+	}
+
+	fn call(&mut self, req: Request<Body>) -> Self::Future {
+		self.inner().call(req)
+	}
+}
+
+impl<Grpc, Web, GrpcBody, WebBody> Multiplexer<Grpc, Web>
+where
+	Grpc: Service<Request<Body>, Response = Response<GrpcBody>> + Clone + Send + 'static,
+	Web: Service<Request<Body>, Response = Response<WebBody>> + Clone + Send + 'static,
+	Grpc::Future: Send + 'static,
+	Web::Future: Send + 'static,
+	Grpc::Error: Into<BoxedError>,
+	Web::Error: Into<BoxedError>,
+	GrpcBody: HttpBody + Send + 'static,
+	WebBody: HttpBody + Send + 'static,
+	GrpcBody::Data: Into<Bytes>,
+	WebBody::Data: Into<Bytes>,
+	GrpcBody::Error: Into<BoxedError>,
+	WebBody::Error: Into<BoxedError>,
+{
+	/// Erase the types of the inner services and of the response body
+	///
+	/// The boxed service can be stored in struct fields, returned from
+	/// functions, or chosen at runtime, whatever layers the inner services have.
+	///
+	/// # Examples:
+	/// ```
+	/// use multiplex_tonic_hyper::{BoxMultiplexer, Multiplexer};
+	/// # use hyper::{service::service_fn, Body, Request, Response};
+	/// # async fn handle(_: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+	/// # 	Ok(Response::new(Body::empty()))
+	/// # }
+	///
+	/// struct App {
+	/// 	multiplexer: BoxMultiplexer,
+	/// }
+	///
+	/// let app = App {
+	/// 	multiplexer: Multiplexer::new(service_fn(handle), service_fn(handle)).boxed(),
+	/// };
+	/// ```
+	pub fn boxed(self) -> BoxMultiplexer {
+		let inner = self.map_response(|response| response.map(UnsyncBoxBody::new));
+		BoxMultiplexer {
+			inner: Mutex::new(BoxCloneService::new(inner)),
+		}
+	}
+}
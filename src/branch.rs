@@ -0,0 +1,504 @@
+use std::{future::Future, task::Poll};
+
+use futures::future::BoxFuture;
+use hyper::{
+	body::{Bytes, HttpBody},
+	http::response::Parts,
+	Body, Request, Response,
+};
+use pin_project::pin_project;
+use tower::Service;
+
+#[cfg(any(feature = "connect", feature = "transcoding"))]
+use crate::adapter::{self, Adapted, AdaptedBody};
+#[cfg(feature = "compression")]
+use crate::compression::{Encoder, Negotiated};
+use crate::{
+	into_data,
+	lifecycle::Lifecycle,
+	limit::{Loan, Permit},
+	local::{LocalBody, LocalFuture},
+	timeout, to_boxed, BoxedError,
+};
+
+/// Inner service selected by the [Multiplexer][crate::Multiplexer] for a request
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Branch {
+	///The request was sent to the gRPC service
+	Grpc,
+	///The request was sent to the web service
+	Web,
+}
+
+impl Branch {
+	/// Lowercase name of the branch, used in logs and labels
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Branch::Grpc => "grpc",
+			Branch::Web => "web",
+		}
+	}
+}
+
+impl std::fmt::Display for Branch {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(self.as_str())
+	}
+}
+
+/// Future returned by one of the inner services, with the state of its request
+///
+/// The request state is moved to the [BranchBody] when the response is ready,
+/// so it lives until the response body ends, or is dropped.
+#[pin_project]
+pub struct BranchFuture<S, B>
+where
+	S: Service<Request<Body>>,
+{
+	#[pin]
+	inner: State<S, B>,
+	lifecycle: Option<Lifecycle>,
+}
+
+/// The response comes from the inner service, or is generated by the multiplexer
+//Deferred calls are only used by the requests that wait
+#[allow(clippy::large_enum_variant)]
+#[pin_project(project = StateProj)]
+enum State<S, B>
+where
+	S: Service<Request<Body>>,
+{
+	//The inner service, lent to a request until it gets a slot of the
+	//concurrency limit and the service is ready
+	Deferred {
+		queued: Option<BoxFuture<'static, Permit>>,
+		service: Loan<S>,
+		request: Option<Request<Body>>,
+	},
+	Inner(#[pin] S::Future),
+	Local(LocalFuture),
+	//A converted response, read until its end to be converted at once
+	#[cfg_attr(
+		not(any(feature = "connect", feature = "transcoding")),
+		allow(dead_code)
+	)]
+	Buffering {
+		parts: Option<Parts>,
+		#[pin]
+		body: B,
+	},
+}
+
+impl<S, B> BranchFuture<S, B>
+where
+	S: Service<Request<Body>>,
+{
+	pub(crate) fn new(inner: S::Future, lifecycle: Lifecycle) -> Self {
+		BranchFuture {
+			inner: State::Inner(inner),
+			lifecycle: Some(lifecycle),
+		}
+	}
+
+	/// Call `service` with the request after waiting for `queued` and for its readiness
+	pub(crate) fn deferred(
+		service: Loan<S>,
+		request: Request<Body>,
+		queued: BoxFuture<'static, Permit>,
+		lifecycle: Lifecycle,
+	) -> Self {
+		BranchFuture {
+			inner: State::Deferred {
+				queued: Some(queued),
+				service,
+				request: Some(request),
+			},
+			lifecycle: Some(lifecycle),
+		}
+	}
+
+	/// Future that answers the request without calling the inner service
+	pub(crate) fn local(local: LocalFuture, lifecycle: Lifecycle) -> Self {
+		BranchFuture {
+			inner: State::Local(local),
+			lifecycle: Some(lifecycle),
+		}
+	}
+}
+
+impl<S, B> Future for BranchFuture<S, B>
+where
+	S: Service<Request<Body>, Response = Response<B>>,
+	S::Error: Into<BoxedError>,
+	B: HttpBody,
+	B::Data: Into<Bytes>,
+	B::Error: Into<BoxedError>,
+{
+	type Output = Result<Response<BranchBody<B>>, BoxedError>;
+
+	//Only converted responses poll again, once they are buffered
+	#[cfg_attr(
+		not(any(feature = "connect", feature = "transcoding")),
+		allow(clippy::never_loop)
+	)]
+	fn poll(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
+		let this = self.project();
+		let lifecycle = this
+			.lifecycle
+			.as_mut()
+			.expect("BranchFuture polled after completion");
+		let _entered = lifecycle.enter();
+		if let Some(error) = lifecycle.poll_aborted(cx) {
+			lifecycle.on_error(&error);
+			return Poll::Ready(Err(error));
+		}
+		let mut inner = this.inner;
+		let result = loop {
+			let result = match poll_inner(inner.as_mut(), lifecycle, cx) {
+				//The inner service may fail, or answer anything, after reading too much
+				Poll::Ready(result) => match lifecycle.body_limit_response() {
+					Some(response) => Ok(response.map(Kind::Local)),
+					None => result,
+				},
+				Poll::Pending => match lifecycle.poll_deadline(cx, false) {
+					Some(branch) => Ok(timeout::response(branch).map(Kind::Local)),
+					None => return Poll::Pending,
+				},
+			};
+			#[cfg(any(feature = "connect", feature = "transcoding"))]
+			let result = match (result, lifecycle.take_adapted()) {
+				(Ok(response), Some(adapted)) => match adapt(response, adapted) {
+					Adapt::Converted(response) => Ok(response),
+					Adapt::Buffer(parts, body, adapted) => {
+						lifecycle.set_adapted(adapted);
+						inner.set(State::Buffering {
+							parts: Some(parts),
+							body,
+						});
+						continue;
+					}
+				},
+				(result, _) => result,
+			};
+			break result;
+		};
+		#[cfg(feature = "compression")]
+		let result = match lifecycle.take_compression() {
+			Some(negotiated) => result.map(|response| compress(response, negotiated)),
+			None => result,
+		};
+		match result {
+			Ok(mut response) => {
+				if let Some(headers) = lifecycle.take_response_headers() {
+					let response_headers = response.headers_mut();
+					for (name, value) in headers {
+						if name == hyper::header::VARY {
+							response_headers.append(name, value);
+						} else {
+							response_headers.insert(name, value);
+						}
+					}
+				}
+				lifecycle.on_response(&response);
+				//Servers may not poll a body that is already complete
+				if response.body().is_end() {
+					lifecycle.on_data_end();
+				}
+				let lifecycle = this.lifecycle.take().unwrap();
+				Poll::Ready(Ok(response.map(|inner| BranchBody {
+					inner,
+					lifecycle,
+					timed_out: false,
+				})))
+			}
+			Err(error) => {
+				lifecycle.on_error(&error);
+				Poll::Ready(Err(error))
+			}
+		}
+	}
+}
+
+/// Response of a request converted from another protocol
+#[cfg(any(feature = "connect", feature = "transcoding"))]
+enum Adapt<B> {
+	Converted(Response<Kind<B>>),
+	//The body is read before the response is converted
+	Buffer(Parts, B, Box<dyn Adapted>),
+}
+
+/// Convert the response of a request converted from another protocol
+#[cfg(any(feature = "connect", feature = "transcoding"))]
+fn adapt<B>(response: Response<Kind<B>>, mut adapted: Box<dyn Adapted>) -> Adapt<B> {
+	let (mut parts, body) = response.into_parts();
+	let body = match body {
+		Kind::Inner(body) if adapted.buffered() => return Adapt::Buffer(parts, body, adapted),
+		body => body,
+	};
+	if let Some(replaced) = adapted.head(&mut parts) {
+		return Adapt::Converted(replaced.map(Kind::Local));
+	}
+	let body = match body {
+		Kind::Inner(body) => Kind::Adapted(body, AdaptedBody::new(adapted)),
+		body => body,
+	};
+	Adapt::Converted(Response::from_parts(parts, body))
+}
+
+/// Compress the response of the web service, if it can be compressed
+#[cfg(feature = "compression")]
+fn compress<B: HttpBody>(response: Response<Kind<B>>, negotiated: Negotiated) -> Response<Kind<B>> {
+	let (mut parts, body) = response.into_parts();
+	let body = match body {
+		Kind::Inner(body) if !body.is_end_stream() => {
+			match negotiated.apply(&mut parts, body.size_hint()) {
+				Some(encoder) => Kind::Compressed(body, encoder),
+				None => Kind::Inner(body),
+			}
+		}
+		body => body,
+	};
+	Response::from_parts(parts, body)
+}
+
+/// Poll the inner future, after calling a deferred service
+fn poll_inner<S, B>(
+	mut inner: std::pin::Pin<&mut State<S, B>>,
+	lifecycle: &mut Lifecycle,
+	cx: &mut std::task::Context<'_>,
+) -> Poll<Result<Response<Kind<B>>, BoxedError>>
+where
+	S: Service<Request<Body>, Response = Response<B>>,
+	S::Error: Into<BoxedError>,
+	B: HttpBody,
+	B::Data: Into<Bytes>,
+	B::Error: Into<BoxedError>,
+{
+	loop {
+		let future = match inner.as_mut().project() {
+			StateProj::Deferred {
+				queued,
+				service,
+				request,
+			} => {
+				if let Some(waiting) = queued {
+					let permit = futures::ready!(waiting.as_mut().poll(cx));
+					lifecycle.hold_permit(permit);
+					*queued = None;
+				}
+				futures::ready!(service.poll_ready(cx)).map_err(to_boxed)?;
+				let request = request.take().expect("deferred service called twice");
+				service.call(request)
+			}
+			StateProj::Inner(inner) => {
+				return inner
+					.poll(cx)
+					.map_ok(|r| r.map(Kind::Inner))
+					.map_err(to_boxed)
+			}
+			StateProj::Local(local) => {
+				return local.as_mut().poll(cx).map_ok(|r| r.map(Kind::Local))
+			}
+			#[cfg(any(feature = "connect", feature = "transcoding"))]
+			StateProj::Buffering { parts, body } => {
+				let adapted = lifecycle
+					.adapted_mut()
+					.expect("buffered response without adapter");
+				let response = futures::ready!(adapter::poll_buffered(adapted, parts, body, cx));
+				//The response is already converted
+				lifecycle.take_adapted();
+				return Poll::Ready(response.map(|r| r.map(Kind::Local)));
+			}
+			#[cfg(not(any(feature = "connect", feature = "transcoding")))]
+			StateProj::Buffering { .. } => unreachable!("only converted responses are buffered"),
+		};
+		inner.set(State::Inner(future));
+	}
+}
+
+/// Response body of one of the inner services, with the state of its request
+///
+/// The request is complete when the body returns its trailers, or when it is dropped.
+#[pin_project]
+pub struct BranchBody<B> {
+	#[pin]
+	inner: Kind<B>,
+	lifecycle: Lifecycle,
+	//The deadline expired, and the gRPC trailers were not returned yet
+	timed_out: bool,
+}
+
+#[pin_project(project = KindProj)]
+enum Kind<B> {
+	Inner(#[pin] B),
+	Local(LocalBody),
+	//The body of a converted request, converted back to its protocol
+	#[cfg(any(feature = "connect", feature = "transcoding"))]
+	Adapted(#[pin] B, AdaptedBody),
+	//The body of a web response, compressed
+	#[cfg(feature = "compression")]
+	Compressed(#[pin] B, Encoder),
+}
+
+impl<B: HttpBody> Kind<B> {
+	fn is_end(&self) -> bool {
+		match self {
+			Kind::Inner(body) => body.is_end_stream(),
+			Kind::Local(body) => body.is_end_stream(),
+			#[cfg(any(feature = "connect", feature = "transcoding"))]
+			Kind::Adapted(_, adapted) => adapted.is_end(),
+			#[cfg(feature = "compression")]
+			Kind::Compressed(_, encoder) => encoder.is_end(),
+		}
+	}
+}
+
+impl<B> HttpBody for Kind<B>
+where
+	B: HttpBody,
+	B::Data: Into<hyper::body::Bytes>,
+	B::Error: Into<BoxedError>,
+{
+	type Data = hyper::body::Bytes;
+
+	type Error = BoxedError;
+
+	fn poll_data(
+		self: std::pin::Pin<&mut Self>,
+		cx: &mut std::task::Context<'_>,
+	) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+		match self.project() {
+			KindProj::Inner(body) => body.poll_data(cx).map_ok(into_data).map_err(to_boxed),
+			KindProj::Local(body) => std::pin::Pin::new(body).poll_data(cx),
+			#[cfg(any(feature = "connect", feature = "transcoding"))]
+			KindProj::Adapted(body, adapted) => adapted.poll_data(body, cx),
+			#[cfg(feature = "compression")]
+			KindProj::Compressed(body, encoder) => encoder.poll_data(body, cx),
+		}
+	}
+
+	fn poll_trailers(
+		self: std::pin::Pin<&mut Self>,
+		cx: &mut std::task::Context<'_>,
+	) -> Poll<Result<Option<hyper::HeaderMap>, Self::Error>> {
+		match self.project() {
+			KindProj::Inner(body) => body.poll_trailers(cx).map_err(to_boxed),
+			KindProj::Local(body) => std::pin::Pin::new(body).poll_trailers(cx),
+			//The trailers of the gRPC response were converted in the body
+			#[cfg(any(feature = "connect", feature = "transcoding"))]
+			KindProj::Adapted(..) => Poll::Ready(Ok(None)),
+			#[cfg(feature = "compression")]
+			KindProj::Compressed(body, _) => body.poll_trailers(cx).map_err(to_boxed),
+		}
+	}
+
+	fn is_end_stream(&self) -> bool {
+		self.is_end()
+	}
+
+	fn size_hint(&self) -> hyper::body::SizeHint {
+		match self {
+			Kind::Inner(body) => body.size_hint(),
+			Kind::Local(body) => body.size_hint(),
+			#[cfg(any(feature = "connect", feature = "transcoding"))]
+			Kind::Adapted(..) => Default::default(),
+			#[cfg(feature = "compression")]
+			Kind::Compressed(..) => Default::default(),
+		}
+	}
+}
+
+impl<B> HttpBody for BranchBody<B>
+where
+	B: HttpBody,
+	B::Data: Into<hyper::body::Bytes>,
+	B::Error: Into<BoxedError>,
+{
+	type Data = hyper::body::Bytes;
+
+	type Error = BoxedError;
+
+	fn poll_data(
+		self: std::pin::Pin<&mut Self>,
+		cx: &mut std::task::Context<'_>,
+	) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+		let mut this = self.project();
+		let _entered = this.lifecycle.enter();
+		if let Some(error) = this.lifecycle.poll_aborted(cx) {
+			this.lifecycle.on_error(&error);
+			return Poll::Ready(Some(Err(error)));
+		}
+		if *this.timed_out {
+			return Poll::Ready(None);
+		}
+		let poll = this.inner.as_mut().poll_data(cx);
+		match &poll {
+			Poll::Ready(Some(Ok(data))) => {
+				this.lifecycle.on_data(data);
+				//Servers stop polling when the body reaches its known length
+				if this.inner.is_end_stream() {
+					this.lifecycle.on_data_end();
+				}
+			}
+			Poll::Ready(Some(Err(error))) => this.lifecycle.on_error(error),
+			Poll::Ready(None) => this.lifecycle.on_data_end(),
+			Poll::Pending => match this.lifecycle.poll_deadline(cx, true) {
+				Some(Branch::Grpc) => {
+					//Ends the stream, the status is sent in the trailers
+					*this.timed_out = true;
+					this.lifecycle.on_data_end();
+					return Poll::Ready(None);
+				}
+				Some(Branch::Web) => {
+					let error = timeout::error();
+					this.lifecycle.on_error(&error);
+					return Poll::Ready(Some(Err(error)));
+				}
+				None => {}
+			},
+		}
+		poll
+	}
+
+	fn poll_trailers(
+		self: std::pin::Pin<&mut Self>,
+		cx: &mut std::task::Context<'_>,
+	) -> Poll<Result<Option<hyper::HeaderMap>, Self::Error>> {
+		let this = self.project();
+		let _entered = this.lifecycle.enter();
+		if let Some(error) = this.lifecycle.poll_aborted(cx) {
+			this.lifecycle.on_error(&error);
+			return Poll::Ready(Err(error));
+		}
+		let poll = if *this.timed_out {
+			Poll::Ready(Ok(Some(timeout::trailers())))
+		} else {
+			match this.inner.poll_trailers(cx) {
+				Poll::Pending => match this.lifecycle.poll_deadline(cx, true) {
+					Some(Branch::Grpc) => Poll::Ready(Ok(Some(timeout::trailers()))),
+					Some(Branch::Web) => Poll::Ready(Err(timeout::error())),
+					None => Poll::Pending,
+				},
+				Poll::Ready(Ok(trailers)) => match this.lifecycle.body_limit_trailers() {
+					Some(replaced) => Poll::Ready(Ok(Some(replaced))),
+					None => Poll::Ready(Ok(trailers)),
+				},
+				poll => poll,
+			}
+		};
+		match &poll {
+			Poll::Ready(Ok(trailers)) => this.lifecycle.on_trailers(trailers.as_ref()),
+			Poll::Ready(Err(error)) => this.lifecycle.on_error(error),
+			Poll::Pending => {}
+		}
+		poll
+	}
+
+	fn is_end_stream(&self) -> bool {
+		self.inner.is_end_stream()
+	}
+
+	fn size_hint(&self) -> hyper::body::SizeHint {
+		self.inner.size_hint()
+	}
+}
@@ -0,0 +1,213 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request};
+use tower::Service;
+
+use crate::{
+	limit::Limiter, AccessLog, BodyLimit, Branch, Classifier, ConcurrencyLimit, CorsPolicy,
+	DrainHandle, GrpcContentType, MakeMultiplexer, Multiplexer, Timeout,
+};
+
+/// Options shared by every [Multiplexer] created from the same [Builder]
+#[derive(Clone, Default)]
+pub(crate) struct Config {
+	pub(crate) content_type: GrpcContentType,
+	pub(crate) classifier: Option<Classifier>,
+	pub(crate) access_log: Option<AccessLog>,
+	pub(crate) drain: Option<DrainHandle>,
+	pub(crate) grpc_limit: Option<Limiter>,
+	pub(crate) web_limit: Option<Limiter>,
+	pub(crate) grpc_timeout: Option<Timeout>,
+	pub(crate) web_timeout: Option<Timeout>,
+	pub(crate) grpc_body_limit: Option<BodyLimit>,
+	pub(crate) web_body_limit: Option<BodyLimit>,
+	pub(crate) grpc_cors: Option<CorsPolicy>,
+	pub(crate) web_cors: Option<CorsPolicy>,
+	#[cfg(feature = "health")]
+	pub(crate) health: Option<crate::HealthHandle>,
+	#[cfg(feature = "connect")]
+	pub(crate) connect: Option<crate::ConnectProtocol>,
+	#[cfg(feature = "transcoding")]
+	pub(crate) transcoder: Option<crate::Transcoder>,
+	#[cfg(feature = "reflection")]
+	pub(crate) reflection: Option<crate::Reflection>,
+	#[cfg(feature = "compression")]
+	pub(crate) compression: Option<crate::Compression>,
+}
+
+impl Config {
+	pub(crate) fn limiter(&self, branch: Branch) -> Option<&Limiter> {
+		match branch {
+			Branch::Grpc => self.grpc_limit.as_ref(),
+			Branch::Web => self.web_limit.as_ref(),
+		}
+	}
+
+	pub(crate) fn body_limit(&self, branch: Branch) -> Option<&BodyLimit> {
+		match branch {
+			Branch::Grpc => self.grpc_body_limit.as_ref(),
+			Branch::Web => self.web_body_limit.as_ref(),
+		}
+	}
+
+	pub(crate) fn cors(&self, branch: Branch) -> Option<&CorsPolicy> {
+		match branch {
+			Branch::Grpc => self.grpc_cors.as_ref(),
+			Branch::Web => self.web_cors.as_ref(),
+		}
+	}
+
+	pub(crate) fn timeout(&self, branch: Branch) -> Option<&Timeout> {
+		match branch {
+			Branch::Grpc => self.grpc_timeout.as_ref(),
+			Branch::Web => self.web_timeout.as_ref(),
+		}
+	}
+}
+
+/// Builder for a [Multiplexer] or a [MakeMultiplexer] with optional features
+///
+/// [Multiplexer::new] and [MakeMultiplexer::new] are the same as building
+/// with the default options.
+///
+/// # Examples:
+/// ```
+/// use multiplex_tonic_hyper::{AccessLog, Multiplexer};
+/// use tower::make::Shared;
+/// # use hyper::{service::service_fn, Body, Request, Response};
+/// # async fn handle(_: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+/// # 	Ok(Response::new(Body::empty()))
+/// # }
+/// # let grpc = service_fn(handle);
+/// # let web = service_fn(handle);
+///
+/// let make_multiplexer = Multiplexer::builder()
+/// 	.access_log(AccessLog::json(std::io::stderr()))
+/// 	.build_make(Shared::new(grpc), Shared::new(web));
+/// ```
+#[derive(Clone, Default)]
+pub struct Builder {
+	config: Config,
+}
+
+impl Builder {
+	/// Builder with the default options
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Select which `content-type` values go to the gRPC branch, see [GrpcContentType]
+	pub fn grpc_content_type(mut self, content_type: GrpcContentType) -> Self {
+		self.config.content_type = content_type;
+		self
+	}
+
+	/// Send the requests matching `classifier` to the gRPC branch, instead of
+	/// checking the content type alone, see [Classifier]
+	pub fn classifier(mut self, classifier: Classifier) -> Self {
+		self.config.classifier = Some(classifier);
+		self
+	}
+
+	/// Emit an access log record for each request
+	pub fn access_log(mut self, access_log: AccessLog) -> Self {
+		self.config.access_log = Some(access_log);
+		self
+	}
+
+	/// Reject new requests once `drain` starts draining, see [DrainHandle]
+	pub fn drain(mut self, drain: DrainHandle) -> Self {
+		self.config.drain = Some(drain);
+		self
+	}
+
+	/// Limit the requests in flight on `branch`, see [ConcurrencyLimit]
+	pub fn concurrency_limit(mut self, branch: Branch, limit: ConcurrencyLimit) -> Self {
+		let limiter = Some(Limiter::new(limit));
+		match branch {
+			Branch::Grpc => self.config.grpc_limit = limiter,
+			Branch::Web => self.config.web_limit = limiter,
+		}
+		self
+	}
+
+	/// Time out the requests on `branch`, see [Timeout]
+	pub fn timeout(mut self, branch: Branch, timeout: Timeout) -> Self {
+		match branch {
+			Branch::Grpc => self.config.grpc_timeout = Some(timeout),
+			Branch::Web => self.config.web_timeout = Some(timeout),
+		}
+		self
+	}
+
+	/// Limit the size of the request bodies on `branch`, see [BodyLimit]
+	pub fn body_limit(mut self, branch: Branch, limit: BodyLimit) -> Self {
+		match branch {
+			Branch::Grpc => self.config.grpc_body_limit = Some(limit),
+			Branch::Web => self.config.web_body_limit = Some(limit),
+		}
+		self
+	}
+
+	/// Answer CORS preflights, and add CORS headers to the responses on `branch`, see [CorsPolicy]
+	pub fn cors(mut self, branch: Branch, policy: CorsPolicy) -> Self {
+		match branch {
+			Branch::Grpc => self.config.grpc_cors = Some(policy),
+			Branch::Web => self.config.web_cors = Some(policy),
+		}
+		self
+	}
+
+	/// Serve the health endpoints described in [HealthHandle][crate::HealthHandle]
+	#[cfg(feature = "health")]
+	pub fn health(mut self, health: crate::HealthHandle) -> Self {
+		self.config.health = Some(health);
+		self
+	}
+
+	/// Serve the gRPC methods annotated with `google.api.http` as JSON, see [Transcoder][crate::Transcoder]
+	#[cfg(feature = "transcoding")]
+	pub fn transcoding(mut self, transcoder: crate::Transcoder) -> Self {
+		self.config.transcoder = Some(transcoder);
+		self
+	}
+
+	/// Serve the gRPC service to Connect clients, see [ConnectProtocol][crate::ConnectProtocol]
+	#[cfg(feature = "connect")]
+	pub fn connect(mut self, connect: crate::ConnectProtocol) -> Self {
+		self.config.connect = Some(connect);
+		self
+	}
+
+	/// Serve gRPC server reflection for some descriptors, see [Reflection][crate::Reflection]
+	#[cfg(feature = "reflection")]
+	pub fn reflection(mut self, reflection: crate::Reflection) -> Self {
+		self.config.reflection = Some(reflection);
+		self
+	}
+
+	/// Compress the responses of the web branch, see [Compression][crate::Compression]
+	#[cfg(feature = "compression")]
+	pub fn compression(mut self, compression: crate::Compression) -> Self {
+		self.config.compression = Some(compression);
+		self
+	}
+
+	/// Build a [Multiplexer] with these options
+	pub fn build<Grpc, Web>(self, grpc: Grpc, web: Web) -> Multiplexer<Grpc, Web>
+	where
+		Grpc: Service<Request<Body>>,
+		Web: Service<Request<Body>>,
+	{
+		Multiplexer::with_config(grpc, web, Arc::new(self.config))
+	}
+
+	/// Build a [MakeMultiplexer], that creates all its Multiplexers with these options
+	pub fn build_make<MakeGrpc, MakeWeb>(
+		self,
+		make_grpc: MakeGrpc,
+		make_web: MakeWeb,
+	) -> MakeMultiplexer<MakeGrpc, MakeWeb> {
+		MakeMultiplexer::with_config(make_grpc, make_web, Arc::new(self.config))
+	}
+}
@@ -0,0 +1,210 @@
+use std::{fmt, ops::Not, sync::Arc};
+
+use hyper::{header::TE, Body, Method, Request, Version};
+
+use crate::{Branch, GrpcContentType};
+
+/// Condition that sends the requests matching it to the gRPC branch of a [Multiplexer][crate::Multiplexer]
+///
+/// Conditions are combined with [and][Classifier::and], [or][Classifier::or]
+/// and `!`. The default classifier is the lenient [content_type][Classifier::content_type] check.
+///
+/// A Multiplexer with a classifier does not use the rejection of
+/// [GrpcContentType::reject_mismatched], the requests not matching go to the web branch.
+///
+/// # Examples:
+/// ```
+/// use hyper::Version;
+/// use multiplex_tonic_hyper::{Classifier, GrpcContentType, Multiplexer};
+/// # use hyper::{service::service_fn, Body, Request, Response};
+/// # async fn handle(_: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+/// # 	Ok(Response::new(Body::empty()))
+/// # }
+/// # let grpc = service_fn(handle);
+/// # let web = service_fn(handle);
+///
+/// //HTTP/2 POST requests with gRPC content type, or with `te: trailers`
+/// let classifier = Classifier::version(Version::HTTP_2)
+/// 	.and(Classifier::method(hyper::Method::POST))
+/// 	.and(Classifier::content_type(GrpcContentType::strict()).or(Classifier::te_trailers()));
+/// let multiplexer = Multiplexer::builder().classifier(classifier).build(grpc, web);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Classifier(Rule);
+
+type Condition = dyn Fn(&Request<Body>) -> bool + Send + Sync;
+
+#[derive(Clone)]
+enum Rule {
+	Version(Version),
+	TeTrailers,
+	Method(Method),
+	ContentType(GrpcContentType),
+	Fn(Arc<Condition>),
+	And(Box<Rule>, Box<Rule>),
+	Or(Box<Rule>, Box<Rule>),
+	Not(Box<Rule>),
+}
+
+impl Classifier {
+	/// Match requests with this HTTP version
+	pub fn version(version: Version) -> Self {
+		Classifier(Rule::Version(version))
+	}
+
+	/// Match requests with `trailers` in their `te` header
+	pub fn te_trailers() -> Self {
+		Classifier(Rule::TeTrailers)
+	}
+
+	/// Match requests with this method
+	pub fn method(method: Method) -> Self {
+		Classifier(Rule::Method(method))
+	}
+
+	/// Match requests whose `content-type` is accepted by `content_type`
+	pub fn content_type(content_type: GrpcContentType) -> Self {
+		Classifier(Rule::ContentType(content_type))
+	}
+
+	/// Match requests for which `condition` returns true
+	pub fn from_fn(condition: impl Fn(&Request<Body>) -> bool + Send + Sync + 'static) -> Self {
+		Classifier(Rule::Fn(Arc::new(condition)))
+	}
+
+	/// Match requests matching both conditions
+	pub fn and(self, other: Classifier) -> Self {
+		Classifier(Rule::And(Box::new(self.0), Box::new(other.0)))
+	}
+
+	/// Match requests matching any of the conditions
+	pub fn or(self, other: Classifier) -> Self {
+		Classifier(Rule::Or(Box::new(self.0), Box::new(other.0)))
+	}
+
+	/// If `request` matches this condition
+	pub fn matches(&self, request: &Request<Body>) -> bool {
+		self.0.matches(request)
+	}
+
+	pub(crate) fn branch(&self, request: &Request<Body>) -> Branch {
+		match self.matches(request) {
+			true => Branch::Grpc,
+			false => Branch::Web,
+		}
+	}
+}
+
+impl Default for Classifier {
+	fn default() -> Self {
+		Self::content_type(GrpcContentType::default())
+	}
+}
+
+impl Not for Classifier {
+	type Output = Classifier;
+
+	/// Match requests not matching this condition
+	fn not(self) -> Self::Output {
+		Classifier(Rule::Not(Box::new(self.0)))
+	}
+}
+
+impl Rule {
+	fn matches(&self, request: &Request<Body>) -> bool {
+		match self {
+			Rule::Version(version) => request.version() == *version,
+			Rule::TeTrailers => request
+				.headers()
+				.get_all(TE)
+				.iter()
+				.filter_map(|value| value.to_str().ok())
+				.flat_map(|value| value.split(','))
+				.any(|coding| {
+					let coding = coding.split(';').next().unwrap_or_default();
+					coding.trim().eq_ignore_ascii_case("trailers")
+				}),
+			Rule::Method(method) => request.method() == method,
+			Rule::ContentType(content_type) => {
+				content_type.classify(request.headers()) == Some(Branch::Grpc)
+			}
+			Rule::Fn(condition) => condition(request),
+			Rule::And(a, b) => a.matches(request) && b.matches(request),
+			Rule::Or(a, b) => a.matches(request) || b.matches(request),
+			Rule::Not(rule) => !rule.matches(request),
+		}
+	}
+}
+
+impl fmt::Debug for Rule {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Rule::Version(version) => f.debug_tuple("Version").field(version).finish(),
+			Rule::TeTrailers => f.write_str("TeTrailers"),
+			Rule::Method(method) => f.debug_tuple("Method").field(method).finish(),
+			Rule::ContentType(content_type) => {
+				f.debug_tuple("ContentType").field(content_type).finish()
+			}
+			Rule::Fn(_) => f.write_str("Fn"),
+			Rule::And(a, b) => f.debug_tuple("And").field(a).field(b).finish(),
+			Rule::Or(a, b) => f.debug_tuple("Or").field(a).field(b).finish(),
+			Rule::Not(rule) => f.debug_tuple("Not").field(rule).finish(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use hyper::{header::CONTENT_TYPE, Body, Method, Request, Version};
+
+	use super::Classifier;
+	use crate::GrpcContentType;
+
+	fn request(version: Version, te: Option<&str>, content_type: Option<&str>) -> Request<Body> {
+		let mut request = Request::post("/").version(version);
+		if let Some(te) = te {
+			request = request.header("te", te);
+		}
+		if let Some(content_type) = content_type {
+			request = request.header(CONTENT_TYPE, content_type);
+		}
+		request.body(Body::empty()).unwrap()
+	}
+
+	#[test]
+	fn te_trailers_reads_every_coding() {
+		let te = Classifier::te_trailers();
+		assert!(te.matches(&request(Version::HTTP_2, Some("trailers"), None)));
+		assert!(te.matches(&request(Version::HTTP_2, Some("gzip, Trailers;q=1"), None)));
+		assert!(!te.matches(&request(Version::HTTP_2, Some("gzip"), None)));
+		assert!(!te.matches(&request(Version::HTTP_2, None, None)));
+	}
+
+	#[test]
+	fn combinators_follow_boolean_logic() {
+		let http2 = || Classifier::version(Version::HTTP_2);
+		let grpc = || Classifier::content_type(GrpcContentType::strict());
+		let classifier = http2()
+			.and(Classifier::method(Method::POST))
+			.and(grpc().or(Classifier::te_trailers()));
+		assert!(classifier.matches(&request(Version::HTTP_2, Some("trailers"), None)));
+		assert!(classifier.matches(&request(Version::HTTP_2, None, Some("application/grpc"))));
+		assert!(!classifier.matches(&request(Version::HTTP_11, Some("trailers"), None)));
+		assert!(!classifier.matches(&request(Version::HTTP_2, None, Some("text/plain"))));
+
+		let not_http2 = !http2();
+		assert!(not_http2.matches(&request(Version::HTTP_11, None, None)));
+		assert!(!not_http2.matches(&request(Version::HTTP_2, None, None)));
+	}
+
+	#[test]
+	fn default_classifier_checks_the_content_type_prefix() {
+		let classifier = Classifier::default();
+		assert!(classifier.matches(&request(
+			Version::HTTP_11,
+			None,
+			Some("application/grpc-web")
+		)));
+		assert!(!classifier.matches(&request(Version::HTTP_2, Some("trailers"), None)));
+	}
+}
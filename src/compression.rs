@@ -0,0 +1,382 @@
+use std::{
+	io::{self, Write},
+	pin::Pin,
+	task::{Context, Poll},
+};
+
+use hyper::{
+	body::{Bytes, HttpBody, SizeHint},
+	header::{
+		HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE,
+		CONTENT_TYPE, VARY,
+	},
+	http::response::Parts,
+	HeaderMap, StatusCode,
+};
+
+use crate::{to_boxed, BoxedError};
+
+/// Compression of the web responses of a [Multiplexer][crate::Multiplexer]
+///
+/// The encoding is selected from the `accept-encoding` header of the request,
+/// among gzip, brotli and zstd. Responses are not compressed when they already
+/// have a `content-encoding`, when they are partial content, when their content
+/// type is already compressed, like images and archives, or when their size hint
+/// says they are smaller than [min_size][Compression::min_size], 1 KiB by default.
+///
+/// The gRPC branch is never compressed, gRPC compresses each message itself.
+/// Streamed responses are flushed whenever the web service has no data ready,
+/// so the client gets each part without waiting for the next.
+///
+/// # Examples:
+/// ```
+/// use multiplex_tonic_hyper::{Compression, Multiplexer};
+/// # use hyper::{service::service_fn, Body, Request, Response};
+/// # async fn handle(_: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+/// # 	Ok(Response::new(Body::empty()))
+/// # }
+/// # let grpc = service_fn(handle);
+/// # let web = service_fn(handle);
+///
+/// let multiplexer = Multiplexer::builder()
+/// 	.compression(Compression::new().zstd(false).min_size(256))
+/// 	.build(grpc, web);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Compression {
+	gzip: bool,
+	brotli: bool,
+	zstd: bool,
+	min_size: u64,
+}
+
+impl Compression {
+	/// Compress with any of the supported encodings
+	pub fn new() -> Self {
+		Compression {
+			gzip: true,
+			brotli: true,
+			zstd: true,
+			min_size: 1024,
+		}
+	}
+
+	/// Enable or disable gzip
+	pub fn gzip(mut self, enabled: bool) -> Self {
+		self.gzip = enabled;
+		self
+	}
+
+	/// Enable or disable brotli
+	pub fn brotli(mut self, enabled: bool) -> Self {
+		self.brotli = enabled;
+		self
+	}
+
+	/// Enable or disable zstd
+	pub fn zstd(mut self, enabled: bool) -> Self {
+		self.zstd = enabled;
+		self
+	}
+
+	/// Do not compress responses whose size hint is smaller than `min_size` bytes
+	pub fn min_size(mut self, min_size: u64) -> Self {
+		self.min_size = min_size;
+		self
+	}
+
+	fn enabled(&self, encoding: Encoding) -> bool {
+		match encoding {
+			Encoding::Gzip => self.gzip,
+			Encoding::Brotli => self.brotli,
+			Encoding::Zstd => self.zstd,
+		}
+	}
+
+	/// Encoding preferred by the client among the enabled ones
+	pub(crate) fn negotiate(&self, headers: &HeaderMap) -> Option<Negotiated> {
+		let mut weights = [None; 3];
+		let mut wildcard = None;
+		let codings = headers
+			.get_all(ACCEPT_ENCODING)
+			.iter()
+			.filter_map(|value| value.to_str().ok())
+			.flat_map(|value| value.split(','));
+		for coding in codings {
+			let mut params = coding.split(';');
+			let name = params.next().unwrap_or_default().trim();
+			let weight = params
+				.filter_map(|param| param.trim().strip_prefix("q="))
+				.next()
+				.map_or(Some(1.0), |weight| weight.trim().parse::<f32>().ok());
+			let Some(weight) = weight else { continue };
+			if name == "*" {
+				wildcard = Some(weight);
+			} else if let Some(encoding) = Encoding::from_name(name) {
+				weights[encoding as usize] = Some(weight);
+			}
+		}
+		//On ties, the first one is used
+		let mut best: Option<(Encoding, f32)> = None;
+		for encoding in [Encoding::Brotli, Encoding::Zstd, Encoding::Gzip] {
+			let weight = weights[encoding as usize].or(wildcard).unwrap_or(0.0);
+			let better = match best {
+				Some((_, best)) => weight > best,
+				None => true,
+			};
+			if self.enabled(encoding) && weight > 0.0 && better {
+				best = Some((encoding, weight));
+			}
+		}
+		best.map(|(encoding, _)| Negotiated {
+			encoding,
+			min_size: self.min_size,
+		})
+	}
+}
+
+impl Default for Compression {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Supported encodings, their values index the weights in [Compression::negotiate]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Encoding {
+	Gzip = 0,
+	Brotli = 1,
+	Zstd = 2,
+}
+
+impl Encoding {
+	fn from_name(name: &str) -> Option<Self> {
+		let encoding = match name.to_ascii_lowercase().as_str() {
+			"gzip" | "x-gzip" => Encoding::Gzip,
+			"br" => Encoding::Brotli,
+			"zstd" => Encoding::Zstd,
+			_ => return None,
+		};
+		Some(encoding)
+	}
+
+	fn as_str(&self) -> &'static str {
+		match self {
+			Encoding::Gzip => "gzip",
+			Encoding::Brotli => "br",
+			Encoding::Zstd => "zstd",
+		}
+	}
+}
+
+/// Encoding selected for a request, applied if its response can be compressed
+pub(crate) struct Negotiated {
+	encoding: Encoding,
+	min_size: u64,
+}
+
+impl Negotiated {
+	/// Set the headers of a compressed response and return its encoder, if it should be compressed
+	pub(crate) fn apply(&self, parts: &mut Parts, size_hint: SizeHint) -> Option<Encoder> {
+		let small = size_hint.upper().is_some_and(|size| size < self.min_size);
+		let no_content = parts.status.is_informational()
+			|| parts.status == StatusCode::NO_CONTENT
+			|| parts.status == StatusCode::NOT_MODIFIED;
+		//Ranges are offsets of the uncompressed representation
+		let partial = parts.status == StatusCode::PARTIAL_CONTENT;
+		let headers = &mut parts.headers;
+		if small
+			|| no_content
+			|| partial
+			|| headers.contains_key(CONTENT_RANGE)
+			|| headers.contains_key(CONTENT_ENCODING)
+			|| !is_compressible(headers)
+		{
+			return None;
+		}
+		headers.insert(
+			CONTENT_ENCODING,
+			HeaderValue::from_static(self.encoding.as_str()),
+		);
+		headers.remove(CONTENT_LENGTH);
+		headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+		Some(Encoder::new(self.encoding))
+	}
+}
+
+/// If the content type is not compressed already
+fn is_compressible(headers: &HeaderMap) -> bool {
+	let Some(content_type) = headers
+		.get(CONTENT_TYPE)
+		.and_then(|value| value.to_str().ok())
+	else {
+		return true;
+	};
+	let content_type = content_type.trim().to_ascii_lowercase();
+	if content_type.starts_with("image/svg+xml") {
+		return true;
+	}
+	let compressed = [
+		"image/",
+		"audio/",
+		"video/",
+		"font/woff",
+		"application/zip",
+		"application/gzip",
+		"application/zstd",
+	];
+	!compressed
+		.iter()
+		.any(|prefix| content_type.starts_with(prefix))
+}
+
+enum Writer {
+	Gzip(flate2::write::GzEncoder<Vec<u8>>),
+	Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+	Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Writer {
+	fn output(&mut self) -> &mut Vec<u8> {
+		match self {
+			Writer::Gzip(writer) => writer.get_mut(),
+			Writer::Brotli(writer) => writer.get_mut(),
+			Writer::Zstd(writer) => writer.get_mut(),
+		}
+	}
+
+	fn writer(&mut self) -> &mut dyn Write {
+		match self {
+			Writer::Gzip(writer) => writer,
+			Writer::Brotli(writer) => writer,
+			Writer::Zstd(writer) => writer,
+		}
+	}
+
+	fn finish(self) -> io::Result<Vec<u8>> {
+		match self {
+			Writer::Gzip(writer) => writer.finish(),
+			Writer::Brotli(writer) => Ok(writer.into_inner()),
+			Writer::Zstd(writer) => writer.finish(),
+		}
+	}
+}
+
+/// State of a compressed response body
+pub(crate) struct Encoder {
+	//None once the inner body ended
+	writer: Option<Writer>,
+	//Data was written since the last flush
+	unflushed: bool,
+}
+
+impl Encoder {
+	fn new(encoding: Encoding) -> Self {
+		let output = Vec::new();
+		let writer = match encoding {
+			Encoding::Gzip => Writer::Gzip(flate2::write::GzEncoder::new(
+				output,
+				flate2::Compression::default(),
+			)),
+			Encoding::Brotli => {
+				Writer::Brotli(Box::new(brotli::CompressorWriter::new(output, 4096, 5, 22)))
+			}
+			Encoding::Zstd => Writer::Zstd(
+				zstd::stream::write::Encoder::new(output, zstd::DEFAULT_COMPRESSION_LEVEL)
+					.expect("zstd encoder with the default level"),
+			),
+		};
+		Encoder {
+			writer: Some(writer),
+			unflushed: false,
+		}
+	}
+
+	pub(crate) fn poll_data<B>(
+		&mut self,
+		mut body: Pin<&mut B>,
+		cx: &mut Context<'_>,
+	) -> Poll<Option<Result<Bytes, BoxedError>>>
+	where
+		B: HttpBody,
+		B::Data: Into<Bytes>,
+		B::Error: Into<BoxedError>,
+	{
+		loop {
+			let Some(writer) = &mut self.writer else {
+				return Poll::Ready(None);
+			};
+			match body.as_mut().poll_data(cx) {
+				Poll::Ready(Some(Ok(data))) => {
+					let data: Bytes = data.into();
+					writer.writer().write_all(&data).map_err(to_boxed)?;
+					self.unflushed = true;
+				}
+				Poll::Ready(Some(Err(error))) => return Poll::Ready(Some(Err(error.into()))),
+				Poll::Ready(None) => {
+					let output = self.writer.take().unwrap().finish().map_err(to_boxed)?;
+					return Poll::Ready((!output.is_empty()).then(|| Ok(output.into())));
+				}
+				Poll::Pending if self.unflushed => {
+					writer.writer().flush().map_err(to_boxed)?;
+					self.unflushed = false;
+				}
+				Poll::Pending => return Poll::Pending,
+			}
+			let output = std::mem::take(writer.output());
+			if !output.is_empty() {
+				return Poll::Ready(Some(Ok(output.into())));
+			}
+		}
+	}
+
+	pub(crate) fn is_end(&self) -> bool {
+		self.writer.is_none()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use hyper::{header::ACCEPT_ENCODING, HeaderMap};
+
+	use super::{Compression, Encoding};
+
+	fn negotiate(compression: &Compression, accept_encoding: &str) -> Option<Encoding> {
+		let mut headers = HeaderMap::new();
+		headers.insert(ACCEPT_ENCODING, accept_encoding.parse().unwrap());
+		compression
+			.negotiate(&headers)
+			.map(|negotiated| negotiated.encoding)
+	}
+
+	#[test]
+	fn negotiation_follows_the_weights() {
+		let compression = Compression::new();
+		assert_eq!(negotiate(&compression, "gzip"), Some(Encoding::Gzip));
+		assert_eq!(
+			negotiate(&compression, "gzip, deflate, br"),
+			Some(Encoding::Brotli)
+		);
+		assert_eq!(
+			negotiate(&compression, "br;q=0.5, zstd;q=0.8"),
+			Some(Encoding::Zstd)
+		);
+		assert_eq!(
+			negotiate(&compression, "*;q=0.1, br;q=0"),
+			Some(Encoding::Zstd)
+		);
+		assert_eq!(negotiate(&compression, "gzip;q=0, identity"), None);
+		assert_eq!(negotiate(&compression, "deflate"), None);
+		assert!(compression.negotiate(&HeaderMap::new()).is_none());
+	}
+
+	#[test]
+	fn disabled_encodings_are_not_negotiated() {
+		let compression = Compression::new().brotli(false);
+		assert_eq!(
+			negotiate(&compression, "br, gzip;q=0.5"),
+			Some(Encoding::Gzip)
+		);
+		assert_eq!(negotiate(&compression, "br"), None);
+	}
+}
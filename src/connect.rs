@@ -0,0 +1,518 @@
+use std::{
+	fmt,
+	sync::{Arc, Mutex},
+};
+
+use futures::Stream;
+use hyper::{
+	body::{Bytes, HttpBody},
+	header::{HeaderName, HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE},
+	http::response::Parts,
+	Body, HeaderMap, Method, Request, Response,
+};
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor};
+use serde_json::{Map, Value as Json};
+
+use crate::{
+	adapter::{Adapted, Adapter, Converted},
+	grpc::{self, code, Envelopes, UnaryBody},
+	local::{LocalBody, Once},
+	BodyLimit, BoxedError,
+};
+
+/// Serves the gRPC service to clients of the [Connect protocol](https://connectrpc.com/docs/protocol)
+///
+/// Connect calls are `POST` requests to the path of a gRPC method. Unary calls
+/// send one message with the `application/proto` or `application/json`
+/// content-type, and streaming calls use `application/connect+proto` or
+/// `application/connect+json`. They are converted to gRPC calls, and sent to the
+/// gRPC inner service, on the gRPC [Branch][crate::Branch].
+///
+/// Unary requests also need the `connect-protocol-version` header, or a method
+/// known by the descriptors, so other JSON requests still go to the web service.
+/// The JSON codec needs the descriptors of the services, see
+/// [from_descriptor_set][ConnectProtocol::from_descriptor_set]. Without them, only
+/// the proto codec is served.
+///
+/// Failed unary calls get the HTTP status that matches their code, and a body like
+/// `{"code":"not_found","message":"no greeter"}`. Streams end with the Connect
+/// end-of-stream message. The `connect-timeout-ms` header is sent as
+/// `grpc-timeout`. Unary `GET` requests are not supported.
+///
+/// # Examples:
+/// ```
+/// use multiplex_tonic_hyper::{ConnectProtocol, Multiplexer};
+/// # use hyper::{service::service_fn, Body, Request, Response};
+/// # async fn handle(_: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+/// # 	Ok(Response::new(Body::empty()))
+/// # }
+/// # let grpc = service_fn(handle);
+/// # let web = service_fn(handle);
+/// use hello_world_tonic::hello_world::FILE_DESCRIPTOR_SET;
+///
+/// let connect = ConnectProtocol::from_descriptor_set(FILE_DESCRIPTOR_SET).unwrap();
+/// let multiplexer = Multiplexer::builder().connect(connect).build(grpc, web);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ConnectProtocol {
+	pool: Option<DescriptorPool>,
+}
+
+/// Error returned when the descriptors are invalid
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectError(String);
+
+impl fmt::Display for ConnectError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
+impl std::error::Error for ConnectError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Codec {
+	Proto,
+	Json,
+}
+
+impl Codec {
+	fn as_str(&self) -> &'static str {
+		match self {
+			Codec::Proto => "proto",
+			Codec::Json => "json",
+		}
+	}
+}
+
+/// Status of a request that could not be converted, shared with its body
+type ErrorSlot = Arc<Mutex<Option<(i32, String)>>>;
+
+impl ConnectProtocol {
+	/// Serve the proto codec, without descriptors
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Serve the proto and the JSON codecs, for the services in an encoded `FileDescriptorSet`
+	pub fn from_descriptor_set(bytes: &[u8]) -> Result<Self, ConnectError> {
+		let pool =
+			DescriptorPool::decode(bytes).map_err(|error| ConnectError(error.to_string()))?;
+		Ok(ConnectProtocol { pool: Some(pool) })
+	}
+}
+
+impl Adapter for ConnectProtocol {
+	/// Convert a Connect request to a gRPC request, buffering at most `limit` of unary bodies
+	fn adapt(
+		&self,
+		request: Request<Body>,
+		limit: Option<&BodyLimit>,
+	) -> Result<Converted, Request<Body>> {
+		if request.method() != Method::POST {
+			return Err(request);
+		}
+		let Some((service, method)) = grpc::split_path(request.uri().path()) else {
+			return Err(request);
+		};
+		let content_type = request
+			.headers()
+			.get(CONTENT_TYPE)
+			.and_then(|value| value.to_str().ok())
+			.and_then(|value| value.split(';').next())
+			.unwrap_or_default();
+		let (streaming, codec) = match content_type.trim() {
+			"application/proto" => (false, Codec::Proto),
+			"application/json" => (false, Codec::Json),
+			"application/connect+proto" => (true, Codec::Proto),
+			"application/connect+json" => (true, Codec::Json),
+			_ => return Err(request),
+		};
+		let descriptor = self.pool.as_ref().and_then(|pool| {
+			let service = pool.get_service_by_name(service)?;
+			let method = service.methods().find(|found| found.name() == method);
+			method
+		});
+		//Plain protobuf or JSON requests are for the web service
+		if !streaming
+			&& descriptor.is_none()
+			&& !request.headers().contains_key("connect-protocol-version")
+		{
+			return Err(request);
+		}
+		let json = match codec {
+			Codec::Json => match &descriptor {
+				Some(descriptor) => Some(descriptor),
+				None => return Err(request),
+			},
+			Codec::Proto => None,
+		};
+		let error = ErrorSlot::default();
+		let (mut parts, body) = request.into_parts();
+		let headers = &mut parts.headers;
+		let input = json.map(|descriptor| descriptor.input());
+		let body = if streaming {
+			rename(headers, "connect-content-encoding", "grpc-encoding");
+			rename(headers, "connect-accept-encoding", "grpc-accept-encoding");
+			match input {
+				Some(input) => Body::wrap_stream(json_requests(body, input, error.clone())),
+				None => body,
+			}
+		} else {
+			let encoding = headers
+				.remove(CONTENT_ENCODING)
+				.filter(|encoding| encoding != "identity");
+			let compressed = encoding.is_some();
+			if let Some(encoding) = encoding {
+				headers.insert("grpc-encoding", encoding);
+			}
+			let slot = error.clone();
+			let max_bytes = limit.map_or(u64::MAX, |limit| limit.max_bytes(parts.uri.path()));
+			Body::wrap_stream(futures::stream::once(async move {
+				let result = match grpc::read_limited(body, max_bytes).await {
+					Ok(body) => match input {
+						Some(_) if compressed => {
+							Err("compressed JSON messages are not supported".into())
+						}
+						Some(input) => json_to_proto(input, &body),
+						None => Ok(body.to_vec()),
+					}
+					.map_err(|error| (code::INVALID_ARGUMENT, error.to_string())),
+					Err(status) => Err(status),
+				};
+				result
+					.map(|message| grpc::encode_frame(compressed as u8, &message))
+					.map_err(|(code, message)| fail(&slot, code, message))
+			}))
+		};
+		if let Some(timeout) = headers.remove("connect-timeout-ms") {
+			let timeout = timeout.to_str().ok().and_then(|ms| ms.parse::<u64>().ok());
+			if let Some(ms) = timeout {
+				//grpc-timeout has at most 8 digits
+				let timeout = match ms {
+					0..=99_999_999 => format!("{ms}m"),
+					_ => format!("{}S", (ms / 1000).min(99_999_999)),
+				};
+				headers.insert("grpc-timeout", HeaderValue::from_str(&timeout).unwrap());
+			}
+		}
+		headers.remove(CONTENT_LENGTH);
+		headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+		headers.insert("te", HeaderValue::from_static("trailers"));
+		let call = ConnectCall {
+			codec,
+			streaming,
+			output: json.map(|descriptor| descriptor.output()),
+			error,
+			unary: Default::default(),
+			envelopes: Default::default(),
+		};
+		Ok((Request::from_parts(parts, body), Box::new(call)))
+	}
+}
+
+fn rename(headers: &mut HeaderMap, from: &'static str, to: &'static str) {
+	if let Some(value) = headers.remove(from) {
+		headers.insert(to, value);
+	}
+}
+
+/// Record the status of a request, to answer it instead of the gRPC service
+fn fail(slot: &ErrorSlot, code: i32, message: String) -> BoxedError {
+	let error = message.clone().into();
+	*slot.lock().unwrap() = Some((code, message));
+	error
+}
+
+/// Convert the JSON messages of a streaming request to protobuf
+fn json_requests(
+	body: Body,
+	input: MessageDescriptor,
+	slot: ErrorSlot,
+) -> impl Stream<Item = Result<Bytes, BoxedError>> {
+	futures::stream::unfold(Some((body, Envelopes::default())), move |state| {
+		let input = input.clone();
+		let slot = slot.clone();
+		async move {
+			let (mut body, mut envelopes) = state?;
+			loop {
+				if let Some(next) = envelopes.next() {
+					let result = match next {
+						Ok((0, message)) => json_to_proto(input, &message)
+							.map(|message| grpc::encode_frame(0, &message))
+							.map_err(|error| (code::INVALID_ARGUMENT, error.to_string())),
+						Ok(_) => Err((
+							code::INVALID_ARGUMENT,
+							"compressed JSON messages are not supported".into(),
+						)),
+						Err(status) => Err(status),
+					};
+					return match result {
+						Ok(frame) => Some((Ok(frame), Some((body, envelopes)))),
+						Err((code, message)) => Some((Err(fail(&slot, code, message)), None)),
+					};
+				}
+				match body.data().await {
+					Some(Ok(data)) => envelopes.push(&data),
+					Some(Err(error)) => return Some((Err(error.into()), None)),
+					None if envelopes.is_empty() => return None,
+					None => {
+						let error =
+							fail(&slot, code::INVALID_ARGUMENT, "incomplete message".into());
+						return Some((Err(error), None));
+					}
+				}
+			}
+		}
+	})
+}
+
+fn json_to_proto(input: MessageDescriptor, json: &[u8]) -> Result<Vec<u8>, BoxedError> {
+	let mut deserializer = serde_json::Deserializer::from_slice(json);
+	let message = DynamicMessage::deserialize(input, &mut deserializer)?;
+	deserializer.end()?;
+	Ok(message.encode_to_vec())
+}
+
+fn proto_to_json(output: &MessageDescriptor, message: &[u8]) -> Result<Vec<u8>, BoxedError> {
+	let message = DynamicMessage::decode(output.clone(), message)?;
+	Ok(serde_json::to_vec(&message)?)
+}
+
+/// Name of a status code in Connect errors
+fn code_name(code: i32) -> &'static str {
+	const NAMES: [&str; 17] = [
+		"ok",
+		"canceled",
+		"unknown",
+		"invalid_argument",
+		"deadline_exceeded",
+		"not_found",
+		"already_exists",
+		"permission_denied",
+		"resource_exhausted",
+		"failed_precondition",
+		"aborted",
+		"out_of_range",
+		"unimplemented",
+		"internal",
+		"unavailable",
+		"data_loss",
+		"unauthenticated",
+	];
+	usize::try_from(code)
+		.ok()
+		.and_then(|code| NAMES.get(code))
+		.unwrap_or(&"unknown")
+}
+
+fn error_json(code: i32, message: &str) -> Json {
+	let mut error = Map::new();
+	error.insert("code".into(), code_name(code).into());
+	if !message.is_empty() {
+		error.insert("message".into(), message.into());
+	}
+	Json::Object(error)
+}
+
+/// End-of-stream message, with the status and the metadata of the gRPC trailers
+fn end_stream(status: Option<(i32, String)>, trailers: Option<&HeaderMap>) -> Bytes {
+	let mut end = Map::new();
+	match status {
+		Some((code::OK, _)) => {}
+		Some((code, message)) => {
+			end.insert("error".into(), error_json(code, &message));
+		}
+		None => {
+			end.insert(
+				"error".into(),
+				error_json(2, "call ended without a gRPC status"),
+			);
+		}
+	}
+	let mut metadata = Map::new();
+	for (name, value) in trailers.into_iter().flatten() {
+		let Ok(value) = value.to_str() else { continue };
+		if name.as_str().starts_with("grpc-") {
+			continue;
+		}
+		let values = metadata
+			.entry(name.as_str())
+			.or_insert_with(|| Json::Array(Vec::new()));
+		if let Json::Array(values) = values {
+			values.push(value.into());
+		}
+	}
+	if !metadata.is_empty() {
+		end.insert("metadata".into(), Json::Object(metadata));
+	}
+	grpc::encode_frame(2, &serde_json::to_vec(&end).unwrap())
+}
+
+/// State of a Connect call, to convert its gRPC response
+struct ConnectCall {
+	codec: Codec,
+	streaming: bool,
+	//Descriptor of the response, with the JSON codec
+	output: Option<MessageDescriptor>,
+	error: ErrorSlot,
+	unary: UnaryBody,
+	envelopes: Envelopes,
+}
+
+impl ConnectCall {
+	fn content_type(&self) -> HeaderValue {
+		let prefix = if self.streaming {
+			"application/connect+"
+		} else {
+			"application/"
+		};
+		HeaderValue::from_str(&format!("{prefix}{}", self.codec.as_str())).unwrap()
+	}
+
+	/// Connect error that replaces a response without messages
+	fn error_response(&self, headers: &HeaderMap) -> Option<Response<LocalBody>> {
+		let (code, message) = match self.error.lock().unwrap().take() {
+			Some(status) => status,
+			None => match grpc::parse_status(headers)? {
+				(code::OK, _) => return None,
+				status => status,
+			},
+		};
+		let response = if self.streaming {
+			let mut response =
+				Response::new(Once::new(end_stream(Some((code, message)), None), None).boxed());
+			response
+				.headers_mut()
+				.insert(CONTENT_TYPE, self.content_type());
+			response
+		} else {
+			let body = error_json(code, &message).to_string();
+			let mut response = Response::new(Once::new(body, None).boxed());
+			*response.status_mut() = grpc::http_status(code);
+			response
+				.headers_mut()
+				.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+			response
+		};
+		Some(response)
+	}
+}
+
+impl Adapted for ConnectCall {
+	/// Replace the gRPC headers of a response with the Connect headers
+	fn head(&mut self, parts: &mut Parts) -> Option<Response<LocalBody>> {
+		//Failed calls have no messages
+		if let Some(error) = self.error_response(&parts.headers) {
+			return Some(error);
+		}
+		let headers = &mut parts.headers;
+		headers.remove(CONTENT_LENGTH);
+		headers.insert(CONTENT_TYPE, self.content_type());
+		let encoding = headers.remove("grpc-encoding");
+		headers.remove("grpc-accept-encoding");
+		if let (true, Some(encoding)) = (self.streaming, encoding) {
+			headers.insert(
+				HeaderName::from_static("connect-content-encoding"),
+				encoding,
+			);
+		}
+		None
+	}
+
+	fn data(&mut self, data: Bytes) -> Result<Option<Bytes>, BoxedError> {
+		if !self.streaming {
+			self.unary.push(&data)?;
+			return Ok(None);
+		}
+		let Some(output) = &self.output else {
+			return Ok(Some(data));
+		};
+		self.envelopes.push(&data);
+		let mut converted = Vec::new();
+		while let Some(next) = self.envelopes.next() {
+			match next {
+				Ok((0, message)) => {
+					let json = proto_to_json(output, &message)?;
+					converted.extend_from_slice(&grpc::encode_frame(0, &json));
+				}
+				Ok(_) => return Err("compressed JSON messages are not supported".into()),
+				Err((_, message)) => return Err(message.into()),
+			}
+		}
+		Ok((!converted.is_empty()).then(|| converted.into()))
+	}
+
+	fn end(&mut self, trailers: Option<&HeaderMap>) -> Result<Option<Bytes>, BoxedError> {
+		if self.streaming {
+			let status = trailers.and_then(grpc::parse_status);
+			return Ok(Some(end_stream(status, trailers)));
+		}
+		let message = self.unary.message()?;
+		match &self.output {
+			Some(output) => proto_to_json(output, &message).map(|json| Some(json.into())),
+			None => Ok(Some(message)),
+		}
+	}
+
+	fn buffered(&self) -> bool {
+		!self.streaming
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use hyper::HeaderMap;
+
+	use super::{code, code_name, end_stream, grpc::encode_frame, Envelopes};
+
+	#[test]
+	fn envelopes_wait_for_complete_messages() {
+		let mut envelopes = Envelopes::default();
+		let frame = encode_frame(0, b"abc");
+		envelopes.push(&frame[..4]);
+		assert_eq!(envelopes.next(), None);
+		envelopes.push(&frame[4..]);
+		envelopes.push(&encode_frame(1, b"")[..]);
+		assert_eq!(envelopes.next(), Some(Ok((0, b"abc".to_vec()))));
+		assert_eq!(envelopes.next(), Some(Ok((1, Vec::new()))));
+		assert!(envelopes.is_empty());
+	}
+
+	#[test]
+	fn envelopes_reject_large_messages() {
+		let mut envelopes = Envelopes::default();
+		envelopes.push(&[0, 0, 0x40, 0, 0]);
+		assert_eq!(envelopes.next(), None, "4 MiB is allowed");
+		let mut envelopes = Envelopes::default();
+		envelopes.push(&[0, 0xff, 0xff, 0xff, 0xff]);
+		let (status, _) = envelopes.next().unwrap().unwrap_err();
+		assert_eq!(status, code::RESOURCE_EXHAUSTED);
+	}
+
+	#[test]
+	fn end_stream_has_the_error_and_metadata() {
+		let mut trailers = HeaderMap::new();
+		trailers.insert("grpc-status", "5".parse().unwrap());
+		trailers.insert("x-request-id", "1".parse().unwrap());
+		let end = end_stream(Some((5, "no greeter".into())), Some(&trailers));
+		assert_eq!(end[0], 2);
+		let json: serde_json::Value = serde_json::from_slice(&end[5..]).unwrap();
+		assert_eq!(
+			json,
+			serde_json::json!({
+				"error": {"code": "not_found", "message": "no greeter"},
+				"metadata": {"x-request-id": ["1"]}
+			})
+		);
+		assert_eq!(&end_stream(Some((0, String::new())), None)[5..], b"{}");
+	}
+
+	#[test]
+	fn unknown_codes_are_unknown() {
+		assert_eq!(code_name(16), "unauthenticated");
+		assert_eq!(code_name(17), "unknown");
+		assert_eq!(code_name(-1), "unknown");
+	}
+}
@@ -0,0 +1,236 @@
+use hyper::{header::CONTENT_TYPE, HeaderMap, Response, StatusCode};
+
+use crate::{
+	local::{self, LocalBody},
+	Branch,
+};
+
+/// Which `content-type` values send a request to the gRPC branch of a [Multiplexer][crate::Multiplexer]
+///
+/// - [lenient][GrpcContentType::lenient], the default, accepts every value
+///   starting with `application/grpc`, including `application/grpc-web`.
+/// - [strict][GrpcContentType::strict] accepts `application/grpc` and
+///   `application/grpc+<codec>` only.
+/// - [codecs][GrpcContentType::codecs] accepts `application/grpc+<codec>` for
+///   the listed codecs, and `application/grpc` if `proto` is listed.
+///
+/// Strict and codecs modes parse the value as a media type, ignoring case,
+/// whitespace and parameters like `; charset=utf-8`. By default, the values
+/// they refuse go to the web branch. With [reject_mismatched][GrpcContentType::reject_mismatched],
+/// refused values starting with `application/grpc` get `415 Unsupported Media Type` instead.
+///
+/// # Examples:
+/// ```
+/// use multiplex_tonic_hyper::{GrpcContentType, Multiplexer};
+/// # use hyper::{service::service_fn, Body, Request, Response};
+/// # async fn handle(_: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+/// # 	Ok(Response::new(Body::empty()))
+/// # }
+/// # let grpc = service_fn(handle);
+/// # let web = service_fn(handle);
+///
+/// let multiplexer = Multiplexer::builder()
+/// 	.grpc_content_type(GrpcContentType::codecs(["proto", "json"]).reject_mismatched(true))
+/// 	.build(grpc, web);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GrpcContentType {
+	mode: Mode,
+	reject_mismatched: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Mode {
+	Lenient,
+	Strict,
+	Codecs(Vec<String>),
+}
+
+const GRPC: &str = "application/grpc";
+
+impl GrpcContentType {
+	/// Accept every value starting with `application/grpc`
+	pub fn lenient() -> Self {
+		Self::with_mode(Mode::Lenient)
+	}
+
+	/// Accept `application/grpc` and `application/grpc+<codec>` only
+	pub fn strict() -> Self {
+		Self::with_mode(Mode::Strict)
+	}
+
+	/// Accept `application/grpc+<codec>` for the listed codecs, like `proto` or `+json`
+	///
+	/// `application/grpc` is accepted when `proto` is listed, its default codec.
+	pub fn codecs(codecs: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+		let codecs = codecs
+			.into_iter()
+			.map(|codec| {
+				let codec = codec.as_ref().trim();
+				codec
+					.strip_prefix('+')
+					.unwrap_or(codec)
+					.to_ascii_lowercase()
+			})
+			.collect();
+		Self::with_mode(Mode::Codecs(codecs))
+	}
+
+	fn with_mode(mode: Mode) -> Self {
+		GrpcContentType {
+			mode,
+			reject_mismatched: false,
+		}
+	}
+
+	/// Reject the refused values that start with `application/grpc`, instead of sending them to the web branch
+	///
+	/// Has no effect on lenient mode, that accepts all of them.
+	pub fn reject_mismatched(mut self, reject_mismatched: bool) -> Self {
+		self.reject_mismatched = reject_mismatched;
+		self
+	}
+
+	/// Branch of the request, or `None` if it must be rejected
+	pub(crate) fn classify(&self, headers: &HeaderMap) -> Option<Branch> {
+		let Some(value) = headers.get(CONTENT_TYPE) else {
+			return Some(Branch::Web);
+		};
+		let codecs = match &self.mode {
+			Mode::Lenient if value.as_bytes().starts_with(GRPC.as_bytes()) => {
+				return Some(Branch::Grpc)
+			}
+			Mode::Lenient => return Some(Branch::Web),
+			Mode::Strict => None,
+			Mode::Codecs(codecs) => Some(codecs),
+		};
+		let Some(essence) = value.to_str().ok().and_then(essence) else {
+			return Some(Branch::Web);
+		};
+		let codec = match essence.strip_prefix(GRPC) {
+			Some("") => Some("proto"),
+			Some(suffix) => suffix.strip_prefix('+').filter(|codec| !codec.is_empty()),
+			None => return Some(Branch::Web),
+		};
+		let accepted = match (codec, codecs) {
+			(Some(_), None) => true,
+			(Some(codec), Some(codecs)) => codecs.iter().any(|allowed| allowed == codec),
+			(None, _) => false,
+		};
+		match accepted {
+			true => Some(Branch::Grpc),
+			false if self.reject_mismatched => None,
+			false => Some(Branch::Web),
+		}
+	}
+}
+
+impl Default for GrpcContentType {
+	fn default() -> Self {
+		Self::lenient()
+	}
+}
+
+/// Lowercase `type/subtype` of a media type, without its parameters
+fn essence(value: &str) -> Option<String> {
+	let essence = value.split(';').next()?.trim();
+	let (kind, subtype) = essence.split_once('/')?;
+	let is_token = |part: &str| {
+		!part.is_empty()
+			&& part
+				.bytes()
+				.all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+	};
+	(is_token(kind) && is_token(subtype)).then(|| essence.to_ascii_lowercase())
+}
+
+/// Response to a request with a refused gRPC content type
+pub(crate) fn unsupported() -> Response<LocalBody> {
+	local::text(
+		StatusCode::UNSUPPORTED_MEDIA_TYPE,
+		"unsupported gRPC content type\n",
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use hyper::{header::CONTENT_TYPE, HeaderMap};
+
+	use super::GrpcContentType;
+	use crate::Branch;
+
+	fn classify(content_type: &GrpcContentType, value: &str) -> Option<Branch> {
+		let mut headers = HeaderMap::new();
+		headers.insert(CONTENT_TYPE, value.parse().unwrap());
+		content_type.classify(&headers)
+	}
+
+	#[test]
+	fn lenient_mode_matches_the_prefix() {
+		let lenient = GrpcContentType::default();
+		for value in [
+			"application/grpc",
+			"application/grpcfoo",
+			"application/grpc-web",
+		] {
+			assert_eq!(classify(&lenient, value), Some(Branch::Grpc), "{value}");
+		}
+		for value in ["Application/gRPC", "text/html"] {
+			assert_eq!(classify(&lenient, value), Some(Branch::Web), "{value}");
+		}
+		assert_eq!(lenient.classify(&HeaderMap::new()), Some(Branch::Web));
+	}
+
+	#[test]
+	fn strict_mode_parses_the_media_type() {
+		let strict = GrpcContentType::strict();
+		for value in [
+			"application/grpc",
+			"Application/GRPC+Proto",
+			" application/grpc+json ; charset=utf-8",
+		] {
+			assert_eq!(classify(&strict, value), Some(Branch::Grpc), "{value}");
+		}
+		for value in [
+			"application/grpcfoo",
+			"application/grpc-web",
+			"application/grpc+",
+			"application/grpc/x",
+			"text/html",
+		] {
+			assert_eq!(classify(&strict, value), Some(Branch::Web), "{value}");
+		}
+	}
+
+	#[test]
+	fn codecs_mode_accepts_the_listed_codecs() {
+		let codecs = GrpcContentType::codecs(["+json"]);
+		assert_eq!(
+			classify(&codecs, "application/grpc+JSON"),
+			Some(Branch::Grpc)
+		);
+		assert_eq!(
+			classify(&codecs, "application/grpc+proto"),
+			Some(Branch::Web)
+		);
+		assert_eq!(classify(&codecs, "application/grpc"), Some(Branch::Web));
+
+		let codecs = GrpcContentType::codecs(["Proto"]);
+		assert_eq!(classify(&codecs, "application/grpc"), Some(Branch::Grpc));
+		assert_eq!(
+			classify(&codecs, "application/grpc+proto"),
+			Some(Branch::Grpc)
+		);
+	}
+
+	#[test]
+	fn mismatched_values_can_be_rejected() {
+		let strict = GrpcContentType::strict().reject_mismatched(true);
+		assert_eq!(classify(&strict, "application/grpc-web"), None);
+		assert_eq!(classify(&strict, "application/grpcfoo"), None);
+		assert_eq!(classify(&strict, "application/json"), Some(Branch::Web));
+
+		let lenient = GrpcContentType::lenient().reject_mismatched(true);
+		assert_eq!(classify(&lenient, "Application/gRPC"), Some(Branch::Web));
+	}
+}
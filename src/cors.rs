@@ -0,0 +1,323 @@
+use std::time::Duration;
+
+use hyper::{
+	header::{
+		HeaderName, HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+		ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
+		ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD,
+		ORIGIN, VARY,
+	},
+	Body, Method, Request, Response, StatusCode,
+};
+
+use crate::{
+	builder::Config,
+	local::{LocalBody, Once},
+	Branch,
+};
+
+/// Headers added to the response of a request
+pub(crate) type ResponseHeaders = Vec<(HeaderName, HeaderValue)>;
+
+/// CORS policy of one branch of a [Multiplexer][crate::Multiplexer]
+///
+/// Preflight requests are answered by the Multiplexer, with the policy of the
+/// branch they target. Browsers don't send the content-type in preflights, so
+/// those that request the `x-grpc-web` header go to the gRPC branch, and the
+/// others to the web branch. Without a policy for that branch, the preflight
+/// is sent to its inner service.
+///
+/// Responses to requests from an allowed origin get the `access-control-*`
+/// headers of the policy, on both branches.
+///
+/// # Examples:
+/// ```
+/// use multiplex_tonic_hyper::{Branch, CorsPolicy, Multiplexer};
+/// # use hyper::{service::service_fn, Body, Request, Response};
+/// # async fn handle(_: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+/// # 	Ok(Response::new(Body::empty()))
+/// # }
+/// # let grpc = service_fn(handle);
+/// # let web = service_fn(handle);
+///
+/// let multiplexer = Multiplexer::builder()
+/// 	.cors(
+/// 		Branch::Grpc,
+/// 		CorsPolicy::grpc_web().allow_origin("https://app.example.com"),
+/// 	)
+/// 	.cors(Branch::Web, CorsPolicy::new().allow_any_origin())
+/// 	.build(grpc, web);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CorsPolicy {
+	any_origin: bool,
+	origins: Vec<HeaderValue>,
+	methods: Vec<Method>,
+	//None mirrors the headers requested by the preflight
+	allow_headers: Option<Vec<HeaderName>>,
+	expose_headers: Vec<HeaderName>,
+	allow_credentials: bool,
+	max_age: Option<Duration>,
+}
+
+impl Default for CorsPolicy {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl CorsPolicy {
+	/// Policy that allows no origin, with the `GET`, `HEAD` and `POST` methods and any request header
+	pub fn new() -> Self {
+		CorsPolicy {
+			any_origin: false,
+			origins: Vec::new(),
+			methods: vec![Method::GET, Method::HEAD, Method::POST],
+			allow_headers: None,
+			expose_headers: Vec::new(),
+			allow_credentials: false,
+			max_age: None,
+		}
+	}
+
+	/// Policy for gRPC-Web clients, that allows no origin, with the `POST`
+	/// method, and that exposes the gRPC status headers
+	pub fn grpc_web() -> Self {
+		CorsPolicy {
+			methods: vec![Method::POST],
+			expose_headers: ["grpc-status", "grpc-message", "grpc-status-details-bin"]
+				.into_iter()
+				.map(HeaderName::from_static)
+				.collect(),
+			..Self::new()
+		}
+	}
+
+	/// Allow requests from any origin
+	pub fn allow_any_origin(mut self) -> Self {
+		self.any_origin = true;
+		self
+	}
+
+	/// Allow requests from `origin`, like `https://example.com`
+	///
+	/// # Panics
+	/// If `origin` is not a valid header value.
+	pub fn allow_origin(mut self, origin: &str) -> Self {
+		self.origins
+			.push(HeaderValue::from_str(origin).expect("invalid origin"));
+		self
+	}
+
+	/// Methods allowed in preflights
+	pub fn allow_methods(mut self, methods: impl IntoIterator<Item = Method>) -> Self {
+		self.methods = methods.into_iter().collect();
+		self
+	}
+
+	/// Request headers allowed in preflights, instead of any requested header
+	pub fn allow_headers(mut self, headers: impl IntoIterator<Item = HeaderName>) -> Self {
+		self.allow_headers = Some(headers.into_iter().collect());
+		self
+	}
+
+	/// Response headers the browser exposes to the client
+	pub fn expose_headers(mut self, headers: impl IntoIterator<Item = HeaderName>) -> Self {
+		self.expose_headers.extend(headers);
+		self
+	}
+
+	/// Allow requests with credentials, like cookies
+	pub fn allow_credentials(mut self, allow_credentials: bool) -> Self {
+		self.allow_credentials = allow_credentials;
+		self
+	}
+
+	/// Time browsers may cache the result of a preflight
+	pub fn max_age(mut self, max_age: Duration) -> Self {
+		self.max_age = Some(max_age);
+		self
+	}
+
+	/// Value of `access-control-allow-origin`, if the origin is allowed
+	fn allowed_origin(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+		if self.any_origin && !self.allow_credentials {
+			Some(HeaderValue::from_static("*"))
+		} else if self.any_origin || self.origins.contains(origin) {
+			Some(origin.clone())
+		} else {
+			None
+		}
+	}
+
+	/// Headers common to preflights and other responses
+	fn origin_headers(&self, origin: &HeaderValue) -> Option<ResponseHeaders> {
+		let mut headers = vec![
+			(VARY, HeaderValue::from_static("origin")),
+			(ACCESS_CONTROL_ALLOW_ORIGIN, self.allowed_origin(origin)?),
+		];
+		if self.allow_credentials {
+			headers.push((
+				ACCESS_CONTROL_ALLOW_CREDENTIALS,
+				HeaderValue::from_static("true"),
+			));
+		}
+		Some(headers)
+	}
+
+	/// Headers added to the response of a request from another origin
+	pub(crate) fn response_headers(&self, request: &Request<Body>) -> Option<ResponseHeaders> {
+		let mut headers = self.origin_headers(request.headers().get(ORIGIN)?)?;
+		if !self.expose_headers.is_empty() {
+			headers.push((ACCESS_CONTROL_EXPOSE_HEADERS, join(&self.expose_headers)));
+		}
+		Some(headers)
+	}
+
+	fn preflight(&self, request: &Request<Body>) -> Response<LocalBody> {
+		let mut response = Response::new(Once::new("", None).boxed());
+		*response.status_mut() = StatusCode::NO_CONTENT;
+		let response_headers = response.headers_mut();
+		response_headers.insert(
+			VARY,
+			HeaderValue::from_static(
+				"origin, access-control-request-method, access-control-request-headers",
+			),
+		);
+		let headers = request.headers();
+		let method_allowed = headers
+			.get(ACCESS_CONTROL_REQUEST_METHOD)
+			.and_then(|method| Method::from_bytes(method.as_bytes()).ok())
+			.is_some_and(|method| self.methods.contains(&method));
+		let origin = headers.get(ORIGIN);
+		let origin_headers = origin.and_then(|origin| self.origin_headers(origin));
+		//Without the allow headers, the browser fails the request
+		let (true, Some(origin_headers)) = (method_allowed, origin_headers) else {
+			return response;
+		};
+		for (name, value) in origin_headers {
+			if name != VARY {
+				response_headers.insert(name, value);
+			}
+		}
+		response_headers.insert(ACCESS_CONTROL_ALLOW_METHODS, join(&self.methods));
+		let allow_headers = match &self.allow_headers {
+			Some(allow_headers) => Some(join(allow_headers)),
+			None => headers.get(ACCESS_CONTROL_REQUEST_HEADERS).cloned(),
+		};
+		if let Some(allow_headers) = allow_headers {
+			response_headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, allow_headers);
+		}
+		if let Some(max_age) = self.max_age {
+			response_headers.insert(ACCESS_CONTROL_MAX_AGE, max_age.as_secs().into());
+		}
+		response
+	}
+}
+
+fn join<T: AsRef<str>>(items: &[T]) -> HeaderValue {
+	let joined: Vec<&str> = items.iter().map(AsRef::as_ref).collect();
+	HeaderValue::from_str(&joined.join(", ")).unwrap()
+}
+
+/// Branch and response of a preflight answered by the multiplexer
+pub(crate) fn preflight(
+	config: &Config,
+	request: &Request<Body>,
+) -> Option<(Branch, Response<LocalBody>)> {
+	let headers = request.headers();
+	if request.method() != Method::OPTIONS
+		|| !headers.contains_key(ORIGIN)
+		|| !headers.contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+	{
+		return None;
+	}
+	let grpc_web = headers
+		.get_all(ACCESS_CONTROL_REQUEST_HEADERS)
+		.iter()
+		.filter_map(|value| value.to_str().ok())
+		.flat_map(|value| value.split(','))
+		.any(|header| header.trim().eq_ignore_ascii_case("x-grpc-web"));
+	let branch = if grpc_web { Branch::Grpc } else { Branch::Web };
+	let policy = config.cors(branch)?;
+	Some((branch, policy.preflight(request)))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use hyper::{Body, Request};
+
+	use super::CorsPolicy;
+
+	fn preflight(origin: &str, method: &str) -> Request<Body> {
+		Request::options("/helloworld.Greeter/SayHello")
+			.header("origin", origin)
+			.header("access-control-request-method", method)
+			.header("access-control-request-headers", "content-type, x-grpc-web")
+			.body(Body::empty())
+			.unwrap()
+	}
+
+	#[test]
+	fn preflight_from_allowed_origin() {
+		let policy = CorsPolicy::grpc_web()
+			.allow_origin("https://example.com")
+			.max_age(Duration::from_secs(600));
+		let response = policy.preflight(&preflight("https://example.com", "POST"));
+		let headers = response.headers();
+		assert_eq!(response.status(), 204);
+		assert_eq!(
+			headers["access-control-allow-origin"],
+			"https://example.com"
+		);
+		assert_eq!(headers["access-control-allow-methods"], "POST");
+		assert_eq!(
+			headers["access-control-allow-headers"],
+			"content-type, x-grpc-web"
+		);
+		assert_eq!(headers["access-control-max-age"], "600");
+	}
+
+	#[test]
+	fn preflight_without_allow_headers_when_rejected() {
+		let policy = CorsPolicy::grpc_web().allow_origin("https://example.com");
+		for request in [
+			preflight("https://other.com", "POST"),
+			preflight("https://example.com", "DELETE"),
+		] {
+			let response = policy.preflight(&request);
+			assert_eq!(response.status(), 204);
+			assert!(response
+				.headers()
+				.get("access-control-allow-origin")
+				.is_none());
+		}
+	}
+
+	#[test]
+	fn credentials_echo_the_origin() {
+		let request = Request::post("/")
+			.header("origin", "https://example.com")
+			.body(Body::empty())
+			.unwrap();
+		let any = CorsPolicy::new().allow_any_origin();
+		let headers = any.response_headers(&request).unwrap();
+		assert!(headers.contains(&(
+			"access-control-allow-origin".parse().unwrap(),
+			"*".parse().unwrap()
+		)));
+
+		let credentials = any.allow_credentials(true);
+		let headers = credentials.response_headers(&request).unwrap();
+		assert!(headers.contains(&(
+			"access-control-allow-origin".parse().unwrap(),
+			"https://example.com".parse().unwrap()
+		)));
+		assert!(headers.contains(&(
+			"access-control-allow-credentials".parse().unwrap(),
+			"true".parse().unwrap()
+		)));
+	}
+}
@@ -0,0 +1,311 @@
+use std::{
+	future::Future,
+	pin::Pin,
+	sync::{
+		atomic::{AtomicBool, AtomicUsize, Ordering},
+		Arc,
+	},
+	task::{Context, Poll},
+};
+
+use futures::{
+	future::{BoxFuture, Either},
+	FutureExt,
+};
+use hyper::{
+	header::{HeaderValue, CONNECTION},
+	Body, Request, Response, StatusCode, Version,
+};
+use tokio::sync::Notify;
+
+use crate::{
+	grpc::{self, code},
+	local::{self, LocalBody},
+	BoxedError, Branch,
+};
+
+/// Stop every [Multiplexer][crate::Multiplexer] built with it from taking new requests
+///
+/// Enabled with [Builder::drain][crate::Builder::drain]. After [drain][DrainHandle::drain]
+/// is called, new gRPC calls get the `UNAVAILABLE` status, and new web requests
+/// get `503 Service Unavailable` with `Connection: close`, without calling the
+/// inner services. Requests that were already accepted keep running until
+/// their response body ends.
+///
+/// # Examples:
+/// ```
+/// # async fn run() {
+/// use std::time::Duration;
+/// use multiplex_tonic_hyper::{DrainHandle, Multiplexer};
+/// use tower::make::Shared;
+/// # use hyper::{service::service_fn, Body, Request, Response};
+/// # async fn handle(_: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+/// # 	Ok(Response::new(Body::empty()))
+/// # }
+/// # let grpc = service_fn(handle);
+/// # let web = service_fn(handle);
+///
+/// let drain = DrainHandle::new();
+/// let make_multiplexer = Multiplexer::builder()
+/// 	.drain(drain.clone())
+/// 	.build_make(Shared::new(grpc), Shared::new(web));
+///
+/// //On shutdown, wait up to 30 seconds for in-flight requests
+/// let deadline = tokio::time::sleep(Duration::from_secs(30));
+/// let drained = drain.shutdown(deadline).await;
+/// # assert!(drained);
+/// # }
+/// # tokio_test::block_on(run());
+/// ```
+#[derive(Clone, Default)]
+pub struct DrainHandle {
+	shared: Arc<Shared>,
+}
+
+#[derive(Default)]
+struct Shared {
+	draining: AtomicBool,
+	aborted: AtomicBool,
+	in_flight: AtomicUsize,
+	//Notified when the state changes
+	changed: Notify,
+}
+
+impl Shared {
+	/// Resolve once `done` returns true, checking it each time the state changes
+	async fn wait(self: Arc<Self>, done: fn(&Shared) -> bool) {
+		loop {
+			//Created before the check, so it is woken by any later change
+			let changed = self.changed.notified();
+			if done(&self) {
+				return;
+			}
+			changed.await;
+		}
+	}
+}
+
+impl DrainHandle {
+	/// Handle that is not draining
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Reject new requests, and return a future that resolves when there are no
+	/// requests in flight
+	pub fn drain(&self) -> Drained {
+		if !self.shared.draining.swap(true, Ordering::AcqRel) {
+			self.shared.changed.notify_waiters();
+		}
+		let shared = self.shared.clone();
+		Drained {
+			wait: shared
+				.wait(|shared| shared.in_flight.load(Ordering::Acquire) == 0)
+				.boxed(),
+		}
+	}
+
+	/// If new requests are being rejected
+	pub fn is_draining(&self) -> bool {
+		self.shared.draining.load(Ordering::Acquire)
+	}
+
+	/// Number of requests accepted, whose response has not ended yet
+	pub fn in_flight(&self) -> usize {
+		self.shared.in_flight.load(Ordering::Acquire)
+	}
+
+	/// Fail every request still in flight
+	///
+	/// Their futures and bodies return an error, so the server resets their streams.
+	pub fn abort(&self) {
+		self.shared.draining.store(true, Ordering::Release);
+		self.shared.aborted.store(true, Ordering::Release);
+		self.shared.changed.notify_waiters();
+	}
+
+	/// Drain, and abort the requests still in flight when `deadline` resolves
+	///
+	/// Returns `true` if all requests ended before the deadline.
+	pub async fn shutdown<D: Future<Output = ()>>(&self, deadline: D) -> bool {
+		let drained = self.drain();
+		futures::pin_mut!(deadline);
+		match futures::future::select(drained, deadline).await {
+			Either::Left(_) => true,
+			Either::Right(_) => {
+				self.abort();
+				false
+			}
+		}
+	}
+
+	/// Answer the request if draining, otherwise count it as in flight
+	#[allow(clippy::result_large_err)]
+	pub(crate) fn accept(
+		&self,
+		branch: Branch,
+		request: &Request<Body>,
+	) -> Result<InFlight, Response<LocalBody>> {
+		self.shared.in_flight.fetch_add(1, Ordering::AcqRel);
+		let in_flight = InFlight {
+			shared: self.shared.clone(),
+			aborted: None,
+		};
+		//Checked after counting, so `drained` never misses an accepted request
+		if !self.is_draining() {
+			return Ok(in_flight);
+		}
+		drop(in_flight);
+		Err(match branch {
+			Branch::Grpc => grpc::status_response(code::UNAVAILABLE, "server is shutting down"),
+			Branch::Web => {
+				let mut response =
+					local::text(StatusCode::SERVICE_UNAVAILABLE, "server is shutting down\n");
+				//Connection headers are not allowed in HTTP/2
+				if request.version() <= Version::HTTP_11 {
+					response
+						.headers_mut()
+						.insert(CONNECTION, HeaderValue::from_static("close"));
+				}
+				response
+			}
+		})
+	}
+}
+
+/// Guard of a request accepted by a draining [Multiplexer][crate::Multiplexer]
+pub(crate) struct InFlight {
+	shared: Arc<Shared>,
+	//Only created once the request is polled
+	aborted: Option<BoxFuture<'static, ()>>,
+}
+
+impl InFlight {
+	/// Error to return if the request was aborted, otherwise wake the task on abort
+	pub(crate) fn poll_aborted(&mut self, cx: &mut Context<'_>) -> Option<BoxedError> {
+		if !self.shared.aborted.load(Ordering::Acquire) {
+			let shared = &self.shared;
+			let aborted = self.aborted.get_or_insert_with(|| {
+				shared
+					.clone()
+					.wait(|shared| shared.aborted.load(Ordering::Acquire))
+					.boxed()
+			});
+			if aborted.poll_unpin(cx).is_pending() {
+				return None;
+			}
+		}
+		Some("request aborted after the drain deadline".into())
+	}
+}
+
+impl Drop for InFlight {
+	fn drop(&mut self) {
+		let previous = self.shared.in_flight.fetch_sub(1, Ordering::AcqRel);
+		if previous == 1 && self.shared.draining.load(Ordering::Acquire) {
+			self.shared.changed.notify_waiters();
+		}
+	}
+}
+
+/// Future returned by [DrainHandle::drain], that resolves when there are no requests in flight
+pub struct Drained {
+	wait: BoxFuture<'static, ()>,
+}
+
+impl Future for Drained {
+	type Output = ();
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		self.wait.poll_unpin(cx)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	};
+
+	use futures::{task::ArcWake, FutureExt};
+	use hyper::{Body, Request, StatusCode, Version};
+
+	use super::DrainHandle;
+	use crate::Branch;
+
+	fn request(version: Version) -> Request<Body> {
+		Request::builder()
+			.version(version)
+			.body(Body::empty())
+			.unwrap()
+	}
+
+	#[test]
+	fn counts_requests_until_drained() {
+		let drain = DrainHandle::new();
+		let first = drain.accept(Branch::Grpc, &request(Version::HTTP_2));
+		let second = drain.accept(Branch::Web, &request(Version::HTTP_11));
+		assert_eq!(drain.in_flight(), 2);
+
+		let mut drained = drain.drain();
+		assert!((&mut drained).now_or_never().is_none());
+		drop(first);
+		assert!((&mut drained).now_or_never().is_none());
+		drop(second);
+		assert!(drained.now_or_never().is_some());
+	}
+
+	#[test]
+	fn rejects_requests_while_draining() {
+		let drain = DrainHandle::new();
+		let _drained = drain.drain();
+		assert!(drain.is_draining());
+
+		let grpc = drain
+			.accept(Branch::Grpc, &request(Version::HTTP_2))
+			.err()
+			.unwrap();
+		assert_eq!(grpc.headers()["grpc-status"], "14");
+		let web = drain
+			.accept(Branch::Web, &request(Version::HTTP_11))
+			.err()
+			.unwrap();
+		assert_eq!(web.status(), StatusCode::SERVICE_UNAVAILABLE);
+		assert_eq!(web.headers()["connection"], "close");
+		let web_h2 = drain
+			.accept(Branch::Web, &request(Version::HTTP_2))
+			.err()
+			.unwrap();
+		assert!(web_h2.headers().get("connection").is_none());
+		assert_eq!(drain.in_flight(), 0);
+	}
+
+	#[test]
+	fn abort_fails_requests_in_flight() {
+		let drain = DrainHandle::new();
+		let mut in_flight = drain
+			.accept(Branch::Web, &request(Version::HTTP_11))
+			.ok()
+			.unwrap();
+		let woken = Arc::new(Woken::default());
+		let waker = futures::task::waker(woken.clone());
+		let mut cx = std::task::Context::from_waker(&waker);
+		assert!(in_flight.poll_aborted(&mut cx).is_none());
+		drain.abort();
+		assert!(
+			woken.0.load(Ordering::Acquire),
+			"the task is woken on abort"
+		);
+		assert!(in_flight.poll_aborted(&mut cx).is_some());
+	}
+
+	#[derive(Default)]
+	struct Woken(AtomicBool);
+
+	impl ArcWake for Woken {
+		fn wake_by_ref(arc_self: &Arc<Self>) {
+			arc_self.0.store(true, Ordering::Release);
+		}
+	}
+}
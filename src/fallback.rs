@@ -0,0 +1,469 @@
+use std::{
+	fmt,
+	future::Future,
+	pin::Pin,
+	sync::{Arc, Mutex},
+	task::{ready, Context, Poll},
+};
+
+use futures::{stream, StreamExt};
+use hyper::{
+	body::{Bytes, HttpBody, SizeHint},
+	http::Extensions,
+	Body, HeaderMap, Method, Request, Response, Uri, Version,
+};
+use pin_project::pin_project;
+use tower::Service;
+
+use crate::{grpc::code, to_boxed, BoxedError, EncapsulatedBody};
+
+/// Service that replays gRPC calls to a fallback service when the gRPC service returns `UNIMPLEMENTED`
+///
+/// Use it as the gRPC service of a [Multiplexer][crate::Multiplexer] while
+/// migrating methods from a legacy handler, usually the web service, that also
+/// speaks gRPC framing. When the gRPC service answers with `grpc-status: 12` in
+/// a trailers-only response, the request is sent again to the fallback, and the
+/// client gets the response of the fallback.
+///
+/// The gRPC service gets the request body as a [RecordedBody], that records the
+/// data it reads, with the trailers of the request. Calls are replayed only if the
+/// gRPC service read at most [max_buffer][GrpcFallback::max_buffer] bytes before
+/// answering, the default is 64 KiB. The replayed body has the recorded data
+/// followed by the data the gRPC service did not read, without trailers.
+///
+/// The fallback service is cloned for each call, and only polled for
+/// readiness when the call is replayed. Request extensions can't be cloned in
+/// general, the replay only has the ones registered with
+/// [replay_extension][GrpcFallback::replay_extension].
+///
+/// In a Multiplexer, replayed calls are still on the gRPC branch: tracing, metrics
+/// and access logs attribute them to `branch=grpc`, with the status of the fallback.
+/// With the `tracing` feature, the replay is recorded as an event of the request span.
+///
+/// # Examples:
+/// ```
+/// use multiplex_tonic_hyper::{GrpcFallback, Multiplexer};
+/// # use hyper::{service::service_fn, Body, Request, Response};
+/// # async fn handle(_: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+/// # 	Ok(Response::new(Body::empty()))
+/// # }
+/// # let web = service_fn(handle);
+/// use hello_world_tonic::{hello_world::greeter_server::GreeterServer, server::MyGreeter};
+///
+/// let grpc = GreeterServer::new(MyGreeter::default());
+/// let multiplexer = Multiplexer::new(GrpcFallback::new(grpc, web.clone()), web);
+/// ```
+#[derive(Clone, Debug)]
+pub struct GrpcFallback<Grpc, Fallback> {
+	grpc: Grpc,
+	fallback: Fallback,
+	max_buffer: usize,
+	//Copy one type of extension from a request to its replay
+	extensions: Vec<fn(&Extensions, &mut Extensions)>,
+}
+
+impl<Grpc, Fallback> GrpcFallback<Grpc, Fallback> {
+	/// Send calls to `grpc`, replaying the unimplemented ones to `fallback`
+	pub fn new(grpc: Grpc, fallback: Fallback) -> Self {
+		GrpcFallback {
+			grpc,
+			fallback,
+			max_buffer: 64 * 1024,
+			extensions: Vec::new(),
+		}
+	}
+
+	/// Record up to `max_buffer` bytes of each request body to replay it
+	pub fn max_buffer(mut self, max_buffer: usize) -> Self {
+		self.max_buffer = max_buffer;
+		self
+	}
+
+	/// Copy the request extension of type `T`, if any, to the replayed request
+	pub fn replay_extension<T: Clone + Send + Sync + 'static>(mut self) -> Self {
+		self.extensions.push(|from, to| {
+			if let Some(extension) = from.get::<T>() {
+				to.insert(extension.clone());
+			}
+		});
+		self
+	}
+}
+
+impl<Grpc, Fallback, GrpcBody, FallbackBody> Service<Request<Body>> for GrpcFallback<Grpc, Fallback>
+where
+	Grpc: Service<Request<RecordedBody>, Response = Response<GrpcBody>>,
+	Fallback: Service<Request<Body>, Response = Response<FallbackBody>> + Clone,
+	Grpc::Error: Into<BoxedError>,
+	Fallback::Error: Into<BoxedError>,
+{
+	type Response = Response<EncapsulatedBody<GrpcBody, FallbackBody>>;
+	type Error = BoxedError;
+	type Future = GrpcFallbackFuture<Grpc::Future, Fallback>;
+
+	///Only the gRPC service is polled, the fallback is polled when a call is replayed
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		self.grpc.poll_ready(cx).map_err(to_boxed)
+	}
+
+	fn call(&mut self, req: Request<Body>) -> Self::Future {
+		let recorder = Arc::new(Mutex::new(Recorder {
+			source: None,
+			chunks: Vec::new(),
+			len: 0,
+			max_len: self.max_buffer,
+			overflowed: false,
+		}));
+		let req = req.map(|body| {
+			recorder.lock().unwrap().source = Some(body);
+			RecordedBody(recorder.clone())
+		});
+		let mut replay = Replay {
+			method: req.method().clone(),
+			uri: req.uri().clone(),
+			version: req.version(),
+			headers: req.headers().clone(),
+			extensions: Extensions::new(),
+			recorder,
+		};
+		for copy in &self.extensions {
+			copy(req.extensions(), &mut replay.extensions);
+		}
+		GrpcFallbackFuture {
+			state: State::Grpc {
+				future: self.grpc.call(req),
+				fallback: Some((self.fallback.clone(), replay)),
+			},
+		}
+	}
+}
+
+/// Future of [GrpcFallback]
+#[pin_project]
+pub struct GrpcFallbackFuture<GrpcFuture, Fallback>
+where
+	Fallback: Service<Request<Body>>,
+{
+	#[pin]
+	state: State<GrpcFuture, Fallback>,
+}
+
+#[pin_project(project = StateProj)]
+enum State<GrpcFuture, Fallback>
+where
+	Fallback: Service<Request<Body>>,
+{
+	Grpc {
+		#[pin]
+		future: GrpcFuture,
+		fallback: Option<(Fallback, Replay)>,
+	},
+	Ready {
+		fallback: Fallback,
+		request: Option<Request<Body>>,
+	},
+	Fallback {
+		#[pin]
+		future: Fallback::Future,
+	},
+}
+
+impl<GrpcFuture, Fallback, GrpcError, GrpcBody, FallbackBody> Future
+	for GrpcFallbackFuture<GrpcFuture, Fallback>
+where
+	GrpcFuture: Future<Output = Result<Response<GrpcBody>, GrpcError>>,
+	GrpcError: Into<BoxedError>,
+	Fallback: Service<Request<Body>, Response = Response<FallbackBody>>,
+	Fallback::Error: Into<BoxedError>,
+{
+	type Output = Result<Response<EncapsulatedBody<GrpcBody, FallbackBody>>, BoxedError>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		loop {
+			let mut state = self.as_mut().project().state;
+			let next = match state.as_mut().project() {
+				StateProj::Grpc { future, fallback } => {
+					let response = ready!(future.poll(cx)).map_err(to_boxed)?;
+					let replay = match fallback.take() {
+						Some((fallback, replay)) if is_unimplemented(response.headers()) => {
+							replay.request().map(|request| (fallback, request))
+						}
+						_ => None,
+					};
+					match replay {
+						Some((fallback, request)) => State::Ready {
+							fallback,
+							request: Some(request),
+						},
+						None => return Poll::Ready(Ok(response.map(EncapsulatedBody::Grpc))),
+					}
+				}
+				StateProj::Ready { fallback, request } => {
+					ready!(fallback.poll_ready(cx)).map_err(to_boxed)?;
+					let request = request
+						.take()
+						.expect("polled after the fallback was called");
+					State::Fallback {
+						future: fallback.call(request),
+					}
+				}
+				StateProj::Fallback { future } => {
+					let response = ready!(future.poll(cx)).map_err(to_boxed)?;
+					return Poll::Ready(Ok(response.map(EncapsulatedBody::Web)));
+				}
+			};
+			state.set(next);
+		}
+	}
+}
+
+/// If the headers of a trailers-only response have the `UNIMPLEMENTED` status
+fn is_unimplemented(headers: &HeaderMap) -> bool {
+	headers
+		.get("grpc-status")
+		.and_then(|status| status.to_str().ok())
+		.and_then(|status| status.trim().parse::<i32>().ok())
+		== Some(code::UNIMPLEMENTED)
+}
+
+/// Request body shared by the gRPC service and a possible replay
+struct Recorder {
+	source: Option<Body>,
+	chunks: Vec<Bytes>,
+	len: usize,
+	max_len: usize,
+	overflowed: bool,
+}
+
+impl Recorder {
+	fn record(&mut self, data: &Bytes) {
+		if self.overflowed {
+			return;
+		}
+		self.len += data.len();
+		if self.len > self.max_len {
+			self.overflowed = true;
+			self.chunks = Vec::new();
+		} else {
+			self.chunks.push(data.clone());
+		}
+	}
+}
+
+/// Request body given to the gRPC service of a [GrpcFallback], recording the data it reads
+///
+/// It has the trailers and the size hint of the request body, and ends early
+/// once the call is replayed.
+pub struct RecordedBody(Arc<Mutex<Recorder>>);
+
+impl HttpBody for RecordedBody {
+	type Data = Bytes;
+	type Error = hyper::Error;
+
+	fn poll_data(
+		self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+	) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+		let mut recorder = self.0.lock().unwrap();
+		//The source is gone once the call is replayed
+		let Some(source) = recorder.source.as_mut() else {
+			return Poll::Ready(None);
+		};
+		let chunk = ready!(Pin::new(source).poll_data(cx));
+		if let Some(Ok(data)) = &chunk {
+			recorder.record(data);
+		}
+		Poll::Ready(chunk)
+	}
+
+	fn poll_trailers(
+		self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+	) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+		match self.0.lock().unwrap().source.as_mut() {
+			Some(source) => Pin::new(source).poll_trailers(cx),
+			None => Poll::Ready(Ok(None)),
+		}
+	}
+
+	fn is_end_stream(&self) -> bool {
+		let recorder = self.0.lock().unwrap();
+		recorder.source.as_ref().is_none_or(HttpBody::is_end_stream)
+	}
+
+	fn size_hint(&self) -> SizeHint {
+		let recorder = self.0.lock().unwrap();
+		recorder
+			.source
+			.as_ref()
+			.map_or_else(|| SizeHint::with_exact(0), HttpBody::size_hint)
+	}
+}
+
+impl fmt::Debug for RecordedBody {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("RecordedBody").finish_non_exhaustive()
+	}
+}
+
+/// Parts of the request needed to replay it
+struct Replay {
+	method: Method,
+	uri: Uri,
+	version: Version,
+	headers: HeaderMap,
+	extensions: Extensions,
+	recorder: Arc<Mutex<Recorder>>,
+}
+
+impl Replay {
+	/// The request for the fallback, if the body was not too large to record
+	fn request(self) -> Option<Request<Body>> {
+		let mut recorder = self.recorder.lock().unwrap();
+		if recorder.overflowed {
+			return None;
+		}
+		let recorded = std::mem::take(&mut recorder.chunks);
+		let rest = recorder.source.take().unwrap_or_default();
+		drop(recorder);
+		let body = stream::iter(recorded.into_iter().map(Ok)).chain(rest);
+		let mut request = Request::new(Body::wrap_stream(body));
+		*request.method_mut() = self.method;
+		*request.uri_mut() = self.uri;
+		*request.version_mut() = self.version;
+		*request.headers_mut() = self.headers;
+		*request.extensions_mut() = self.extensions;
+		#[cfg(feature = "tracing")]
+		tracing::debug!("call replayed to the fallback service");
+		Some(request)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::convert::Infallible;
+
+	use hyper::{body::HttpBody, service::service_fn, Body, HeaderMap, Request, Response};
+	use tower::{Service, ServiceExt};
+
+	use super::{GrpcFallback, RecordedBody};
+
+	async fn unimplemented<B>(_req: Request<B>) -> Result<Response<Body>, Infallible> {
+		Ok(Response::builder()
+			.header("grpc-status", "12")
+			.body(Body::empty())
+			.unwrap())
+	}
+
+	/// Reads part of the body before answering `UNIMPLEMENTED`
+	async fn reads_then_unimplemented<B: HttpBody + Unpin>(
+		mut req: Request<B>,
+	) -> Result<Response<Body>, Infallible> {
+		req.body_mut().data().await;
+		unimplemented(req).await
+	}
+
+	async fn echo<B>(req: Request<B>) -> Result<Response<Body>, Infallible>
+	where
+		B: HttpBody,
+		B::Error: std::fmt::Debug,
+	{
+		let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+		Ok(Response::new(Body::from(body)))
+	}
+
+	async fn call<S, B>(service: S, chunks: &'static [&'static str]) -> String
+	where
+		S: Service<Request<Body>, Response = Response<B>>,
+		S::Error: std::fmt::Debug,
+		B: HttpBody,
+		B::Error: std::fmt::Debug,
+	{
+		let body = futures::stream::iter(chunks.iter().map(|chunk| Ok::<_, Infallible>(*chunk)));
+		let request = Request::post("/pkg.Service/Method")
+			.body(Body::wrap_stream(body))
+			.unwrap();
+		let response = service.oneshot(request).await.unwrap();
+		let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		String::from_utf8(body.to_vec()).unwrap()
+	}
+
+	#[tokio::test]
+	async fn unimplemented_calls_are_replayed_with_their_whole_body() {
+		let service = GrpcFallback::new(service_fn(unimplemented), service_fn(echo));
+		assert_eq!(call(service, &["a", "b"]).await, "ab");
+
+		let service = GrpcFallback::new(service_fn(reads_then_unimplemented), service_fn(echo));
+		assert_eq!(call(service, &["a", "b", "c"]).await, "abc");
+	}
+
+	#[tokio::test]
+	async fn calls_are_not_replayed_after_reading_too_much() {
+		let service =
+			GrpcFallback::new(service_fn(reads_then_unimplemented), service_fn(echo)).max_buffer(1);
+		assert_eq!(call(service, &["ab", "c"]).await, "");
+	}
+
+	#[tokio::test]
+	async fn registered_extensions_are_replayed() {
+		#[derive(Clone)]
+		struct RequestId(&'static str);
+		struct Unregistered;
+
+		let fallback = service_fn(|req: Request<Body>| async move {
+			let id = req.extensions().get::<RequestId>().map_or("", |id| id.0);
+			assert!(req.extensions().get::<Unregistered>().is_none());
+			Ok::<_, Infallible>(Response::new(Body::from(id)))
+		});
+		let service =
+			GrpcFallback::new(service_fn(unimplemented), fallback).replay_extension::<RequestId>();
+		let mut request = Request::post("/pkg.Service/Method")
+			.body(Body::empty())
+			.unwrap();
+		request.extensions_mut().insert(RequestId("42"));
+		request.extensions_mut().insert(Unregistered);
+		let response = service.oneshot(request).await.unwrap();
+		let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		assert_eq!(body, "42");
+	}
+
+	#[tokio::test]
+	async fn the_grpc_service_gets_the_size_hint_and_the_trailers() {
+		let grpc = service_fn(|mut req: Request<RecordedBody>| async move {
+			let size = req.body().size_hint().exact();
+			hyper::body::to_bytes(req.body_mut()).await.unwrap();
+			let trailers = req.body_mut().trailers().await.unwrap();
+			let check = trailers
+				.as_ref()
+				.and_then(|trailers| trailers.get("x-check"));
+			let body = format!("{size:?} {check:?}");
+			Ok::<_, Infallible>(Response::new(Body::from(body)))
+		});
+		let mut service = GrpcFallback::new(grpc, service_fn(echo));
+
+		let request = Request::post("/pkg.Service/Method")
+			.body(Body::from("ab"))
+			.unwrap();
+		let response = service.ready().await.unwrap().call(request).await.unwrap();
+		let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		assert_eq!(body, "Some(2) None");
+
+		let (mut sender, body) = Body::channel();
+		let request = Request::post("/pkg.Service/Method").body(body).unwrap();
+		let response = tokio::spawn(service.oneshot(request));
+		sender.send_data("ab".into()).await.unwrap();
+		let mut trailers = HeaderMap::new();
+		trailers.insert("x-check", "ok".parse().unwrap());
+		sender.send_trailers(trailers).await.unwrap();
+		drop(sender);
+		let response = response.await.unwrap().unwrap();
+		let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		assert_eq!(body, "None Some(\"ok\")");
+	}
+
+	#[tokio::test]
+	async fn other_responses_are_not_replayed() {
+		let service = GrpcFallback::new(service_fn(echo), service_fn(unimplemented));
+		assert_eq!(call(service, &["a", "b"]).await, "ab");
+	}
+}
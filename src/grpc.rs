@@ -0,0 +1,406 @@
+//! Helpers for the parts of the gRPC protocol handled by the multiplexer itself
+
+use std::time::Duration;
+
+#[cfg(any(feature = "transcoding", feature = "connect"))]
+use hyper::StatusCode;
+use hyper::{
+	body::Bytes,
+	header::{HeaderValue, CONTENT_TYPE},
+	HeaderMap, Response,
+};
+
+use crate::local::{LocalBody, Once};
+#[cfg(any(feature = "transcoding", feature = "connect"))]
+use crate::BoxedError;
+
+/// Status codes of the responses generated by the multiplexer
+pub(crate) mod code {
+	#[cfg(any(
+		feature = "health",
+		feature = "transcoding",
+		feature = "connect",
+		feature = "reflection"
+	))]
+	pub(crate) const OK: i32 = 0;
+	#[cfg(any(
+		feature = "health",
+		feature = "transcoding",
+		feature = "connect",
+		feature = "reflection"
+	))]
+	pub(crate) const INVALID_ARGUMENT: i32 = 3;
+	pub(crate) const DEADLINE_EXCEEDED: i32 = 4;
+	#[cfg(any(feature = "health", feature = "reflection"))]
+	pub(crate) const NOT_FOUND: i32 = 5;
+	pub(crate) const RESOURCE_EXHAUSTED: i32 = 8;
+	pub(crate) const UNIMPLEMENTED: i32 = 12;
+	#[cfg(any(feature = "transcoding", feature = "connect"))]
+	pub(crate) const INTERNAL: i32 = 13;
+	pub(crate) const UNAVAILABLE: i32 = 14;
+}
+
+/// Trailers with the given status, and its message percent-encoded
+pub(crate) fn status_trailers(code: i32, message: &str) -> HeaderMap {
+	let mut trailers = HeaderMap::new();
+	trailers.insert("grpc-status", HeaderValue::from(code));
+	if !message.is_empty() {
+		let message = percent_encode(message);
+		trailers.insert("grpc-message", HeaderValue::from_str(&message).unwrap());
+	}
+	trailers
+}
+
+/// Response without messages, with the status in the headers
+pub(crate) fn status_response(code: i32, message: &str) -> Response<LocalBody> {
+	let mut response = Response::new(Once::new(Bytes::new(), None).boxed());
+	let headers = response.headers_mut();
+	headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+	headers.extend(status_trailers(code, message));
+	response
+}
+
+/// Response with one message, followed by an OK status
+#[cfg(feature = "health")]
+pub(crate) fn message_response(message: &[u8]) -> Response<LocalBody> {
+	let body = Once::new(
+		encode_frame(0, message),
+		Some(status_trailers(code::OK, "")),
+	);
+	let mut response = Response::new(body.boxed());
+	response
+		.headers_mut()
+		.insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+	response
+}
+
+/// Prefix a message with its flags and its length, as in gRPC and Connect streams
+#[cfg(any(
+	feature = "health",
+	feature = "transcoding",
+	feature = "connect",
+	feature = "reflection"
+))]
+pub(crate) fn encode_frame(flags: u8, message: &[u8]) -> Bytes {
+	let mut frame = Vec::with_capacity(message.len() + 5);
+	frame.push(flags);
+	frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+	frame.extend_from_slice(message);
+	frame.into()
+}
+
+/// Read the only message of a unary request, failing with its status
+#[cfg(feature = "health")]
+pub(crate) async fn read_unary(body: hyper::Body, max_bytes: u64) -> Result<Bytes, (i32, String)> {
+	use hyper::body::Buf;
+
+	let invalid = |message: &str| (code::INVALID_ARGUMENT, message.to_owned());
+	let mut body = read_limited(body, max_bytes).await?;
+	if body.is_empty() {
+		//Empty requests are the default message
+		return Ok(body);
+	}
+	if body.len() < 5 {
+		return Err(invalid("incomplete message"));
+	}
+	if body[0] != 0 {
+		return Err(invalid("compressed messages are not supported"));
+	}
+	body.advance(1);
+	let len = body.get_u32() as usize;
+	if body.len() != len {
+		return Err(invalid("expected exactly one message"));
+	}
+	Ok(body)
+}
+
+/// Read a whole request body, failing with `RESOURCE_EXHAUSTED` once it is larger than `max_bytes`
+#[cfg(any(feature = "health", feature = "transcoding", feature = "connect"))]
+pub(crate) async fn read_limited(
+	body: hyper::Body,
+	max_bytes: u64,
+) -> Result<Bytes, (i32, String)> {
+	let max_bytes = usize::try_from(max_bytes).unwrap_or(usize::MAX);
+	hyper::body::to_bytes(http_body::Limited::new(body, max_bytes))
+		.await
+		.map_err(|error| match error.is::<http_body::LengthLimitError>() {
+			true => (
+				code::RESOURCE_EXHAUSTED,
+				format!("request body is larger than {max_bytes} bytes"),
+			),
+			false => (code::INVALID_ARGUMENT, error.to_string()),
+		})
+}
+
+/// Parse the value of a `grpc-timeout` header, like `100m` or `5S`
+pub(crate) fn parse_timeout(value: &str) -> Option<Duration> {
+	if value.len() < 2 || value.len() > 9 {
+		return None;
+	}
+	let (amount, unit) = value.split_at(value.len() - 1);
+	if !amount.bytes().all(|b| b.is_ascii_digit()) {
+		return None;
+	}
+	let amount: u64 = amount.parse().ok()?;
+	Some(match unit {
+		"H" => Duration::from_secs(amount * 60 * 60),
+		"M" => Duration::from_secs(amount * 60),
+		"S" => Duration::from_secs(amount),
+		"m" => Duration::from_millis(amount),
+		"u" => Duration::from_micros(amount),
+		"n" => Duration::from_nanos(amount),
+		_ => return None,
+	})
+}
+
+/// Status and decoded message of gRPC trailers, or of the headers of a trailers-only response
+#[cfg(any(feature = "transcoding", feature = "connect"))]
+pub(crate) fn parse_status(headers: &HeaderMap) -> Option<(i32, String)> {
+	let code = headers.get("grpc-status")?.to_str().ok()?.parse().ok()?;
+	let message = headers
+		.get("grpc-message")
+		.and_then(|message| message.to_str().ok())
+		.map(|message| percent_decode(message, false, false))
+		.unwrap_or_default();
+	Some((code, message))
+}
+
+/// HTTP status of a failed call with the given gRPC status
+#[cfg(any(feature = "transcoding", feature = "connect"))]
+pub(crate) fn http_status(code: i32) -> StatusCode {
+	match code {
+		1 => StatusCode::from_u16(499).unwrap(),
+		3 | 9 | 11 => StatusCode::BAD_REQUEST,
+		4 => StatusCode::GATEWAY_TIMEOUT,
+		5 => StatusCode::NOT_FOUND,
+		6 | 10 => StatusCode::CONFLICT,
+		7 => StatusCode::FORBIDDEN,
+		8 => StatusCode::TOO_MANY_REQUESTS,
+		12 => StatusCode::NOT_IMPLEMENTED,
+		14 => StatusCode::SERVICE_UNAVAILABLE,
+		16 => StatusCode::UNAUTHORIZED,
+		_ => StatusCode::INTERNAL_SERVER_ERROR,
+	}
+}
+
+/// Largest message read by the multiplexer, like the default of gRPC servers
+#[cfg(any(feature = "transcoding", feature = "connect", feature = "reflection"))]
+pub(crate) const MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Buffers a unary response body, until its trailers
+#[cfg(any(feature = "transcoding", feature = "connect"))]
+#[derive(Default)]
+pub(crate) struct UnaryBody {
+	buffer: Vec<u8>,
+}
+
+#[cfg(any(feature = "transcoding", feature = "connect"))]
+impl UnaryBody {
+	/// Buffer a part of the response body
+	pub(crate) fn push(&mut self, data: &[u8]) -> Result<(), BoxedError> {
+		self.buffer.extend_from_slice(data);
+		if self.buffer.len() > 5 + MAX_MESSAGE_SIZE {
+			let message = format!("the response message is larger than {MAX_MESSAGE_SIZE} bytes");
+			return Err(message.into());
+		}
+		Ok(())
+	}
+
+	/// The only message of the response
+	pub(crate) fn message(&self) -> Result<Bytes, BoxedError> {
+		let buffer = &self.buffer;
+		if buffer.len() < 5 {
+			return Err("the response has no message".into());
+		}
+		if buffer[0] != 0 {
+			return Err("compressed messages are not supported".into());
+		}
+		let len = u32::from_be_bytes([buffer[1], buffer[2], buffer[3], buffer[4]]) as usize;
+		let message = buffer.get(5..5 + len).ok_or("incomplete message")?;
+		Ok(Bytes::copy_from_slice(message))
+	}
+}
+
+/// Flags and message of an envelope
+#[cfg(any(feature = "connect", feature = "reflection"))]
+pub(crate) type Envelope = (u8, Vec<u8>);
+
+/// Splits a stream of data in enveloped messages
+#[cfg(any(feature = "connect", feature = "reflection"))]
+#[derive(Default)]
+pub(crate) struct Envelopes {
+	buffer: Vec<u8>,
+}
+
+#[cfg(any(feature = "connect", feature = "reflection"))]
+impl Envelopes {
+	pub(crate) fn push(&mut self, data: &[u8]) {
+		self.buffer.extend_from_slice(data);
+	}
+
+	/// Flags and message of the next complete envelope, or `RESOURCE_EXHAUSTED`
+	/// once its length is larger than [MAX_MESSAGE_SIZE]
+	pub(crate) fn next(&mut self) -> Option<Result<Envelope, (i32, String)>> {
+		let header = self.buffer.get(..5)?;
+		let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+		if len > MAX_MESSAGE_SIZE {
+			let message = format!("message of {len} bytes is larger than {MAX_MESSAGE_SIZE} bytes");
+			return Some(Err((code::RESOURCE_EXHAUSTED, message)));
+		}
+		if self.buffer.len() < 5 + len {
+			return None;
+		}
+		let envelope: Vec<u8> = self.buffer.drain(..5 + len).collect();
+		Some(Ok((envelope[0], envelope[5..].to_vec())))
+	}
+
+	pub(crate) fn is_empty(&self) -> bool {
+		self.buffer.is_empty()
+	}
+}
+
+fn percent_encode(message: &str) -> String {
+	let mut encoded = String::with_capacity(message.len());
+	for byte in message.bytes() {
+		match byte {
+			b' '..=b'~' if byte != b'%' => encoded.push(byte as char),
+			_ => encoded.push_str(&format!("%{byte:02X}")),
+		}
+	}
+	encoded
+}
+
+/// Decode the `%XX` escapes of `value`
+///
+/// With `keep_slash`, `%2F` stays escaped, and with `plus_as_space`, `+` is
+/// decoded as a space, as in query strings.
+#[cfg(any(feature = "transcoding", feature = "connect"))]
+pub(crate) fn percent_decode(value: &str, keep_slash: bool, plus_as_space: bool) -> String {
+	let bytes = value.as_bytes();
+	let mut decoded = Vec::with_capacity(bytes.len());
+	let mut index = 0;
+	while index < bytes.len() {
+		let escaped = bytes
+			.get(index + 1..index + 3)
+			.filter(|_| bytes[index] == b'%')
+			.and_then(|hex| std::str::from_utf8(hex).ok())
+			.and_then(|hex| u8::from_str_radix(hex, 16).ok())
+			.filter(|byte| !(keep_slash && *byte == b'/'));
+		match (escaped, bytes[index]) {
+			(Some(byte), _) => {
+				decoded.push(byte);
+				index += 3;
+				continue;
+			}
+			(None, b'+') if plus_as_space => decoded.push(b' '),
+			(None, byte) => decoded.push(byte),
+		}
+		index += 1;
+	}
+	String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Split a gRPC path in the form of `/package.Service/Method`
+pub(crate) fn split_path(path: &str) -> Option<(&str, &str)> {
+	let (service, method) = path.strip_prefix('/')?.split_once('/')?;
+	if service.is_empty() || method.is_empty() || method.contains('/') {
+		return None;
+	}
+	Some((service, method))
+}
+
+#[cfg(test)]
+mod tests {
+	#[cfg(any(
+		feature = "health",
+		feature = "transcoding",
+		feature = "connect",
+		feature = "reflection"
+	))]
+	use super::encode_frame;
+	use std::time::Duration;
+
+	use super::{parse_timeout, percent_encode, split_path, status_response};
+
+	#[test]
+	fn split_path_splits_service_and_method() {
+		assert_eq!(
+			split_path("/helloworld.Greeter/SayHello"),
+			Some(("helloworld.Greeter", "SayHello"))
+		);
+	}
+
+	#[test]
+	fn split_path_rejects_other_paths() {
+		assert_eq!(split_path("/"), None);
+		assert_eq!(split_path("/helloworld.Greeter"), None);
+		assert_eq!(split_path("/helloworld.Greeter/"), None);
+		assert_eq!(split_path("/a/b/c"), None);
+	}
+
+	#[test]
+	fn status_response_is_trailers_only() {
+		let response = status_response(14, "shutting down");
+		let headers = response.headers();
+		assert_eq!(headers["content-type"], "application/grpc");
+		assert_eq!(headers["grpc-status"], "14");
+		assert_eq!(headers["grpc-message"], "shutting down");
+	}
+
+	#[cfg(any(
+		feature = "health",
+		feature = "transcoding",
+		feature = "connect",
+		feature = "reflection"
+	))]
+	#[test]
+	fn encode_frame_prefixes_length() {
+		assert_eq!(&encode_frame(0, b"ab")[..], b"\0\0\0\0\x02ab");
+		assert_eq!(&encode_frame(2, b"")[..], b"\x02\0\0\0\0");
+	}
+
+	#[test]
+	fn percent_encode_escapes_percent_and_non_ascii() {
+		assert_eq!(percent_encode("100% ok"), "100%25 ok");
+		assert_eq!(percent_encode("\u{e9}"), "%C3%A9");
+	}
+
+	#[cfg(any(feature = "transcoding", feature = "connect"))]
+	#[test]
+	fn parse_status_decodes_the_message() {
+		let trailers = super::status_trailers(5, "100% \u{e9}");
+		assert_eq!(
+			super::parse_status(&trailers),
+			Some((5, "100% \u{e9}".to_owned()))
+		);
+	}
+
+	#[cfg(any(feature = "transcoding", feature = "connect"))]
+	#[test]
+	fn percent_decode_options() {
+		use super::percent_decode;
+		assert_eq!(percent_decode("a%2Fb+c", false, false), "a/b+c");
+		assert_eq!(percent_decode("a%2Fb+c", true, true), "a%2Fb c");
+		assert_eq!(percent_decode("%zz%4", false, false), "%zz%4");
+	}
+
+	#[test]
+	fn parse_timeout_reads_every_unit() {
+		assert_eq!(parse_timeout("100m"), Some(Duration::from_millis(100)));
+		assert_eq!(parse_timeout("5S"), Some(Duration::from_secs(5)));
+		assert_eq!(parse_timeout("2M"), Some(Duration::from_secs(120)));
+		assert_eq!(parse_timeout("1H"), Some(Duration::from_secs(3600)));
+		assert_eq!(parse_timeout("7u"), Some(Duration::from_micros(7)));
+		assert_eq!(
+			parse_timeout("99999999n"),
+			Some(Duration::from_nanos(99999999))
+		);
+	}
+
+	#[test]
+	fn parse_timeout_rejects_invalid_values() {
+		for value in ["", "m", "100", "100x", "-1S", "+1S", "123456789S", "1.5S"] {
+			assert_eq!(parse_timeout(value), None, "{value:?}");
+		}
+	}
+}
@@ -0,0 +1,398 @@
+//! Standard gRPC health checking service, and HTTP health endpoint
+
+use std::{
+	collections::HashSet,
+	sync::{
+		atomic::{AtomicU8, Ordering},
+		Arc, PoisonError, RwLock,
+	},
+	task::Poll,
+};
+
+use futures::stream;
+use http_body::{combinators::UnsyncBoxBody, Body as _};
+use hyper::{header::CONTENT_TYPE, Body, Method, Request, Response, StatusCode};
+use prost::Message;
+use tokio::sync::watch;
+
+use crate::{
+	grpc::{self, code},
+	local::{self, LocalBody, LocalFuture},
+	to_boxed, BoxedError, Branch,
+};
+
+const SERVICE_PREFIX: &str = "/grpc.health.v1.Health/";
+const HTTP_PATH: &str = "/healthz";
+//Health requests only have a service name
+const MAX_REQUEST_BYTES: u64 = 4096;
+
+/// Status reported by the health endpoints
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ServingStatus {
+	///No inner service was polled yet
+	Unknown,
+	///Both inner services are ready
+	Serving,
+	///An inner service failed, or the status was overridden
+	NotServing,
+}
+
+impl ServingStatus {
+	fn to_proto(self) -> i32 {
+		match self {
+			ServingStatus::Unknown => 0,
+			ServingStatus::Serving => 1,
+			ServingStatus::NotServing => 2,
+		}
+	}
+
+	fn from_u8(value: u8) -> Option<Self> {
+		match value {
+			1 => Some(ServingStatus::Unknown),
+			2 => Some(ServingStatus::Serving),
+			3 => Some(ServingStatus::NotServing),
+			_ => None,
+		}
+	}
+
+	fn to_u8(self) -> u8 {
+		match self {
+			ServingStatus::Unknown => 1,
+			ServingStatus::Serving => 2,
+			ServingStatus::NotServing => 3,
+		}
+	}
+}
+
+/// Status of the `SERVICE_UNKNOWN` value, only used by Watch
+const SERVICE_UNKNOWN: i32 = 3;
+
+//Readiness of one inner service, as reported by its last poll_ready
+const UNKNOWN: u8 = 0;
+const READY: u8 = 1;
+const FAILED: u8 = 2;
+
+/// Response to a request for an inner service that is not ready
+pub(crate) fn unavailable(branch: Branch) -> Response<LocalBody> {
+	match branch {
+		Branch::Grpc => grpc::status_response(code::UNAVAILABLE, "service is not serving"),
+		Branch::Web => local::text(StatusCode::SERVICE_UNAVAILABLE, "service is not serving\n"),
+	}
+}
+
+/// Health status shared by the health endpoints of every [Multiplexer][crate::Multiplexer]
+/// built with it
+///
+/// Enabled with [Builder::health][crate::Builder::health], it serves the standard
+/// `grpc.health.v1.Health` service on the gRPC branch, and `GET /healthz` on the
+/// web branch, without calling the inner services.
+///
+/// The status is [Serving][ServingStatus::Serving] after both inner services
+/// returned ready, and [NotServing][ServingStatus::NotServing] while the last
+/// `poll_ready` of one of them returned an error.
+///
+/// A Multiplexer with health is always ready, so the probes are answered while
+/// an inner service is not. Its other requests get `UNAVAILABLE` or
+/// `503 Service Unavailable`, without calling their inner service, while the
+/// last `poll_ready` of that service did not return ready.
+///
+/// [set_override][HealthHandle::set_override] replaces the status reported,
+/// for example to fail the probes before draining a server.
+///
+/// # Examples:
+/// ```
+/// use multiplex_tonic_hyper::{HealthHandle, Multiplexer, ServingStatus};
+/// # use hyper::{service::service_fn, Body, Request, Response};
+/// # async fn handle(_: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+/// # 	Ok(Response::new(Body::empty()))
+/// # }
+/// # let grpc = service_fn(handle);
+/// # let web = service_fn(handle);
+///
+/// let health = HealthHandle::new().with_service("helloworld.Greeter");
+/// let multiplexer = Multiplexer::builder()
+/// 	.health(health.clone())
+/// 	.build(grpc, web);
+///
+/// //Before shutting down
+/// health.set_override(ServingStatus::NotServing);
+/// ```
+#[derive(Clone)]
+pub struct HealthHandle {
+	shared: Arc<Shared>,
+}
+
+struct Shared {
+	grpc: AtomicU8,
+	web: AtomicU8,
+	overridden: AtomicU8,
+	services: RwLock<HashSet<String>>,
+	sender: watch::Sender<ServingStatus>,
+}
+
+impl Default for HealthHandle {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl HealthHandle {
+	/// Handle that reports only the overall status, with the empty service name
+	pub fn new() -> Self {
+		let (sender, _) = watch::channel(ServingStatus::Unknown);
+		HealthHandle {
+			shared: Arc::new(Shared {
+				grpc: AtomicU8::new(UNKNOWN),
+				web: AtomicU8::new(UNKNOWN),
+				overridden: AtomicU8::new(0),
+				services: Default::default(),
+				sender,
+			}),
+		}
+	}
+
+	/// Also report the overall status for the gRPC service with this name
+	///
+	/// Checking other names returns `NOT_FOUND`. The name is known by every
+	/// clone of this handle, including the ones already given to a [Builder][crate::Builder].
+	pub fn with_service(self, name: impl Into<String>) -> Self {
+		self.shared
+			.services
+			.write()
+			.unwrap_or_else(PoisonError::into_inner)
+			.insert(name.into());
+		//Wake the watches of this name
+		self.shared.sender.send_modify(|_| ());
+		self
+	}
+
+	/// Status reported by the health endpoints
+	pub fn status(&self) -> ServingStatus {
+		let shared = &self.shared;
+		if let Some(status) = ServingStatus::from_u8(shared.overridden.load(Ordering::Acquire)) {
+			return status;
+		}
+		match (
+			shared.grpc.load(Ordering::Acquire),
+			shared.web.load(Ordering::Acquire),
+		) {
+			(FAILED, _) | (_, FAILED) => ServingStatus::NotServing,
+			(READY, READY) => ServingStatus::Serving,
+			_ => ServingStatus::Unknown,
+		}
+	}
+
+	/// Report this status, regardless of the inner services
+	pub fn set_override(&self, status: ServingStatus) {
+		self.shared
+			.overridden
+			.store(status.to_u8(), Ordering::Release);
+		self.notify();
+	}
+
+	/// Report the status of the inner services again
+	pub fn clear_override(&self) {
+		self.shared.overridden.store(0, Ordering::Release);
+		self.notify();
+	}
+
+	/// Record the result of an inner service poll_ready
+	pub(crate) fn observe<E>(&self, branch: Branch, poll: &Poll<Result<(), E>>) {
+		let readiness = match poll {
+			Poll::Ready(Ok(())) => READY,
+			Poll::Ready(Err(_)) => FAILED,
+			Poll::Pending => return,
+		};
+		let slot = match branch {
+			Branch::Grpc => &self.shared.grpc,
+			Branch::Web => &self.shared.web,
+		};
+		if slot.swap(readiness, Ordering::AcqRel) != readiness {
+			self.notify();
+		}
+	}
+
+	fn notify(&self) {
+		let status = self.status();
+		self.shared.sender.send_if_modified(|current| {
+			let modified = *current != status;
+			*current = status;
+			modified
+		});
+	}
+
+	fn knows(&self, service: &str) -> bool {
+		service.is_empty()
+			|| self
+				.shared
+				.services
+				.read()
+				.unwrap_or_else(PoisonError::into_inner)
+				.contains(service)
+	}
+
+	/// Answer the request if it is for a health endpoint, otherwise give it back
+	#[allow(clippy::result_large_err)]
+	pub(crate) fn serve(
+		&self,
+		branch: Branch,
+		request: Request<Body>,
+	) -> Result<LocalFuture, Request<Body>> {
+		match branch {
+			Branch::Grpc => match request.uri().path().strip_prefix(SERVICE_PREFIX) {
+				Some("Check") => Ok(Box::pin(self.clone().check(request.into_body()))),
+				Some("Watch") => Ok(Box::pin(self.clone().watch(request.into_body()))),
+				Some(method) => {
+					let message = format!("unknown method {method}");
+					let response = grpc::status_response(code::UNIMPLEMENTED, &message);
+					Ok(local::ready(response))
+				}
+				None => Err(request),
+			},
+			Branch::Web => {
+				let method = request.method();
+				if request.uri().path() != HTTP_PATH
+					|| (method != Method::GET && method != Method::HEAD)
+				{
+					return Err(request);
+				}
+				let response = match self.status() {
+					ServingStatus::Serving => local::text(StatusCode::OK, "SERVING\n"),
+					ServingStatus::NotServing => {
+						local::text(StatusCode::SERVICE_UNAVAILABLE, "NOT_SERVING\n")
+					}
+					ServingStatus::Unknown => {
+						local::text(StatusCode::SERVICE_UNAVAILABLE, "UNKNOWN\n")
+					}
+				};
+				Ok(local::ready(response))
+			}
+		}
+	}
+
+	async fn check(self, body: Body) -> Result<Response<LocalBody>, BoxedError> {
+		let request = match decode_request(body).await {
+			Ok(request) => request,
+			Err(response) => return Ok(response),
+		};
+		if !self.knows(&request.service) {
+			return Ok(grpc::status_response(code::NOT_FOUND, "unknown service"));
+		}
+		let response = HealthCheckResponse {
+			status: self.status().to_proto(),
+		};
+		Ok(grpc::message_response(&response.encode_to_vec()))
+	}
+
+	async fn watch(self, body: Body) -> Result<Response<LocalBody>, BoxedError> {
+		let request = match decode_request(body).await {
+			Ok(request) => request,
+			Err(response) => return Ok(response),
+		};
+		let frame = |status: i32| {
+			let message = HealthCheckResponse { status }.encode_to_vec();
+			Ok::<_, BoxedError>(grpc::encode_frame(0, &message))
+		};
+		let mut receiver = self.shared.sender.subscribe();
+		receiver.mark_changed();
+		//An unknown service may be added later, so its stream stays open
+		let state = (self, request.service, receiver, None);
+		let updates = stream::unfold(
+			state,
+			move |(health, service, mut receiver, last)| async move {
+				loop {
+					receiver.changed().await.ok()?;
+					let status = receiver.borrow_and_update().to_proto();
+					let status = match health.knows(&service) {
+						true => status,
+						false => SERVICE_UNKNOWN,
+					};
+					if last != Some(status) {
+						return Some((frame(status), (health, service, receiver, Some(status))));
+					}
+				}
+			},
+		);
+		let body = Body::wrap_stream(updates).map_err(to_boxed);
+		let mut response = Response::new(UnsyncBoxBody::new(body));
+		response
+			.headers_mut()
+			.insert(CONTENT_TYPE, "application/grpc".parse().unwrap());
+		Ok(response)
+	}
+}
+
+async fn decode_request(body: Body) -> Result<HealthCheckRequest, Response<LocalBody>> {
+	let message = grpc::read_unary(body, MAX_REQUEST_BYTES)
+		.await
+		.map_err(|(code, message)| grpc::status_response(code, &message))?;
+	HealthCheckRequest::decode(message)
+		.map_err(|error| grpc::status_response(code::INVALID_ARGUMENT, &error.to_string()))
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct HealthCheckRequest {
+	#[prost(string, tag = "1")]
+	service: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct HealthCheckResponse {
+	#[prost(int32, tag = "1")]
+	status: i32,
+}
+
+#[cfg(test)]
+mod tests {
+	use std::task::Poll;
+
+	use super::{HealthHandle, ServingStatus};
+	use crate::Branch;
+
+	fn ready() -> Poll<Result<(), ()>> {
+		Poll::Ready(Ok(()))
+	}
+
+	#[test]
+	fn serving_after_both_branches_are_ready() {
+		let health = HealthHandle::new();
+		assert_eq!(health.status(), ServingStatus::Unknown);
+		health.observe(Branch::Grpc, &ready());
+		assert_eq!(health.status(), ServingStatus::Unknown);
+		health.observe(Branch::Web, &Poll::<Result<(), ()>>::Pending);
+		assert_eq!(health.status(), ServingStatus::Unknown);
+		health.observe(Branch::Web, &ready());
+		assert_eq!(health.status(), ServingStatus::Serving);
+	}
+
+	#[test]
+	fn not_serving_while_a_branch_failed() {
+		let health = HealthHandle::new();
+		health.observe(Branch::Grpc, &ready());
+		health.observe(Branch::Web, &Poll::Ready(Err(())));
+		assert_eq!(health.status(), ServingStatus::NotServing);
+		health.observe(Branch::Web, &ready());
+		assert_eq!(health.status(), ServingStatus::Serving);
+	}
+
+	#[test]
+	fn override_replaces_the_status_until_cleared() {
+		let health = HealthHandle::new();
+		health.observe(Branch::Grpc, &ready());
+		health.observe(Branch::Web, &ready());
+		health.set_override(ServingStatus::NotServing);
+		assert_eq!(health.clone().status(), ServingStatus::NotServing);
+		health.clear_override();
+		assert_eq!(health.status(), ServingStatus::Serving);
+	}
+
+	#[test]
+	fn knows_the_empty_name_and_added_services() {
+		let health = HealthHandle::new().with_service("helloworld.Greeter");
+		assert!(health.knows(""));
+		assert!(health.knows("helloworld.Greeter"));
+		assert!(!health.knows("other.Service"));
+		let _clone = health.clone().with_service("other.Service");
+		assert!(health.knows("other.Service"));
+	}
+}
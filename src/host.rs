@@ -0,0 +1,403 @@
+//! Routing between services by the host of the request
+
+use std::{
+	collections::HashMap,
+	convert::Infallible,
+	future::Future,
+	pin::Pin,
+	str::FromStr,
+	sync::{Arc, Mutex},
+	task::{Context, Poll},
+};
+
+use hyper::{header::HOST, http::uri::Authority, Body, Request};
+use pin_project::pin_project;
+use tower::Service;
+
+use crate::{to_boxed, BoxedError};
+
+/// Service that routes requests by their host, to one service per virtual host
+///
+/// The host is the authority of the request URI, like the `:authority` of
+/// HTTP/2 requests, or else the `Host` header, without the port. Patterns are
+/// either a host name, like `api.example.com`, or a wildcard for its
+/// subdomains, like `*.example.com`. Host names take precedence over
+/// wildcards, and longer wildcards over shorter ones. Requests for other
+/// hosts go to the default service.
+///
+/// Every service must have the same type, usually a [Multiplexer][crate::Multiplexer]
+/// for each tenant. The router is only ready when all services are ready.
+///
+/// # Examples:
+/// ```
+/// use multiplex_tonic_hyper::{HostRouter, Multiplexer};
+/// # use hyper::{service::service_fn, Body, Request, Response};
+/// # async fn handle(_: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+/// # 	Ok(Response::new(Body::empty()))
+/// # }
+/// # let tenant = || Multiplexer::new(service_fn(handle), service_fn(handle));
+///
+/// let router = HostRouter::new(tenant())
+/// 	.host("a.example.com", tenant())
+/// 	.host("*.b.example.com", tenant());
+/// ```
+#[derive(Clone, Debug)]
+pub struct HostRouter<S> {
+	default: S,
+	services: Vec<S>,
+	exact: HashMap<String, usize>,
+	//Suffixes like `.example.com`, longest first
+	wildcards: Vec<(String, usize)>,
+}
+
+impl<S> HostRouter<S> {
+	/// Send requests for hosts without a service to `default`
+	pub fn new(default: S) -> Self {
+		HostRouter {
+			default,
+			services: Vec::new(),
+			exact: HashMap::new(),
+			wildcards: Vec::new(),
+		}
+	}
+
+	/// Send requests for hosts that match `pattern` to `service`
+	///
+	/// Adding a pattern again replaces its service.
+	pub fn host(mut self, pattern: &str, service: S) -> Self {
+		let pattern = normalize(pattern);
+		let existing = match pattern.strip_prefix('*') {
+			Some(suffix) => self
+				.wildcards
+				.iter()
+				.find(|(existing, _)| existing == suffix)
+				.map(|(_, index)| *index),
+			None => self.exact.get(&pattern).copied(),
+		};
+		if let Some(index) = existing {
+			self.services[index] = service;
+			return self;
+		}
+		let index = self.services.len();
+		self.services.push(service);
+		match pattern.strip_prefix('*') {
+			Some(suffix) => {
+				self.wildcards.push((suffix.to_owned(), index));
+				self.wildcards
+					.sort_by_key(|(suffix, _)| std::cmp::Reverse(suffix.len()));
+			}
+			None => {
+				self.exact.insert(pattern, index);
+			}
+		}
+		self
+	}
+
+	/// Index of the service for the host of the request, or None for the default
+	fn route<B>(&self, req: &Request<B>) -> Option<usize> {
+		let host = request_host(req)?;
+		if let Some(index) = self.exact.get(&host) {
+			return Some(*index);
+		}
+		self.wildcards
+			.iter()
+			.find(|(suffix, _)| host.len() > suffix.len() && host.ends_with(suffix.as_str()))
+			.map(|(_, index)| *index)
+	}
+
+	/// Same routes, with each service replaced by `f(service)`
+	fn map<T>(&self, mut f: impl FnMut(&S) -> T) -> HostRouter<T> {
+		HostRouter {
+			default: f(&self.default),
+			services: self.services.iter().map(f).collect(),
+			exact: self.exact.clone(),
+			wildcards: self.wildcards.clone(),
+		}
+	}
+}
+
+impl<S> Service<Request<Body>> for HostRouter<S>
+where
+	S: Service<Request<Body>>,
+{
+	type Response = S::Response;
+	type Error = S::Error;
+	type Future = S::Future;
+
+	///Only is ready if all services are ready
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		let mut ready = self.default.poll_ready(cx)?.is_ready();
+		for service in &mut self.services {
+			ready &= service.poll_ready(cx)?.is_ready();
+		}
+		match ready {
+			true => Poll::Ready(Ok(())),
+			false => Poll::Pending,
+		}
+	}
+
+	fn call(&mut self, req: Request<Body>) -> Self::Future {
+		match self.route(&req) {
+			Some(index) => self.services[index].call(req),
+			None => self.default.call(req),
+		}
+	}
+}
+
+/// A MakeService for [HostRouter], that makes the service of each host on its first request
+///
+/// Each connection gets a [HostRouter] of [Lazy] services. A host's make service
+/// is only called, with `()` as the target, when the connection receives a
+/// request for that host. [MakeMultiplexer][crate::MakeMultiplexer] and
+/// [Shared][tower::make::Shared] accept that target.
+///
+/// # Examples:
+/// ```no_run
+/// use multiplex_tonic_hyper::{MakeHostRouter, MakeMultiplexer};
+/// # use hyper::{service::service_fn, Body, Request, Response};
+/// # use tower::make::Shared;
+/// # async fn handle(_: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+/// # 	Ok(Response::new(Body::empty()))
+/// # }
+/// # let tenant = || MakeMultiplexer::new(Shared::new(service_fn(handle)), Shared::new(service_fn(handle)));
+///
+/// let make_router = MakeHostRouter::new(tenant())
+/// 	.host("a.example.com", tenant())
+/// 	.host("*.b.example.com", tenant());
+/// # let addr = "[::1]:0".parse().unwrap();
+/// let server = hyper::Server::bind(&addr).serve(make_router);
+/// ```
+#[derive(Clone, Debug)]
+pub struct MakeHostRouter<M> {
+	makes: HostRouter<M>,
+}
+
+impl<M> MakeHostRouter<M> {
+	/// Make the services for hosts without a route with `default`
+	pub fn new(default: M) -> Self {
+		MakeHostRouter {
+			makes: HostRouter::new(default),
+		}
+	}
+
+	/// Make the services for hosts that match `pattern` with `make`
+	///
+	/// The patterns are the same of [HostRouter::host].
+	pub fn host(mut self, pattern: &str, make: M) -> Self {
+		self.makes = self.makes.host(pattern, make);
+		self
+	}
+}
+
+impl<M, Target> Service<Target> for MakeHostRouter<M>
+where
+	M: Service<()> + Clone,
+{
+	type Response = HostRouter<Lazy<M>>;
+	type Error = Infallible;
+	type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn call(&mut self, _target: Target) -> Self::Future {
+		futures::future::ready(Ok(self.makes.map(|make| Lazy::new(make.clone()))))
+	}
+}
+
+/// Service that is made by its make service on the first request
+///
+/// Requests that arrive while the service is being made make their own
+/// service, and the first one made is kept.
+pub struct Lazy<M: Service<()>> {
+	make: M,
+	service: Option<M::Response>,
+	//Receives the service made by the future of a call
+	made: Option<Arc<Mutex<Option<M::Response>>>>,
+}
+
+impl<M: Service<()>> Lazy<M> {
+	fn new(make: M) -> Self {
+		Lazy {
+			make,
+			service: None,
+			made: None,
+		}
+	}
+}
+
+impl<M, S> Service<Request<Body>> for Lazy<M>
+where
+	M: Service<(), Response = S>,
+	M::Error: Into<BoxedError>,
+	S: Service<Request<Body>>,
+	S::Error: Into<BoxedError>,
+{
+	type Response = S::Response;
+	type Error = BoxedError;
+	type Future = LazyFuture<M::Future, S>;
+
+	///Checks the service once it is made, and its make service before that
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		if self.service.is_none() {
+			let made = self
+				.made
+				.as_ref()
+				.and_then(|made| made.lock().unwrap().take());
+			if made.is_some() {
+				self.service = made;
+				self.made = None;
+			}
+		}
+		match &mut self.service {
+			Some(service) => service.poll_ready(cx).map_err(Into::into),
+			None => self.make.poll_ready(cx).map_err(Into::into),
+		}
+	}
+
+	fn call(&mut self, req: Request<Body>) -> Self::Future {
+		match &mut self.service {
+			Some(service) => LazyFuture::Call(service.call(req)),
+			None => LazyFuture::Make {
+				making: self.make.call(()),
+				request: Some(req),
+				made: self.made.get_or_insert_with(Default::default).clone(),
+			},
+		}
+	}
+}
+
+/// Future of [Lazy], that makes the service if needed before calling it
+#[pin_project(project = LazyProj)]
+pub enum LazyFuture<MakeFuture, S: Service<Request<Body>>> {
+	///Making the service
+	Make {
+		///Future of the make service
+		#[pin]
+		making: MakeFuture,
+		///Request to call the service with
+		request: Option<Request<Body>>,
+		///Where to keep the service
+		made: Arc<Mutex<Option<S>>>,
+	},
+	///Waiting for the made service to be ready
+	Ready {
+		///The made service
+		service: Option<S>,
+		///Request to call the service with
+		request: Option<Request<Body>>,
+		///Where to keep the service
+		made: Arc<Mutex<Option<S>>>,
+	},
+	///Calling the service
+	Call(#[pin] S::Future),
+}
+
+impl<MakeFuture, MakeError, S> Future for LazyFuture<MakeFuture, S>
+where
+	MakeFuture: Future<Output = Result<S, MakeError>>,
+	MakeError: Into<BoxedError>,
+	S: Service<Request<Body>>,
+	S::Error: Into<BoxedError>,
+{
+	type Output = Result<S::Response, BoxedError>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		loop {
+			let next = match self.as_mut().project() {
+				LazyProj::Make {
+					making,
+					request,
+					made,
+				} => {
+					let service = futures::ready!(making.poll(cx)).map_err(to_boxed)?;
+					LazyFuture::Ready {
+						service: Some(service),
+						request: request.take(),
+						made: made.clone(),
+					}
+				}
+				LazyProj::Ready {
+					service,
+					request,
+					made,
+				} => {
+					let ready = service
+						.as_mut()
+						.expect("polled after completion")
+						.poll_ready(cx);
+					futures::ready!(ready).map_err(to_boxed)?;
+					let mut service = service.take().unwrap();
+					let future = service.call(request.take().unwrap());
+					made.lock().unwrap().get_or_insert(service);
+					LazyFuture::Call(future)
+				}
+				LazyProj::Call(future) => return future.poll(cx).map_err(to_boxed),
+			};
+			self.set(next);
+		}
+	}
+}
+
+/// Host of the request in lowercase, without the port and a trailing dot
+fn request_host<B>(req: &Request<B>) -> Option<String> {
+	let from_header = || {
+		let value = req.headers().get(HOST)?.to_str().ok()?;
+		Authority::from_str(value).ok()
+	};
+	let authority = req.uri().authority().cloned().or_else(from_header)?;
+	Some(normalize(authority.host()))
+}
+
+fn normalize(host: &str) -> String {
+	host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+	use hyper::{Body, Request};
+
+	use super::HostRouter;
+
+	fn route(router: &HostRouter<&'static str>, host: &str) -> &'static str {
+		let request = Request::get("/")
+			.header("host", host)
+			.body(Body::empty())
+			.unwrap();
+		match router.route(&request) {
+			Some(index) => router.services[index],
+			None => router.default,
+		}
+	}
+
+	#[test]
+	fn hosts_take_precedence_over_wildcards() {
+		let router = HostRouter::new("default")
+			.host("*.example.com", "wildcard")
+			.host("*.api.example.com", "api wildcard")
+			.host("www.Example.com", "www");
+
+		assert_eq!(route(&router, "www.example.com:8080"), "www");
+		assert_eq!(route(&router, "WWW.example.com."), "www");
+		assert_eq!(route(&router, "other.example.com"), "wildcard");
+		assert_eq!(route(&router, "a.b.example.com"), "wildcard");
+		assert_eq!(route(&router, "v1.api.example.com"), "api wildcard");
+		assert_eq!(route(&router, "example.com"), "default");
+		assert_eq!(route(&router, "notexample.com"), "default");
+		assert_eq!(route(&router, "[::1]:80"), "default");
+	}
+
+	#[test]
+	fn uri_authority_is_used_before_the_host_header() {
+		let router = HostRouter::new("default").host("a.example.com", "a");
+		let request = Request::get("http://a.example.com/")
+			.header("host", "b.example.com")
+			.body(Body::empty())
+			.unwrap();
+		assert_eq!(router.route(&request), Some(0));
+
+		let replaced = router.host("A.example.com", "new a");
+		assert_eq!(route(&replaced, "a.example.com"), "new a");
+	}
+}
@@ -1,24 +1,119 @@
 #![warn(missing_docs)]
+#![allow(clippy::tabs_in_doc_comments)]
 
 //! Crate to route requests between a tonic gRPC service, and some other service
 //!
 //! The [Multiplexer] struct implements Service<Request<Body>>, and routes
 //! requests based on the Content-Type header.
-
-use std::{future::Future, task::Poll};
+//!
+//! # Optional features
+//!
+//! - `tracing`: creates a span for each request, with the selected [Branch],
+//!   and emits events when an inner service fails and when a response stream ends.
+//! - `metrics`: records counters and histograms for each branch with the `metrics`
+//!   facade. See [describe_metrics] for the list of metrics.
+//! - `health`: serves the standard gRPC health checking service and `/healthz`,
+//!   with the readiness of the inner services. See [HealthHandle].
+//! - `transcoding`: serves the unary methods of the gRPC service as JSON, on the
+//!   routes of their `google.api.http` annotations. See [Transcoder].
+//! - `connect`: serves the gRPC service to clients of the Connect protocol.
+//!   See [ConnectProtocol].
+//! - `reflection`: serves gRPC server reflection for encoded file descriptor sets,
+//!   for tools like `grpcurl`. See [Reflection].
+//! - `compression`: compresses the responses of the web branch with gzip, brotli
+//!   or zstd, as accepted by the client. See [Compression].
+//! - `testing`: publishes the [testing] module, with fake services and an
+//!   in-memory server for tests.
+
+use std::{future::Future, net::SocketAddr, sync::Arc, task::Poll};
 
 use hyper::{body::HttpBody, Body, Request, Response};
 use pin_project::pin_project;
 use tower::Service;
 
-pub use make::MakeMultiplexer;
+pub use access_log::{
+	AccessLog, AccessLogFormat, AccessLogWriter, AccessRecord, CombinedLogFormat, JsonFormat,
+};
+pub use body_limit::BodyLimit;
+pub use boxed::BoxMultiplexer;
+pub use branch::{Branch, BranchBody, BranchFuture};
+pub use builder::Builder;
+pub use classifier::Classifier;
+#[cfg(feature = "compression")]
+pub use compression::Compression;
+#[cfg(feature = "connect")]
+pub use connect::{ConnectError, ConnectProtocol};
+pub use content_type::GrpcContentType;
+pub use cors::CorsPolicy;
+pub use drain::{DrainHandle, Drained};
+pub use fallback::{GrpcFallback, GrpcFallbackFuture, RecordedBody};
+#[cfg(feature = "health")]
+pub use health::{HealthHandle, ServingStatus};
+pub use host::{HostRouter, Lazy, LazyFuture, MakeHostRouter};
+pub use limit::ConcurrencyLimit;
+pub use make::{MakeMultiplexer, NoRemoteAddr, RemoteAddr};
+#[cfg(feature = "reflection")]
+pub use reflection::{Reflection, ReflectionError};
+pub use reload::{ReloadHandle, Reloadable};
+pub use timeout::Timeout;
+#[cfg(feature = "transcoding")]
+pub use transcoding::{Transcoder, TranscoderError};
+pub use websocket::WebSocketRouter;
+mod access_log;
+#[cfg(any(feature = "connect", feature = "transcoding"))]
+mod adapter;
+mod body_limit;
+mod boxed;
+mod branch;
+mod builder;
+mod classifier;
+#[cfg(feature = "compression")]
+mod compression;
+#[cfg(feature = "connect")]
+mod connect;
+mod content_type;
+mod cors;
+mod drain;
+mod fallback;
+mod grpc;
+#[cfg(feature = "health")]
+mod health;
+mod host;
+mod lifecycle;
+mod limit;
+mod local;
 mod make;
+#[cfg(feature = "metrics")]
+mod meter;
+#[cfg(feature = "reflection")]
+mod reflection;
+mod reload;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod timeout;
+#[cfg(feature = "tracing")]
+mod trace;
+#[cfg(feature = "transcoding")]
+mod transcoding;
+mod websocket;
+
+use builder::Config;
+use lifecycle::Lifecycle;
+use limit::{Admission, Lender};
+#[cfg(feature = "metrics")]
+pub use meter::describe_metrics;
 
 /// Service that routes to a gRPC service and other service
 ///
 /// This service checks the Content-Type header, and send all requests
 /// with `application/grpc` to the grpc service, and all other requests
-/// to the web service.
+/// to the web service. [GrpcContentType] selects stricter matching modes,
+/// and [Classifier] combines other conditions, like the HTTP version.
+///
+/// Requests that upgrade the connection, like WebSockets over HTTP/1.1 or
+/// HTTP/2 extended CONNECT, reach the web service with their `OnUpgrade`
+/// extension, so [hyper::upgrade::on] works there. See [WebSocketRouter] to
+/// send them to a dedicated service.
 ///
 /// # Examples:
 ///
@@ -78,8 +173,18 @@ mod make;
 /// # tokio_test::block_on(run()).unwrap();
 /// ```
 pub struct Multiplexer<Grpc, Web> {
-	grpc: Grpc,
-	web: Web,
+	grpc: Lender<Grpc>,
+	web: Lender<Web>,
+	config: Arc<Config>,
+	remote_addr: Option<SocketAddr>,
+	#[cfg(feature = "metrics")]
+	grpc_pending: meter::PendingTimer,
+	#[cfg(feature = "metrics")]
+	web_pending: meter::PendingTimer,
+	#[cfg(feature = "health")]
+	grpc_ready: bool,
+	#[cfg(feature = "health")]
+	web_ready: bool,
 }
 impl<Grpc, Web> Multiplexer<Grpc, Web>
 where
@@ -88,7 +193,57 @@ where
 {
 	///This function consumes two Services, and returns a Multiplexer
 	pub fn new(grpc: Grpc, web: Web) -> Self {
-		Multiplexer { grpc, web }
+		Self::with_config(grpc, web, Default::default())
+	}
+
+	pub(crate) fn with_config(grpc: Grpc, web: Web, config: Arc<Config>) -> Self {
+		Multiplexer {
+			grpc: Lender::new(grpc),
+			web: Lender::new(web),
+			config,
+			remote_addr: None,
+			#[cfg(feature = "metrics")]
+			grpc_pending: Default::default(),
+			#[cfg(feature = "metrics")]
+			web_pending: Default::default(),
+			#[cfg(feature = "health")]
+			grpc_ready: false,
+			#[cfg(feature = "health")]
+			web_ready: false,
+		}
+	}
+
+	/// Set the address of the client connected to this Multiplexer, to be used in access logs
+	pub fn with_remote_addr(mut self, remote_addr: SocketAddr) -> Self {
+		self.remote_addr = Some(remote_addr);
+		self
+	}
+}
+
+impl<Grpc: Clone, Web: Clone> Clone for Multiplexer<Grpc, Web> {
+	fn clone(&self) -> Self {
+		Multiplexer {
+			grpc: self.grpc.clone(),
+			web: self.web.clone(),
+			config: self.config.clone(),
+			remote_addr: self.remote_addr,
+			//The clone was not polled yet
+			#[cfg(feature = "metrics")]
+			grpc_pending: Default::default(),
+			#[cfg(feature = "metrics")]
+			web_pending: Default::default(),
+			#[cfg(feature = "health")]
+			grpc_ready: false,
+			#[cfg(feature = "health")]
+			web_ready: false,
+		}
+	}
+}
+
+impl Multiplexer<(), ()> {
+	/// Builder to create a Multiplexer or a [MakeMultiplexer] with optional features
+	pub fn builder() -> Builder {
+		Builder::new()
 	}
 }
 type BoxedError = Box<dyn std::error::Error + Send + Sync + 'static>;
@@ -105,21 +260,51 @@ where
 	//Inner errors can be converted to our error type
 	Grpc::Error: Into<BoxedError>,
 	Web::Error: Into<BoxedError>,
+	//Converted responses are read by the multiplexer
+	GrpcBody::Data: Into<hyper::body::Bytes>,
+	WebBody::Data: Into<hyper::body::Bytes>,
+	GrpcBody::Error: Into<BoxedError>,
+	WebBody::Error: Into<BoxedError>,
 {
-	type Response = Response<EncapsulatedBody<GrpcBody, WebBody>>;
+	type Response = Response<EncapsulatedBody<BranchBody<GrpcBody>, BranchBody<WebBody>>>;
 	///Generic error that can be moved between threads
 	type Error = BoxedError;
-	type Future = EncapsulatedFuture<Grpc::Future, Web::Future>;
+	type Future = EncapsulatedFuture<BranchFuture<Grpc, GrpcBody>, BranchFuture<Web, WebBody>>;
 
 	///Call inner services poll_ready, and propagate errors.
-	/// Only is ready if both are ready.
+	/// Only is ready if both are ready, or always with health, to answer the probes.
+	/// Not ready while a request waits for a slot of a [ConcurrencyLimit].
 	fn poll_ready(
 		&mut self,
 		cx: &mut std::task::Context<'_>,
 	) -> std::task::Poll<Result<(), Self::Error>> {
+		//A request that waits for a slot gives the service back once it called it
+		futures::ready!(self.grpc.poll_returned(cx));
+		futures::ready!(self.web.poll_returned(cx));
 		//There is no problem in calling poll_ready if is Ready, and the docs don't have any limitation on pending
-		let grpc = self.grpc.poll_ready(cx).map_err(to_boxed)?;
-		let web = self.web.poll_ready(cx).map_err(to_boxed)?;
+		let grpc = self.grpc.get_mut().poll_ready(cx);
+		#[cfg(feature = "metrics")]
+		self.grpc_pending.observe(Branch::Grpc, &grpc);
+		#[cfg(feature = "health")]
+		if let Some(health) = &self.config.health {
+			health.observe(Branch::Grpc, &grpc);
+		}
+		let web = self.web.get_mut().poll_ready(cx);
+		#[cfg(feature = "metrics")]
+		self.web_pending.observe(Branch::Web, &web);
+		#[cfg(feature = "health")]
+		if let Some(health) = &self.config.health {
+			health.observe(Branch::Web, &web);
+		}
+		//The other requests check the readiness of their own branch in call
+		#[cfg(feature = "health")]
+		if self.config.health.is_some() {
+			self.grpc_ready = matches!(grpc, Poll::Ready(Ok(())));
+			self.web_ready = matches!(web, Poll::Ready(Ok(())));
+			return Poll::Ready(Ok(()));
+		}
+		let grpc = grpc.map_err(to_boxed)?;
+		let web = web.map_err(to_boxed)?;
 		match (grpc, web) {
 			(Poll::Ready(_), Poll::Ready(_)) => Poll::Ready(Ok(())),
 			_ => Poll::Pending,
@@ -127,15 +312,137 @@ where
 	}
 
 	fn call(&mut self, req: Request<Body>) -> Self::Future {
-		let is_grpc = req
-			.headers()
-			.get("content-type")
-			.map(|x| x.as_bytes().starts_with(b"application/grpc"))
-			.unwrap_or_default();
-		if is_grpc {
-			EncapsulatedFuture::Grpc(self.grpc.call(req))
-		} else {
-			EncapsulatedFuture::Web(self.web.call(req))
+		let classified = match &self.config.classifier {
+			Some(classifier) => Some(classifier.branch(&req)),
+			None => self.config.content_type.classify(req.headers()),
+		};
+		//Refused gRPC requests are answered on the gRPC branch
+		let mut branch = classified.unwrap_or(Branch::Grpc);
+		//Converted requests have no content-length, the limit uses the original one
+		let content_length = body_limit::content_length(req.headers());
+		#[cfg(any(feature = "connect", feature = "transcoding"))]
+		let mut adapted = None;
+		#[cfg(any(feature = "connect", feature = "transcoding"))]
+		let req = match branch {
+			Branch::Web => match adapter::adapt(&self.config, req) {
+				Ok((req, call)) => {
+					branch = Branch::Grpc;
+					adapted = Some(call);
+					req
+				}
+				Err(req) => req,
+			},
+			Branch::Grpc => req,
+		};
+		let preflight = cors::preflight(&self.config, &req);
+		if let Some((preflight_branch, _)) = &preflight {
+			branch = *preflight_branch;
+		}
+		let mut lifecycle = Lifecycle::new(branch, &req, &self.config, self.remote_addr);
+		let _entered = lifecycle.enter();
+		#[cfg(any(feature = "connect", feature = "transcoding"))]
+		if let Some(adapted) = adapted {
+			lifecycle.set_adapted(adapted);
+		}
+		#[cfg(feature = "compression")]
+		if let (Some(compression), Branch::Web) = (&self.config.compression, branch) {
+			if let Some(negotiated) = compression.negotiate(req.headers()) {
+				lifecycle.set_compression(negotiated);
+			}
+		}
+		if classified.is_none() {
+			let response = local::ready(content_type::unsupported());
+			return EncapsulatedFuture::local(branch, response, lifecycle);
+		}
+		if let Some((_, response)) = preflight {
+			return EncapsulatedFuture::local(branch, local::ready(response), lifecycle);
+		}
+		if let Some(headers) = self
+			.config
+			.cors(branch)
+			.and_then(|policy| policy.response_headers(&req))
+		{
+			lifecycle.add_response_headers(headers);
+		}
+		if let Some(drain) = &self.config.drain {
+			match drain.accept(branch, &req) {
+				Ok(in_flight) => lifecycle.hold(in_flight),
+				Err(rejected) => {
+					return EncapsulatedFuture::local(branch, local::ready(rejected), lifecycle)
+				}
+			}
+		}
+		#[cfg(feature = "health")]
+		let req = match &self.config.health {
+			Some(health) => match health.serve(branch, req) {
+				Ok(local) => return EncapsulatedFuture::local(branch, local, lifecycle),
+				Err(req) => req,
+			},
+			None => req,
+		};
+		#[cfg(feature = "reflection")]
+		let req = match &self.config.reflection {
+			Some(reflection) if branch == Branch::Grpc => match reflection.serve(req) {
+				Ok(local) => return EncapsulatedFuture::local(branch, local, lifecycle),
+				Err(req) => req,
+			},
+			_ => req,
+		};
+		let req = match self.config.body_limit(branch) {
+			Some(limit) => match limit.apply(branch, req, content_length) {
+				Ok((req, exceeded)) => {
+					lifecycle.set_body_limit(exceeded);
+					req
+				}
+				Err(rejected) => {
+					return EncapsulatedFuture::local(branch, local::ready(rejected), lifecycle)
+				}
+			},
+			None => req,
+		};
+		if let Some(timeout) = self.config.timeout(branch) {
+			lifecycle.set_deadline(timeout.deadline(branch, &req));
+		}
+		//With health, poll_ready returns before the inner services are ready
+		#[cfg(feature = "health")]
+		let ready = match (&self.config.health, branch) {
+			(None, _) => true,
+			(Some(_), Branch::Grpc) => std::mem::take(&mut self.grpc_ready),
+			(Some(_), Branch::Web) => std::mem::take(&mut self.web_ready),
+		};
+		#[cfg(feature = "health")]
+		if !ready {
+			let response = local::ready(health::unavailable(branch));
+			return EncapsulatedFuture::local(branch, response, lifecycle);
+		}
+		let mut queued = None;
+		if let Some(limiter) = self.config.limiter(branch) {
+			match limiter.admit(branch) {
+				Admission::Acquired(permit) => lifecycle.hold_permit(permit),
+				Admission::Queued(acquire) => queued = Some(acquire),
+				Admission::Shed(shed) => {
+					return EncapsulatedFuture::local(branch, local::ready(shed), lifecycle)
+				}
+			}
+		}
+		//Requests that wait borrow the inner service until they call it
+		match (branch, queued) {
+			(Branch::Grpc, None) => {
+				let future = self.grpc.get_mut().call(req);
+				EncapsulatedFuture::Grpc(BranchFuture::new(future, lifecycle))
+			}
+			(Branch::Web, None) => {
+				let future = self.web.get_mut().call(req);
+				EncapsulatedFuture::Web(BranchFuture::new(future, lifecycle))
+			}
+			(Branch::Grpc, Some(queued)) => {
+				let service = self.grpc.lend();
+				EncapsulatedFuture::Grpc(BranchFuture::deferred(service, req, queued, lifecycle))
+			}
+			(Branch::Web, Some(queued)) => {
+				let service = self.web.lend();
+				EncapsulatedFuture::Web(BranchFuture::deferred(service, req, queued, lifecycle))
+			}
 		}
 	}
 }
@@ -152,6 +459,21 @@ pub enum EncapsulatedFuture<GrpcFuture, WebFuture> {
 	///Encapsulates a future from Web service
 	Web(#[pin] WebFuture),
 }
+impl<Grpc, Web, GrpcBody, WebBody>
+	EncapsulatedFuture<BranchFuture<Grpc, GrpcBody>, BranchFuture<Web, WebBody>>
+where
+	Grpc: Service<Request<Body>>,
+	Web: Service<Request<Body>>,
+{
+	/// Answer the request on the selected branch without calling its inner service
+	fn local(branch: Branch, local: local::LocalFuture, lifecycle: Lifecycle) -> Self {
+		match branch {
+			Branch::Grpc => EncapsulatedFuture::Grpc(BranchFuture::local(local, lifecycle)),
+			Branch::Web => EncapsulatedFuture::Web(BranchFuture::local(local, lifecycle)),
+		}
+	}
+}
+
 /// This implementation should map the response and the error from the inner futures
 ///
 /// The response has its body mapped to another enum, the enum should implement `HttpBody`
@@ -236,6 +558,20 @@ where
 			BodyProj::Web(body) => body.poll_trailers(cx).map_err(to_boxed),
 		}
 	}
+
+	fn is_end_stream(&self) -> bool {
+		match self {
+			EncapsulatedBody::Grpc(body) => body.is_end_stream(),
+			EncapsulatedBody::Web(body) => body.is_end_stream(),
+		}
+	}
+
+	fn size_hint(&self) -> hyper::body::SizeHint {
+		match self {
+			EncapsulatedBody::Grpc(body) => body.size_hint(),
+			EncapsulatedBody::Web(body) => body.size_hint(),
+		}
+	}
 }
 
 #[cfg(test)]
@@ -0,0 +1,260 @@
+use std::{net::SocketAddr, task::Context};
+
+use hyper::{Body, HeaderMap, Request, Response};
+
+use crate::{
+	access_log::AccessEntry, body_limit::Exceeded, builder::Config, cors::ResponseHeaders,
+	drain::InFlight, limit::Permit, local::LocalBody, timeout::Deadline, BoxedError, Branch,
+};
+
+#[cfg(any(feature = "connect", feature = "transcoding"))]
+use crate::adapter::Adapted;
+#[cfg(feature = "compression")]
+use crate::compression::Negotiated;
+#[cfg(feature = "metrics")]
+use crate::meter::RequestMeter;
+#[cfg(feature = "tracing")]
+use crate::trace::RequestSpan;
+
+/// How a request ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Outcome {
+	///The response body was streamed until the end
+	Completed,
+	///The inner future or body returned an error
+	Failed,
+	///The future or the body was dropped before the end
+	Cancelled,
+}
+
+/// State of a request, from [Multiplexer::call][tower::Service::call] until the end of its response body
+///
+/// [on_end][Lifecycle::on_end] is called exactly once, when the body ends, or
+/// when the future or the body is dropped.
+pub(crate) struct Lifecycle {
+	grpc_status: Option<i32>,
+	data_end: bool,
+	failed: bool,
+	ended: bool,
+	access: Option<AccessEntry>,
+	in_flight: Option<InFlight>,
+	permit: Option<Permit>,
+	deadline: Option<Deadline>,
+	body_limit: Option<Exceeded>,
+	response_headers: Option<ResponseHeaders>,
+	#[cfg(any(feature = "connect", feature = "transcoding"))]
+	adapted: Option<Box<dyn Adapted>>,
+	#[cfg(feature = "compression")]
+	compression: Option<Negotiated>,
+	#[cfg(feature = "tracing")]
+	span: RequestSpan,
+	#[cfg(feature = "metrics")]
+	meter: RequestMeter,
+}
+
+/// Guard returned by [Lifecycle::enter]
+pub(crate) struct Entered {
+	#[cfg(feature = "tracing")]
+	_span: tracing::span::EnteredSpan,
+}
+
+impl Lifecycle {
+	pub(crate) fn new(
+		branch: Branch,
+		request: &Request<Body>,
+		config: &Config,
+		remote_addr: Option<SocketAddr>,
+	) -> Self {
+		Lifecycle {
+			grpc_status: None,
+			data_end: false,
+			failed: false,
+			ended: false,
+			access: config
+				.access_log
+				.as_ref()
+				.map(|log| AccessEntry::new(log, branch, request, remote_addr)),
+			in_flight: None,
+			permit: None,
+			deadline: None,
+			body_limit: None,
+			response_headers: None,
+			#[cfg(any(feature = "connect", feature = "transcoding"))]
+			adapted: None,
+			#[cfg(feature = "compression")]
+			compression: None,
+			#[cfg(feature = "tracing")]
+			span: RequestSpan::new(branch, request),
+			#[cfg(feature = "metrics")]
+			meter: RequestMeter::new(branch),
+		}
+	}
+
+	/// Enter the context of this request, while polling its future or body
+	pub(crate) fn enter(&self) -> Entered {
+		Entered {
+			#[cfg(feature = "tracing")]
+			_span: self.span.enter(),
+		}
+	}
+
+	/// Count the request as in flight until it ends
+	pub(crate) fn hold(&mut self, in_flight: InFlight) {
+		self.in_flight = Some(in_flight);
+	}
+
+	/// Hold a slot of the concurrency limit until the request ends
+	pub(crate) fn hold_permit(&mut self, permit: Permit) {
+		self.permit = Some(permit);
+	}
+
+	/// Time out the request at `deadline`
+	pub(crate) fn set_deadline(&mut self, deadline: Deadline) {
+		self.deadline = Some(deadline);
+	}
+
+	/// Branch of the request if its deadline expired
+	pub(crate) fn poll_deadline(&mut self, cx: &mut Context<'_>, in_body: bool) -> Option<Branch> {
+		self.deadline.as_mut()?.poll_expired(cx, in_body)
+	}
+
+	/// Add `headers` to the response
+	pub(crate) fn add_response_headers(&mut self, headers: ResponseHeaders) {
+		self.response_headers = Some(headers);
+	}
+
+	/// Headers to add to the response, taken once
+	pub(crate) fn take_response_headers(&mut self) -> Option<ResponseHeaders> {
+		self.response_headers.take()
+	}
+
+	/// Convert the response of a request converted from another protocol
+	#[cfg(any(feature = "connect", feature = "transcoding"))]
+	pub(crate) fn set_adapted(&mut self, adapted: Box<dyn Adapted>) {
+		self.adapted = Some(adapted);
+	}
+
+	/// State to convert the response, while its body is buffered
+	#[cfg(any(feature = "connect", feature = "transcoding"))]
+	pub(crate) fn adapted_mut(&mut self) -> Option<&mut (dyn Adapted + 'static)> {
+		self.adapted.as_deref_mut()
+	}
+
+	/// State to convert the response back to the protocol of the request, taken once
+	#[cfg(any(feature = "connect", feature = "transcoding"))]
+	pub(crate) fn take_adapted(&mut self) -> Option<Box<dyn Adapted>> {
+		self.adapted.take()
+	}
+
+	/// Compress the response with the encoding negotiated for the request
+	#[cfg(feature = "compression")]
+	pub(crate) fn set_compression(&mut self, negotiated: Negotiated) {
+		self.compression = Some(negotiated);
+	}
+
+	/// Encoding negotiated for the request, taken once
+	#[cfg(feature = "compression")]
+	pub(crate) fn take_compression(&mut self) -> Option<Negotiated> {
+		self.compression.take()
+	}
+
+	/// Track if the request body exceeds its limit
+	pub(crate) fn set_body_limit(&mut self, exceeded: Exceeded) {
+		self.body_limit = Some(exceeded);
+	}
+
+	/// Response that replaces the response of the inner service, if the request body exceeded its limit
+	pub(crate) fn body_limit_response(&self) -> Option<Response<LocalBody>> {
+		let exceeded = self.body_limit.as_ref()?;
+		exceeded.is_exceeded().then(|| exceeded.response())
+	}
+
+	/// gRPC trailers that replace the trailers of the inner service, if the request body exceeded its limit
+	pub(crate) fn body_limit_trailers(&self) -> Option<HeaderMap> {
+		let exceeded = self.body_limit.as_ref()?;
+		exceeded.is_exceeded().then(|| exceeded.trailers())?
+	}
+
+	/// Error to return if the request in flight was aborted
+	pub(crate) fn poll_aborted(&mut self, cx: &mut Context<'_>) -> Option<BoxedError> {
+		self.in_flight.as_mut()?.poll_aborted(cx)
+	}
+
+	pub(crate) fn on_response<B>(&mut self, response: &Response<B>) {
+		//Trailers-only responses carry the grpc-status in the headers
+		if let Some(grpc_status) = parse_grpc_status(response.headers()) {
+			self.grpc_status = Some(grpc_status);
+		}
+		#[cfg(feature = "tracing")]
+		self.span.on_response(response.status(), self.grpc_status);
+		if let Some(access) = &mut self.access {
+			access.on_response(response.status());
+		}
+		#[cfg(feature = "metrics")]
+		self.meter.on_response();
+	}
+
+	pub(crate) fn on_data(&mut self, data: &hyper::body::Bytes) {
+		if let Some(access) = &mut self.access {
+			access.on_data(data.len());
+		}
+		#[cfg(feature = "metrics")]
+		self.meter.on_data(data.len());
+	}
+
+	pub(crate) fn on_data_end(&mut self) {
+		self.data_end = true;
+	}
+
+	pub(crate) fn on_trailers(&mut self, trailers: Option<&HeaderMap>) {
+		if let Some(grpc_status) = trailers.and_then(parse_grpc_status) {
+			self.grpc_status = Some(grpc_status);
+		}
+		self.data_end = true;
+		self.on_end();
+	}
+
+	#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+	pub(crate) fn on_error(&mut self, error: &BoxedError) {
+		self.failed = true;
+		#[cfg(feature = "tracing")]
+		self.span.on_error(error);
+		self.on_end();
+	}
+
+	fn outcome(&self) -> Outcome {
+		if self.failed {
+			Outcome::Failed
+		} else if self.data_end {
+			Outcome::Completed
+		} else {
+			Outcome::Cancelled
+		}
+	}
+
+	fn on_end(&mut self) {
+		if self.ended {
+			return;
+		}
+		self.ended = true;
+		self.permit = None;
+		let outcome = self.outcome();
+		if let Some(access) = &mut self.access {
+			access.on_end(outcome == Outcome::Completed, self.grpc_status);
+		}
+		#[cfg(feature = "tracing")]
+		self.span.on_end(outcome, self.grpc_status);
+		#[cfg(feature = "metrics")]
+		self.meter.on_end(self.grpc_status);
+	}
+}
+
+impl Drop for Lifecycle {
+	fn drop(&mut self) {
+		self.on_end();
+	}
+}
+
+fn parse_grpc_status(headers: &HeaderMap) -> Option<i32> {
+	headers.get("grpc-status")?.to_str().ok()?.parse().ok()
+}
@@ -0,0 +1,337 @@
+use std::{
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc, Mutex, PoisonError,
+	},
+	task::{Context, Poll},
+};
+
+use futures::{future::BoxFuture, task::AtomicWaker};
+use hyper::{Response, StatusCode};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, TryAcquireError};
+
+use crate::{
+	grpc::{self, code},
+	local::{self, LocalBody},
+	Branch,
+};
+
+/// Maximum number of requests in flight on one branch of a [Multiplexer][crate::Multiplexer]
+///
+/// A request is in flight from the call to the inner service until its response
+/// body ends, or is dropped. The limit is shared by every Multiplexer built by
+/// the same [Builder][crate::Builder].
+///
+/// When the limit is reached, requests are shed: gRPC calls get the
+/// `RESOURCE_EXHAUSTED` status, and web requests get `503 Service Unavailable`.
+/// With [queue][ConcurrencyLimit::queue], up to that many requests wait for a
+/// slot before being shed. A queued request is kept until it gets a slot, and
+/// only then sent to the inner service. The Multiplexer is not ready while one
+/// of its requests waits, so the connection waits with it.
+///
+/// # Examples:
+/// ```
+/// use multiplex_tonic_hyper::{Branch, ConcurrencyLimit, Multiplexer};
+/// # use hyper::{service::service_fn, Body, Request, Response};
+/// # async fn handle(_: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+/// # 	Ok(Response::new(Body::empty()))
+/// # }
+/// # let grpc = service_fn(handle);
+/// # let web = service_fn(handle);
+///
+/// let multiplexer = Multiplexer::builder()
+/// 	.concurrency_limit(Branch::Grpc, ConcurrencyLimit::new(100).queue(50))
+/// 	.concurrency_limit(Branch::Web, ConcurrencyLimit::new(20))
+/// 	.build(grpc, web);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConcurrencyLimit {
+	max_in_flight: usize,
+	max_queued: usize,
+}
+
+impl ConcurrencyLimit {
+	/// Allow `max_in_flight` requests, and shed the others
+	pub fn new(max_in_flight: usize) -> Self {
+		ConcurrencyLimit {
+			max_in_flight,
+			max_queued: 0,
+		}
+	}
+
+	/// Wait for a slot with up to `max_queued` requests, before shedding
+	pub fn queue(mut self, max_queued: usize) -> Self {
+		self.max_queued = max_queued;
+		self
+	}
+}
+
+/// State of a [ConcurrencyLimit], shared by the Multiplexers of one Builder
+#[derive(Clone)]
+pub(crate) struct Limiter {
+	semaphore: Arc<Semaphore>,
+	queued: Arc<AtomicUsize>,
+	max_queued: usize,
+}
+
+/// Decision of a [Limiter] for a new request
+pub(crate) enum Admission {
+	Acquired(Permit),
+	Queued(BoxFuture<'static, Permit>),
+	Shed(Response<LocalBody>),
+}
+
+/// Slot of a request in flight
+pub(crate) type Permit = OwnedSemaphorePermit;
+
+impl Limiter {
+	pub(crate) fn new(limit: ConcurrencyLimit) -> Self {
+		Limiter {
+			semaphore: Arc::new(Semaphore::new(limit.max_in_flight)),
+			queued: Default::default(),
+			max_queued: limit.max_queued,
+		}
+	}
+
+	pub(crate) fn admit(&self, branch: Branch) -> Admission {
+		match self.semaphore.clone().try_acquire_owned() {
+			Ok(permit) => return Admission::Acquired(permit),
+			Err(TryAcquireError::Closed) => unreachable!("the semaphore is never closed"),
+			Err(TryAcquireError::NoPermits) => {}
+		}
+		let queued = self.queued.fetch_add(1, Ordering::AcqRel);
+		let guard = Queued(self.queued.clone());
+		if queued >= self.max_queued {
+			drop(guard);
+			return Admission::Shed(shed(branch));
+		}
+		let semaphore = self.semaphore.clone();
+		Admission::Queued(Box::pin(async move {
+			let _guard = guard;
+			semaphore
+				.acquire_owned()
+				.await
+				.expect("the semaphore is never closed")
+		}))
+	}
+}
+
+/// Counts a request waiting for a slot, until it gets one or is dropped
+struct Queued(Arc<AtomicUsize>);
+
+impl Drop for Queued {
+	fn drop(&mut self) {
+		self.0.fetch_sub(1, Ordering::AcqRel);
+	}
+}
+
+/// Inner service of a branch, that can be lent to a request waiting for a slot
+///
+/// The request gives the service back once it has called it, or when it is dropped.
+pub(crate) struct Lender<S> {
+	service: Option<S>,
+	home: Arc<Home<S>>,
+}
+
+/// Where a lent service is given back
+struct Home<S> {
+	state: Mutex<Returned<S>>,
+	waker: AtomicWaker,
+}
+
+struct Returned<S> {
+	service: Option<S>,
+	//Lenders cloned while the service was lent, given a clone of it
+	clones: Vec<Arc<Home<S>>>,
+	clone: Option<fn(&S) -> S>,
+}
+
+/// Service lent by a [Lender], given back on drop
+pub(crate) struct Loan<S> {
+	service: Option<S>,
+	home: Arc<Home<S>>,
+}
+
+const LENT: &str = "the service is lent until poll_ready returns Ready";
+
+impl<S> Lender<S> {
+	pub(crate) fn new(service: S) -> Self {
+		Lender {
+			service: Some(service),
+			home: Home::empty(),
+		}
+	}
+
+	/// Wait until the service is given back, if it was lent
+	pub(crate) fn poll_returned(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+		if self.service.is_none() {
+			self.home.waker.register(cx.waker());
+			self.service = self.home.lock().service.take();
+		}
+		match self.service {
+			Some(_) => Poll::Ready(()),
+			None => Poll::Pending,
+		}
+	}
+
+	pub(crate) fn get_mut(&mut self) -> &mut S {
+		self.service.as_mut().expect(LENT)
+	}
+
+	pub(crate) fn lend(&mut self) -> Loan<S> {
+		Loan {
+			service: Some(self.service.take().expect(LENT)),
+			home: self.home.clone(),
+		}
+	}
+}
+
+impl<S: Clone> Clone for Lender<S> {
+	fn clone(&self) -> Self {
+		if let Some(service) = &self.service {
+			return Lender::new(service.clone());
+		}
+		let lender = Lender {
+			service: None,
+			home: Home::empty(),
+		};
+		let mut returned = self.home.lock();
+		match &returned.service {
+			Some(service) => lender.home.lock().service = Some(service.clone()),
+			//The clone gets its service when the lent one is given back
+			None => {
+				returned.clones.push(lender.home.clone());
+				returned.clone = Some(S::clone);
+			}
+		}
+		drop(returned);
+		lender
+	}
+}
+
+impl<S> Home<S> {
+	fn empty() -> Arc<Self> {
+		Arc::new(Home {
+			state: Mutex::new(Returned {
+				service: None,
+				clones: Vec::new(),
+				clone: None,
+			}),
+			waker: AtomicWaker::new(),
+		})
+	}
+
+	fn lock(&self) -> std::sync::MutexGuard<'_, Returned<S>> {
+		self.state.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	fn give_back(&self, service: S) {
+		let mut returned = self.lock();
+		if let Some(clone) = returned.clone {
+			for home in returned.clones.drain(..) {
+				home.give_back(clone(&service));
+			}
+		}
+		returned.service = Some(service);
+		drop(returned);
+		self.waker.wake();
+	}
+}
+
+impl<S> std::ops::Deref for Loan<S> {
+	type Target = S;
+
+	fn deref(&self) -> &S {
+		self.service
+			.as_ref()
+			.expect("the loan is given back on drop")
+	}
+}
+
+impl<S> std::ops::DerefMut for Loan<S> {
+	fn deref_mut(&mut self) -> &mut S {
+		self.service
+			.as_mut()
+			.expect("the loan is given back on drop")
+	}
+}
+
+impl<S> Drop for Loan<S> {
+	fn drop(&mut self) {
+		if let Some(service) = self.service.take() {
+			self.home.give_back(service);
+		}
+	}
+}
+
+fn shed(branch: Branch) -> Response<LocalBody> {
+	match branch {
+		Branch::Grpc => {
+			grpc::status_response(code::RESOURCE_EXHAUSTED, "too many concurrent requests")
+		}
+		Branch::Web => local::text(
+			StatusCode::SERVICE_UNAVAILABLE,
+			"too many concurrent requests\n",
+		),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::task::{Context, Poll};
+
+	use futures::{task::noop_waker_ref, FutureExt};
+
+	use super::{Admission, ConcurrencyLimit, Lender, Limiter};
+	use crate::Branch;
+
+	#[test]
+	fn sheds_when_full_without_queue() {
+		let limiter = Limiter::new(ConcurrencyLimit::new(1));
+		let permit = match limiter.admit(Branch::Grpc) {
+			Admission::Acquired(permit) => permit,
+			_ => panic!("the first request gets a slot"),
+		};
+		match limiter.admit(Branch::Grpc) {
+			Admission::Shed(response) => assert_eq!(response.headers()["grpc-status"], "8"),
+			_ => panic!("the second request is shed"),
+		}
+		drop(permit);
+		assert!(matches!(limiter.admit(Branch::Web), Admission::Acquired(_)));
+	}
+
+	#[test]
+	fn queues_up_to_the_bound() {
+		let limiter = Limiter::new(ConcurrencyLimit::new(1).queue(1));
+		let permit = match limiter.admit(Branch::Web) {
+			Admission::Acquired(permit) => permit,
+			_ => panic!("the first request gets a slot"),
+		};
+		let mut queued = match limiter.admit(Branch::Web) {
+			Admission::Queued(queued) => queued,
+			_ => panic!("the second request waits"),
+		};
+		match limiter.admit(Branch::Web) {
+			Admission::Shed(response) => assert_eq!(response.status(), 503),
+			_ => panic!("the third request is shed"),
+		}
+		assert!((&mut queued).now_or_never().is_none());
+		drop(permit);
+		assert!(queued.now_or_never().is_some());
+		assert!(matches!(limiter.admit(Branch::Web), Admission::Acquired(_)));
+	}
+
+	#[test]
+	fn lent_services_are_given_back_to_the_clones() {
+		let mut cx = Context::from_waker(noop_waker_ref());
+		let mut lender = Lender::new(String::from("service"));
+		let loan = lender.lend();
+		let mut clone = lender.clone();
+		assert!(lender.poll_returned(&mut cx).is_pending());
+		assert!(clone.poll_returned(&mut cx).is_pending());
+		drop(loan);
+		assert_eq!(lender.poll_returned(&mut cx), Poll::Ready(()));
+		assert_eq!(clone.poll_returned(&mut cx), Poll::Ready(()));
+		assert_eq!(clone.get_mut(), "service");
+	}
+}
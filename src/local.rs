@@ -0,0 +1,118 @@
+//! Responses generated by the multiplexer itself, instead of an inner service
+
+use std::{
+	pin::Pin,
+	task::{Context, Poll},
+};
+
+use futures::future::BoxFuture;
+use http_body::combinators::UnsyncBoxBody;
+use hyper::{
+	body::{Bytes, HttpBody},
+	header::CONTENT_TYPE,
+	HeaderMap, Response, StatusCode,
+};
+
+use crate::BoxedError;
+
+/// Body of a response generated by the multiplexer
+pub(crate) type LocalBody = UnsyncBoxBody<Bytes, BoxedError>;
+
+/// Future of a response generated by the multiplexer
+pub(crate) type LocalFuture = BoxFuture<'static, Result<Response<LocalBody>, BoxedError>>;
+
+/// Body with at most one chunk of data, followed by optional trailers
+pub(crate) struct Once {
+	data: Option<Bytes>,
+	trailers: Option<HeaderMap>,
+}
+
+impl Once {
+	pub(crate) fn new(data: impl Into<Bytes>, trailers: Option<HeaderMap>) -> Self {
+		let data: Bytes = data.into();
+		Once {
+			data: (!data.is_empty()).then_some(data),
+			trailers,
+		}
+	}
+
+	pub(crate) fn boxed(self) -> LocalBody {
+		UnsyncBoxBody::new(self)
+	}
+}
+
+impl HttpBody for Once {
+	type Data = Bytes;
+
+	type Error = BoxedError;
+
+	fn poll_data(
+		mut self: Pin<&mut Self>,
+		_cx: &mut Context<'_>,
+	) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+		Poll::Ready(self.data.take().map(Ok))
+	}
+
+	fn poll_trailers(
+		mut self: Pin<&mut Self>,
+		_cx: &mut Context<'_>,
+	) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+		Poll::Ready(Ok(self.trailers.take()))
+	}
+
+	fn is_end_stream(&self) -> bool {
+		self.data.is_none() && self.trailers.is_none()
+	}
+
+	fn size_hint(&self) -> hyper::body::SizeHint {
+		let len = self.data.as_ref().map_or(0, Bytes::len);
+		hyper::body::SizeHint::with_exact(len as u64)
+	}
+}
+
+/// Future that is ready with `response`
+pub(crate) fn ready(response: Response<LocalBody>) -> LocalFuture {
+	Box::pin(futures::future::ready(Ok(response)))
+}
+
+/// Plain text response, with the given status
+pub(crate) fn text(status: StatusCode, text: &'static str) -> Response<LocalBody> {
+	let mut response = Response::new(Once::new(text, None).boxed());
+	*response.status_mut() = status;
+	response
+		.headers_mut()
+		.insert(CONTENT_TYPE, "text/plain; charset=utf-8".parse().unwrap());
+	response
+}
+
+#[cfg(test)]
+mod tests {
+	use hyper::{body::HttpBody, HeaderMap, StatusCode};
+
+	use super::{text, Once};
+
+	#[tokio::test]
+	async fn once_yields_data_then_trailers() {
+		let mut trailers = HeaderMap::new();
+		trailers.insert("grpc-status", "0".parse().unwrap());
+		let mut body = Once::new("data", Some(trailers.clone()));
+		assert!(!body.is_end_stream());
+		assert_eq!(body.size_hint().exact(), Some(4));
+		assert_eq!(body.data().await.unwrap().unwrap(), "data");
+		assert!(body.data().await.is_none());
+		assert_eq!(body.trailers().await.unwrap(), Some(trailers));
+		assert!(body.is_end_stream());
+	}
+
+	#[tokio::test]
+	async fn text_sets_status_and_content_type() {
+		let response = text(StatusCode::SERVICE_UNAVAILABLE, "unavailable");
+		assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+		assert_eq!(
+			response.headers()["content-type"],
+			"text/plain; charset=utf-8"
+		);
+		let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		assert_eq!(body, "unavailable");
+	}
+}
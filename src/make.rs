@@ -1,4 +1,6 @@
 use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
 use std::task::Poll;
 
 use futures::future::Join;
@@ -6,30 +8,99 @@ use hyper::{Body, Request};
 use pin_project::pin_project;
 use tower::Service;
 
+use crate::builder::Config;
 use crate::to_boxed;
 use crate::BoxedError;
 use crate::Multiplexer;
 
+/// Gets the address of the client from the target of a MakeService
+///
+/// Implemented for closures that receive a reference to the target, like
+/// `|conn: &&AddrStream| conn.remote_addr()` with [hyper::Server].
+pub trait RemoteAddr<Target> {
+	/// Address of the client connected to `target`
+	fn remote_addr(&self, target: &Target) -> Option<SocketAddr>;
+}
+
+/// Default [RemoteAddr] of a [MakeMultiplexer], that does not know the address
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoRemoteAddr;
+
+impl<Target> RemoteAddr<Target> for NoRemoteAddr {
+	fn remote_addr(&self, _target: &Target) -> Option<SocketAddr> {
+		None
+	}
+}
+
+impl<Target, F> RemoteAddr<Target> for F
+where
+	F: Fn(&Target) -> SocketAddr,
+{
+	fn remote_addr(&self, target: &Target) -> Option<SocketAddr> {
+		Some(self(target))
+	}
+}
+
 /// A MakeService for [Multiplexer]
 ///
 /// This type is used when more than one Multiplexer instance is needed
-pub struct MakeMultiplexer<MakeGrpc, MakeWeb> {
+#[derive(Clone)]
+pub struct MakeMultiplexer<MakeGrpc, MakeWeb, Addr = NoRemoteAddr> {
 	make_grpc: MakeGrpc,
 	make_web: MakeWeb,
+	config: Arc<Config>,
+	remote_addr: Addr,
 }
 
 impl<MakeGrpc, MakeWeb> MakeMultiplexer<MakeGrpc, MakeWeb> {
 	/// Move two make services into a new MakeService for Multiplexer
 	pub fn new(make_grpc: MakeGrpc, make_web: MakeWeb) -> Self {
+		Self::with_config(make_grpc, make_web, Default::default())
+	}
+
+	pub(crate) fn with_config(make_grpc: MakeGrpc, make_web: MakeWeb, config: Arc<Config>) -> Self {
 		MakeMultiplexer {
 			make_grpc,
 			make_web,
+			config,
+			remote_addr: NoRemoteAddr,
+		}
+	}
+}
+
+impl<MakeGrpc, MakeWeb, Addr> MakeMultiplexer<MakeGrpc, MakeWeb, Addr> {
+	/// Get the address of the client of each connection with `remote_addr`
+	///
+	/// The address is set in every [Multiplexer] created, as with
+	/// [Multiplexer::with_remote_addr].
+	///
+	/// # Examples:
+	/// ```no_run
+	/// # use multiplex_tonic_hyper::MakeMultiplexer;
+	/// # use hyper::{server::conn::AddrStream, service::service_fn, Body, Request, Response};
+	/// # use tower::make::Shared;
+	/// # async fn handle(_: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+	/// # 	Ok(Response::new(Body::empty()))
+	/// # }
+	/// # let grpc = Shared::new(service_fn(handle));
+	/// # let web = Shared::new(service_fn(handle));
+	/// let make_multiplexer =
+	/// 	MakeMultiplexer::new(grpc, web).with_remote_addr(|conn: &&AddrStream| conn.remote_addr());
+	/// # let addr = "[::1]:0".parse().unwrap();
+	/// let server = hyper::Server::bind(&addr).serve(make_multiplexer);
+	/// ```
+	pub fn with_remote_addr<F>(self, remote_addr: F) -> MakeMultiplexer<MakeGrpc, MakeWeb, F> {
+		MakeMultiplexer {
+			make_grpc: self.make_grpc,
+			make_web: self.make_web,
+			config: self.config,
+			remote_addr,
 		}
 	}
 }
 
-impl<Grpc, Web, GrpcError, WebError, MakeGrpc, MakeWeb, Target> Service<Target>
-	for MakeMultiplexer<MakeGrpc, MakeWeb>
+impl<Grpc, Web, GrpcError, WebError, MakeGrpc, MakeWeb, Addr, Target> Service<Target>
+	for MakeMultiplexer<MakeGrpc, MakeWeb, Addr>
 where
 	MakeGrpc: Service<Target, Response = Grpc, Error = GrpcError>,
 	MakeWeb: Service<Target, Response = Web, Error = WebError>,
@@ -37,6 +108,7 @@ where
 	Web: Service<Request<Body>>,
 	GrpcError: Into<BoxedError>,
 	WebError: Into<BoxedError>,
+	Addr: RemoteAddr<Target>,
 	Target: Clone,
 {
 	type Response = Multiplexer<Grpc, Web>;
@@ -56,9 +128,15 @@ where
 	}
 
 	fn call(&mut self, req: Target) -> Self::Future {
+		let remote_addr = self.remote_addr.remote_addr(&req);
 		let make_grpc_future = self.make_grpc.call(req.clone());
 		let make_web_future = self.make_web.call(req);
-		MakeMultiplexerFuture::new(make_grpc_future, make_web_future)
+		MakeMultiplexerFuture::new(
+			make_grpc_future,
+			make_web_future,
+			self.config.clone(),
+			remote_addr,
+		)
 	}
 }
 
@@ -70,6 +148,8 @@ where
 {
 	#[pin]
 	inner: Join<MakeGrpcFuture, MakeWebFuture>,
+	config: Arc<Config>,
+	remote_addr: Option<SocketAddr>,
 }
 
 impl<MakeGrpcFuture, MakeWebFuture> MakeMultiplexerFuture<MakeGrpcFuture, MakeWebFuture>
@@ -77,10 +157,17 @@ where
 	MakeGrpcFuture: Future,
 	MakeWebFuture: Future,
 {
-	fn new(make_grpc_future: MakeGrpcFuture, make_web_future: MakeWebFuture) -> Self {
+	fn new(
+		make_grpc_future: MakeGrpcFuture,
+		make_web_future: MakeWebFuture,
+		config: Arc<Config>,
+		remote_addr: Option<SocketAddr>,
+	) -> Self {
 		let joined_future = futures::future::join(make_grpc_future, make_web_future);
 		MakeMultiplexerFuture {
 			inner: joined_future,
+			config,
+			remote_addr,
 		}
 	}
 }
@@ -98,10 +185,17 @@ where
 	type Output = Result<Multiplexer<Grpc, Web>, BoxedError>;
 
 	fn poll(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
-		let poll = self.project().inner.poll(cx);
+		let this = self.project();
+		let poll = this.inner.poll(cx);
 		if let Poll::Ready(output) = poll {
 			match output {
-				(Ok(grpc), Ok(web)) => Poll::Ready(Ok(Multiplexer::new(grpc, web))),
+				(Ok(grpc), Ok(web)) => {
+					let mut multiplexer = Multiplexer::with_config(grpc, web, this.config.clone());
+					if let Some(addr) = this.remote_addr {
+						multiplexer = multiplexer.with_remote_addr(*addr);
+					}
+					Poll::Ready(Ok(multiplexer))
+				}
 				(Err(grpc_error), _) => Poll::Ready(Err(grpc_error.into())),
 				(_, Err(web_error)) => Poll::Ready(Err(web_error.into())),
 			}
@@ -0,0 +1,93 @@
+use std::{task::Poll, time::Instant};
+
+use metrics::{counter, describe_counter, describe_histogram, histogram, Unit};
+
+use crate::Branch;
+
+const REQUESTS: &str = "multiplexer_requests_total";
+const READY_PENDING: &str = "multiplexer_ready_pending_seconds";
+const RESPONSE_LATENCY: &str = "multiplexer_response_latency_seconds";
+const BODY_BYTES: &str = "multiplexer_body_bytes_total";
+const GRPC_STATUS: &str = "multiplexer_grpc_status_total";
+
+/// Register the description and unit of every metric emitted by the multiplexer
+///
+/// The metrics are emitted with the `metrics` facade, so this should be called
+/// after installing a recorder. All metrics have a `branch` label, with the
+/// value `grpc` or `web`:
+///
+/// - `multiplexer_requests_total`: requests routed to each branch.
+/// - `multiplexer_ready_pending_seconds`: time an inner service stayed pending in `poll_ready`.
+/// - `multiplexer_response_latency_seconds`: time from the call until the response headers.
+/// - `multiplexer_body_bytes_total`: response body bytes streamed through the multiplexer.
+/// - `multiplexer_grpc_status_total`: gRPC status observed at the end of each response,
+///   with the status in the `code` label.
+pub fn describe_metrics() {
+	describe_counter!(REQUESTS, Unit::Count, "Requests routed to each branch");
+	describe_histogram!(
+		READY_PENDING,
+		Unit::Seconds,
+		"Time an inner service was pending in poll_ready"
+	);
+	describe_histogram!(
+		RESPONSE_LATENCY,
+		Unit::Seconds,
+		"Time until the inner service returned the response headers"
+	);
+	describe_counter!(BODY_BYTES, Unit::Bytes, "Response body bytes streamed");
+	describe_counter!(
+		GRPC_STATUS,
+		Unit::Count,
+		"gRPC status codes of finished responses"
+	);
+}
+
+/// Metrics of one request
+pub(crate) struct RequestMeter {
+	branch: Branch,
+	start: Instant,
+}
+
+impl RequestMeter {
+	pub(crate) fn new(branch: Branch) -> Self {
+		counter!(REQUESTS, "branch" => branch.as_str()).increment(1);
+		RequestMeter {
+			branch,
+			start: Instant::now(),
+		}
+	}
+
+	pub(crate) fn on_response(&self) {
+		histogram!(RESPONSE_LATENCY, "branch" => self.branch.as_str()).record(self.start.elapsed());
+	}
+
+	pub(crate) fn on_data(&self, len: usize) {
+		counter!(BODY_BYTES, "branch" => self.branch.as_str()).increment(len as u64);
+	}
+
+	pub(crate) fn on_end(&self, grpc_status: Option<i32>) {
+		if let Some(code) = grpc_status {
+			counter!(GRPC_STATUS, "branch" => self.branch.as_str(), "code" => code.to_string())
+				.increment(1);
+		}
+	}
+}
+
+/// Measures how long an inner service stays pending in `poll_ready`
+#[derive(Default)]
+pub(crate) struct PendingTimer {
+	since: Option<Instant>,
+}
+
+impl PendingTimer {
+	pub(crate) fn observe<T>(&mut self, branch: Branch, poll: &Poll<T>) {
+		match (poll, self.since) {
+			(Poll::Pending, None) => self.since = Some(Instant::now()),
+			(Poll::Ready(_), Some(since)) => {
+				histogram!(READY_PENDING, "branch" => branch.as_str()).record(since.elapsed());
+				self.since = None;
+			}
+			_ => {}
+		}
+	}
+}
@@ -0,0 +1,438 @@
+//! gRPC server reflection, for the services of some file descriptor sets
+
+use std::{
+	collections::HashSet,
+	fmt,
+	pin::Pin,
+	task::{Context, Poll},
+};
+
+use http_body::combinators::UnsyncBoxBody;
+use hyper::{
+	body::{Bytes, HttpBody},
+	header::{HeaderValue, CONTENT_TYPE},
+	Body, HeaderMap, Request, Response,
+};
+use prost::Message;
+use prost_reflect::{DescriptorPool, FileDescriptor};
+
+use crate::{
+	grpc::{self, code, Envelopes},
+	local::{self, LocalFuture},
+	BoxedError,
+};
+
+const SERVICE_NAME: &str = "grpc.reflection.v1alpha.ServerReflection";
+const SERVICE_PREFIX: &str = "/grpc.reflection.v1alpha.ServerReflection/";
+
+/// Descriptors served by the gRPC server reflection service
+///
+/// Enabled with [Builder::reflection][crate::Builder::reflection], it serves
+/// `grpc.reflection.v1alpha.ServerReflection` on the gRPC branch, without calling
+/// the gRPC service, so tools like `grpcurl` can list and describe the services.
+/// Request messages larger than 4 MiB end the call with `RESOURCE_EXHAUSTED`.
+///
+/// The descriptor sets must include their imports, like the descriptor sets
+/// written by `tonic_build` with `file_descriptor_set_path`.
+///
+/// # Examples:
+/// ```
+/// use multiplex_tonic_hyper::{Multiplexer, Reflection};
+/// use hello_world_tonic::hello_world::FILE_DESCRIPTOR_SET;
+/// # use hyper::{service::service_fn, Body, Request, Response};
+/// # async fn handle(_: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+/// # 	Ok(Response::new(Body::empty()))
+/// # }
+/// # let grpc = service_fn(handle);
+/// # let web = service_fn(handle);
+///
+/// let reflection = Reflection::from_descriptor_set(FILE_DESCRIPTOR_SET).unwrap();
+/// let multiplexer = Multiplexer::builder()
+/// 	.reflection(reflection)
+/// 	.build(grpc, web);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Reflection {
+	pool: DescriptorPool,
+}
+
+/// Error returned when a descriptor set is invalid
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReflectionError(String);
+
+impl fmt::Display for ReflectionError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
+impl std::error::Error for ReflectionError {}
+
+impl Reflection {
+	/// Serve the files of an encoded `FileDescriptorSet`
+	pub fn from_descriptor_set(bytes: &[u8]) -> Result<Self, ReflectionError> {
+		let pool =
+			DescriptorPool::decode(bytes).map_err(|error| ReflectionError(error.to_string()))?;
+		Ok(Reflection { pool })
+	}
+
+	/// Also serve the files of another encoded `FileDescriptorSet`
+	///
+	/// Files already added, like common imports, are skipped.
+	pub fn with_descriptor_set(mut self, bytes: &[u8]) -> Result<Self, ReflectionError> {
+		self.pool
+			.decode_file_descriptor_set(bytes)
+			.map_err(|error| ReflectionError(error.to_string()))?;
+		Ok(self)
+	}
+
+	/// Answer the request if it is for the reflection service, otherwise give it back
+	#[allow(clippy::result_large_err)]
+	pub(crate) fn serve(&self, request: Request<Body>) -> Result<LocalFuture, Request<Body>> {
+		let response = match request.uri().path().strip_prefix(SERVICE_PREFIX) {
+			Some("ServerReflectionInfo") => {
+				let body = ReflectionBody {
+					pool: self.pool.clone(),
+					requests: request.into_body(),
+					envelopes: Envelopes::default(),
+					status: None,
+				};
+				let mut response = Response::new(UnsyncBoxBody::new(body));
+				response
+					.headers_mut()
+					.insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+				response
+			}
+			Some(method) => {
+				let message = format!("unknown method {method}");
+				grpc::status_response(code::UNIMPLEMENTED, &message)
+			}
+			None => return Err(request),
+		};
+		Ok(local::ready(response))
+	}
+}
+
+/// Answers each request message of the stream as it arrives
+struct ReflectionBody {
+	pool: DescriptorPool,
+	requests: Body,
+	envelopes: Envelopes,
+	//Set when the stream ends
+	status: Option<(i32, String)>,
+}
+
+impl ReflectionBody {
+	fn end(
+		&mut self,
+		code: i32,
+		message: impl Into<String>,
+	) -> Poll<Option<Result<Bytes, BoxedError>>> {
+		self.status = Some((code, message.into()));
+		Poll::Ready(None)
+	}
+}
+
+impl HttpBody for ReflectionBody {
+	type Data = Bytes;
+
+	type Error = BoxedError;
+
+	fn poll_data(
+		self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+	) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+		let this = self.get_mut();
+		loop {
+			if this.status.is_some() {
+				return Poll::Ready(None);
+			}
+			if let Some(next) = this.envelopes.next() {
+				let (flags, message) = match next {
+					Ok(envelope) => envelope,
+					Err((code, message)) => return this.end(code, message),
+				};
+				if flags != 0 {
+					return this.end(
+						code::INVALID_ARGUMENT,
+						"compressed messages are not supported",
+					);
+				}
+				return match ServerReflectionRequest::decode(&message[..]) {
+					Ok(request) => {
+						let response = answer(&this.pool, request);
+						Poll::Ready(Some(Ok(grpc::encode_frame(0, &response.encode_to_vec()))))
+					}
+					Err(error) => this.end(code::INVALID_ARGUMENT, error.to_string()),
+				};
+			}
+			match futures::ready!(Pin::new(&mut this.requests).poll_data(cx)) {
+				Some(Ok(data)) => this.envelopes.push(&data),
+				Some(Err(error)) => return Poll::Ready(Some(Err(error.into()))),
+				None if this.envelopes.is_empty() => return this.end(code::OK, ""),
+				None => return this.end(code::INVALID_ARGUMENT, "incomplete message"),
+			}
+		}
+	}
+
+	fn poll_trailers(
+		mut self: Pin<&mut Self>,
+		_cx: &mut Context<'_>,
+	) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+		let trailers = self
+			.status
+			.take()
+			.map(|(code, message)| grpc::status_trailers(code, &message));
+		Poll::Ready(Ok(trailers))
+	}
+}
+
+fn answer(pool: &DescriptorPool, request: ServerReflectionRequest) -> ServerReflectionResponse {
+	let not_found = |what: &str, name: &str| (code::NOT_FOUND, format!("{what} {name} not found"));
+	let result = match &request.message_request {
+		Some(MessageRequest::FileByFilename(name)) => pool
+			.get_file_by_name(name)
+			.map(file_response)
+			.ok_or_else(|| not_found("file", name)),
+		Some(MessageRequest::FileContainingSymbol(symbol)) => find_symbol(pool, symbol)
+			.map(file_response)
+			.ok_or_else(|| not_found("symbol", symbol)),
+		Some(MessageRequest::FileContainingExtension(extension)) => pool
+			.get_message_by_name(&extension.containing_type)
+			.zip(u32::try_from(extension.extension_number).ok())
+			.and_then(|(message, number)| message.get_extension(number))
+			.map(|extension| file_response(extension.parent_file()))
+			.ok_or_else(|| {
+				let name = format!(
+					"{}({})",
+					extension.containing_type, extension.extension_number
+				);
+				not_found("extension", &name)
+			}),
+		Some(MessageRequest::AllExtensionNumbersOfType(name)) => pool
+			.get_message_by_name(name)
+			.map(|message| {
+				MessageResponse::AllExtensionNumbers(ExtensionNumberResponse {
+					base_type_name: message.full_name().to_owned(),
+					extension_number: message
+						.extensions()
+						.map(|extension| extension.number() as i32)
+						.collect(),
+				})
+			})
+			.ok_or_else(|| not_found("type", name)),
+		Some(MessageRequest::ListServices(_)) => {
+			let mut names: Vec<String> = pool
+				.services()
+				.map(|service| service.full_name().to_owned())
+				.collect();
+			if !names.iter().any(|name| name == SERVICE_NAME) {
+				names.push(SERVICE_NAME.to_owned());
+			}
+			let service = names
+				.into_iter()
+				.map(|name| ServiceResponse { name })
+				.collect();
+			Ok(MessageResponse::ListServices(ListServiceResponse {
+				service,
+			}))
+		}
+		None => Err((code::INVALID_ARGUMENT, "empty request".to_owned())),
+	};
+	let message_response = result.unwrap_or_else(|(error_code, error_message)| {
+		MessageResponse::Error(ErrorResponse {
+			error_code,
+			error_message,
+		})
+	});
+	ServerReflectionResponse {
+		valid_host: request.host.clone(),
+		original_request: Some(request),
+		message_response: Some(message_response),
+	}
+}
+
+/// File that defines a type, a service, an extension or a method
+fn find_symbol(pool: &DescriptorPool, symbol: &str) -> Option<FileDescriptor> {
+	let symbol = symbol.strip_prefix('.').unwrap_or(symbol);
+	pool.get_message_by_name(symbol)
+		.map(|message| message.parent_file())
+		.or_else(|| pool.get_enum_by_name(symbol).map(|e| e.parent_file()))
+		.or_else(|| pool.get_service_by_name(symbol).map(|s| s.parent_file()))
+		.or_else(|| pool.get_extension_by_name(symbol).map(|e| e.parent_file()))
+		.or_else(|| {
+			let (service, method) = symbol.rsplit_once('.')?;
+			let service = pool.get_service_by_name(service)?;
+			let found = service.methods().any(|m| m.name() == method);
+			found.then(|| service.parent_file())
+		})
+}
+
+/// The file, followed by the files it imports, directly or not
+fn file_response(file: FileDescriptor) -> MessageResponse {
+	let mut seen = HashSet::new();
+	let mut pending = vec![file];
+	let mut file_descriptor_proto = Vec::new();
+	while let Some(file) = pending.pop() {
+		if !seen.insert(file.name().to_owned()) {
+			continue;
+		}
+		file_descriptor_proto.push(file.file_descriptor_proto().encode_to_vec());
+		pending.extend(file.dependencies());
+	}
+	MessageResponse::FileDescriptors(FileDescriptorResponse {
+		file_descriptor_proto,
+	})
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ServerReflectionRequest {
+	#[prost(string, tag = "1")]
+	host: String,
+	#[prost(oneof = "MessageRequest", tags = "3, 4, 5, 6, 7")]
+	message_request: Option<MessageRequest>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+enum MessageRequest {
+	#[prost(string, tag = "3")]
+	FileByFilename(String),
+	#[prost(string, tag = "4")]
+	FileContainingSymbol(String),
+	#[prost(message, tag = "5")]
+	FileContainingExtension(ExtensionRequest),
+	#[prost(string, tag = "6")]
+	AllExtensionNumbersOfType(String),
+	#[prost(string, tag = "7")]
+	ListServices(String),
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ExtensionRequest {
+	#[prost(string, tag = "1")]
+	containing_type: String,
+	#[prost(int32, tag = "2")]
+	extension_number: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ServerReflectionResponse {
+	#[prost(string, tag = "1")]
+	valid_host: String,
+	#[prost(message, optional, tag = "2")]
+	original_request: Option<ServerReflectionRequest>,
+	#[prost(oneof = "MessageResponse", tags = "4, 5, 6, 7")]
+	message_response: Option<MessageResponse>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+enum MessageResponse {
+	#[prost(message, tag = "4")]
+	FileDescriptors(FileDescriptorResponse),
+	#[prost(message, tag = "5")]
+	AllExtensionNumbers(ExtensionNumberResponse),
+	#[prost(message, tag = "6")]
+	ListServices(ListServiceResponse),
+	#[prost(message, tag = "7")]
+	Error(ErrorResponse),
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct FileDescriptorResponse {
+	#[prost(bytes = "vec", repeated, tag = "1")]
+	file_descriptor_proto: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ExtensionNumberResponse {
+	#[prost(string, tag = "1")]
+	base_type_name: String,
+	#[prost(int32, repeated, tag = "2")]
+	extension_number: Vec<i32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ListServiceResponse {
+	#[prost(message, repeated, tag = "1")]
+	service: Vec<ServiceResponse>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ServiceResponse {
+	#[prost(string, tag = "1")]
+	name: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ErrorResponse {
+	#[prost(int32, tag = "1")]
+	error_code: i32,
+	#[prost(string, tag = "2")]
+	error_message: String,
+}
+
+#[cfg(test)]
+mod tests {
+	use hello_world_tonic::hello_world::FILE_DESCRIPTOR_SET;
+	use prost::Message;
+	use prost_reflect::DescriptorPool;
+
+	use super::{answer, MessageRequest, MessageResponse, ServerReflectionRequest};
+	use crate::grpc::code;
+
+	fn ask(request: MessageRequest) -> MessageResponse {
+		let pool = DescriptorPool::decode(FILE_DESCRIPTOR_SET).unwrap();
+		let request = ServerReflectionRequest {
+			host: String::new(),
+			message_request: Some(request),
+		};
+		answer(&pool, request).message_response.unwrap()
+	}
+
+	fn file_names(response: MessageResponse) -> Vec<String> {
+		let MessageResponse::FileDescriptors(response) = response else {
+			panic!("expected files, got {response:?}");
+		};
+		response
+			.file_descriptor_proto
+			.iter()
+			.map(|file| prost_reflect::prost_types::FileDescriptorProto::decode(&file[..]).unwrap())
+			.map(|file| file.name.unwrap())
+			.collect()
+	}
+
+	#[test]
+	fn lists_the_services_and_itself() {
+		let MessageResponse::ListServices(list) = ask(MessageRequest::ListServices(String::new()))
+		else {
+			panic!("expected a list");
+		};
+		let names: Vec<_> = list.service.into_iter().map(|s| s.name).collect();
+		assert_eq!(
+			names,
+			[
+				"helloworld.Greeter",
+				"grpc.reflection.v1alpha.ServerReflection"
+			]
+		);
+	}
+
+	#[test]
+	fn symbols_come_with_their_imports() {
+		for symbol in [
+			"helloworld.Greeter",
+			"helloworld.Greeter.SayHello",
+			".helloworld.HelloRequest",
+		] {
+			let files = file_names(ask(MessageRequest::FileContainingSymbol(symbol.into())));
+			assert_eq!(files[0], "helloworld.proto", "{symbol}");
+			assert!(files.iter().any(|name| name == "google/api/http.proto"));
+		}
+		let MessageResponse::Error(error) = ask(MessageRequest::FileContainingSymbol(
+			"helloworld.Nope".into(),
+		)) else {
+			panic!("expected an error");
+		};
+		assert_eq!(error.error_code, code::NOT_FOUND);
+	}
+}
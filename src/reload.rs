@@ -0,0 +1,282 @@
+use std::sync::{
+	atomic::{AtomicUsize, Ordering},
+	Arc, Mutex,
+};
+use std::task::Poll;
+
+use hyper::{Body, Request};
+use tower::{make::Shared, Service};
+
+use crate::{MakeMultiplexer, Multiplexer};
+
+/// Shared storage for the current version of a service
+struct Slot<S> {
+	generation: AtomicUsize,
+	service: Mutex<S>,
+}
+
+impl<S> Slot<S> {
+	fn new(service: S) -> Arc<Self> {
+		Arc::new(Slot {
+			generation: AtomicUsize::new(0),
+			service: Mutex::new(service),
+		})
+	}
+
+	fn store(&self, service: S) {
+		let mut current = self.service.lock().unwrap();
+		*current = service;
+		//Incremented while locked, so readers always see a generation that matches the service
+		self.generation.fetch_add(1, Ordering::Release);
+	}
+}
+
+/// Service that can be replaced at runtime through a [ReloadHandle]
+///
+/// Each clone keeps a local copy of the service, and only checks for a new
+/// version in [poll_ready][Service::poll_ready]. That way, a swap only affects
+/// requests that arrive after it, and the futures already returned by
+/// [call][Service::call] keep running on the old service.
+pub struct Reloadable<S> {
+	slot: Arc<Slot<S>>,
+	generation: usize,
+	service: S,
+}
+
+impl<S: Clone> Reloadable<S> {
+	fn new(slot: Arc<Slot<S>>) -> Self {
+		let (generation, service) = {
+			let service = slot.service.lock().unwrap();
+			(slot.generation.load(Ordering::Acquire), service.clone())
+		};
+		Reloadable {
+			slot,
+			generation,
+			service,
+		}
+	}
+
+	fn reload_if_changed(&mut self) {
+		if self.slot.generation.load(Ordering::Acquire) != self.generation {
+			let service = self.slot.service.lock().unwrap();
+			self.generation = self.slot.generation.load(Ordering::Acquire);
+			self.service = service.clone();
+		}
+	}
+}
+
+impl<S: Clone> Clone for Reloadable<S> {
+	fn clone(&self) -> Self {
+		Reloadable {
+			slot: self.slot.clone(),
+			generation: self.generation,
+			service: self.service.clone(),
+		}
+	}
+}
+
+impl<S, Request> Service<Request> for Reloadable<S>
+where
+	S: Service<Request> + Clone,
+{
+	type Response = S::Response;
+	type Error = S::Error;
+	type Future = S::Future;
+
+	///Swap to the newest service before checking if it is ready
+	fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+		self.reload_if_changed();
+		self.service.poll_ready(cx)
+	}
+
+	fn call(&mut self, req: Request) -> Self::Future {
+		//Always use the service that was polled, even if it was replaced after poll_ready
+		self.service.call(req)
+	}
+}
+
+/// Handle to replace the inner services of a reloadable [Multiplexer] or [MakeMultiplexer]
+///
+/// The handle can be cloned and moved to other tasks. Replacing a service
+/// affects every [Reloadable] created from the same constructor, including all
+/// connections of a [MakeMultiplexer].
+///
+/// # Examples:
+/// ```
+/// # async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+/// # use std::{convert::Infallible, future::{ready, Ready}};
+/// # use multiplex_tonic_hyper::Multiplexer;
+/// use hyper::{service::service_fn, Body, Request, Response};
+/// use tower::{Service, ServiceExt};
+/// type Reply = Ready<Result<Response<Body>, Infallible>>;
+///
+/// //Both versions of a service must have the same type
+/// fn reply(
+/// 	str: &'static str,
+/// ) -> impl Service<Request<Body>, Response = Response<Body>, Error = Infallible, Future = Reply> + Clone
+/// {
+/// 	service_fn(move |_| ready(Ok(Response::new(Body::from(str)))))
+/// }
+///
+/// let (mut multiplex, handle) = Multiplexer::reloadable(reply("gRPC"), reply("old web"));
+///
+/// handle.reload_web(reply("new web"));
+///
+/// multiplex.ready().await?;
+/// let response = multiplex.call(Request::new(Body::empty())).await?;
+/// let content = hyper::body::to_bytes(response.into_body()).await?;
+/// assert_eq!(content, "new web");
+/// # Ok(())
+/// # }
+/// # tokio_test::block_on(run()).unwrap();
+/// ```
+pub struct ReloadHandle<Grpc, Web> {
+	grpc: Arc<Slot<Grpc>>,
+	web: Arc<Slot<Web>>,
+}
+
+impl<Grpc, Web> ReloadHandle<Grpc, Web> {
+	/// Replace the gRPC service used for new requests
+	pub fn reload_grpc(&self, grpc: Grpc) {
+		self.grpc.store(grpc);
+	}
+
+	/// Replace the web service used for new requests
+	pub fn reload_web(&self, web: Web) {
+		self.web.store(web);
+	}
+}
+
+impl<Grpc, Web> Clone for ReloadHandle<Grpc, Web> {
+	fn clone(&self) -> Self {
+		ReloadHandle {
+			grpc: self.grpc.clone(),
+			web: self.web.clone(),
+		}
+	}
+}
+
+impl<Grpc, Web> Multiplexer<Reloadable<Grpc>, Reloadable<Web>>
+where
+	Grpc: Service<Request<Body>> + Clone,
+	Web: Service<Request<Body>> + Clone,
+{
+	/// Create a Multiplexer whose inner services can be replaced with the returned [ReloadHandle]
+	pub fn reloadable(grpc: Grpc, web: Web) -> (Self, ReloadHandle<Grpc, Web>) {
+		let handle = ReloadHandle {
+			grpc: Slot::new(grpc),
+			web: Slot::new(web),
+		};
+		let multiplexer = Multiplexer::new(
+			Reloadable::new(handle.grpc.clone()),
+			Reloadable::new(handle.web.clone()),
+		);
+		(multiplexer, handle)
+	}
+}
+
+impl<Grpc, Web> MakeMultiplexer<Shared<Reloadable<Grpc>>, Shared<Reloadable<Web>>>
+where
+	Grpc: Clone,
+	Web: Clone,
+{
+	/// Create a MakeMultiplexer that shares two reloadable services between all connections
+	///
+	/// Every [Multiplexer] created by this MakeService starts using the new
+	/// services as soon as they are replaced with the returned [ReloadHandle].
+	pub fn reloadable(grpc: Grpc, web: Web) -> (Self, ReloadHandle<Grpc, Web>) {
+		let handle = ReloadHandle {
+			grpc: Slot::new(grpc),
+			web: Slot::new(web),
+		};
+		let make_multiplexer = MakeMultiplexer::new(
+			Shared::new(Reloadable::new(handle.grpc.clone())),
+			Shared::new(Reloadable::new(handle.web.clone())),
+		);
+		(make_multiplexer, handle)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::convert::Infallible;
+	use std::future::{ready, Ready};
+
+	use hyper::{service::service_fn, Body, Request, Response};
+	use tower::{make::MakeService, Service, ServiceExt};
+
+	use crate::{MakeMultiplexer, Multiplexer};
+
+	type Reply = Ready<Result<Response<Body>, Infallible>>;
+
+	//Every call returns the same type, so a service can be replaced by another version
+	fn reply(
+		str: &'static str,
+	) -> impl Service<Request<Body>, Response = Response<Body>, Error = Infallible, Future = Reply> + Clone
+	{
+		service_fn(move |_| ready(Ok(Response::new(Body::from(str)))))
+	}
+
+	#[tokio::test]
+	async fn reload_web_is_used_by_new_requests() {
+		let (mut multiplex, handle) = Multiplexer::reloadable(reply("gRPC"), reply("old"));
+
+		multiplex.ready().await.unwrap();
+		let old = multiplex.call(Request::new(Body::empty()));
+
+		handle.reload_web(reply("new"));
+		multiplex.ready().await.unwrap();
+		let new = multiplex.call(Request::new(Body::empty()));
+
+		//The future created before the swap still finishes on the old service
+		let old = hyper::body::to_bytes(old.await.unwrap().into_body()).await;
+		let new = hyper::body::to_bytes(new.await.unwrap().into_body()).await;
+		assert_eq!(old.unwrap(), "old");
+		assert_eq!(new.unwrap(), "new");
+	}
+
+	#[tokio::test]
+	async fn reload_grpc_after_poll_ready_uses_polled_service() {
+		let (mut multiplex, handle) = Multiplexer::reloadable(reply("old"), reply("web"));
+		let grpc_request = || {
+			Request::builder()
+				.header(hyper::header::CONTENT_TYPE, "application/grpc")
+				.body(Body::empty())
+				.unwrap()
+		};
+
+		multiplex.ready().await.unwrap();
+		handle.reload_grpc(reply("new"));
+		//Was ready before the swap, so this call still goes to the old service
+		let response = multiplex.call(grpc_request()).await.unwrap();
+		let content = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		assert_eq!(content, "old");
+
+		multiplex.ready().await.unwrap();
+		let response = multiplex.call(grpc_request()).await.unwrap();
+		let content = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		assert_eq!(content, "new");
+	}
+
+	#[tokio::test]
+	async fn reload_affects_all_connections_of_make_multiplexer() {
+		let (mut make_multiplexer, handle) =
+			MakeMultiplexer::reloadable(reply("gRPC"), reply("old"));
+
+		let mut first = make_multiplexer.make_service(()).await.unwrap();
+		let mut second = make_multiplexer.make_service(()).await.unwrap();
+		handle.reload_web(reply("new"));
+
+		for multiplex in [&mut first, &mut second] {
+			let response = multiplex
+				.ready()
+				.await
+				.unwrap()
+				.call(Request::new(Body::empty()))
+				.await
+				.unwrap();
+			let content = hyper::body::to_bytes(response.into_body()).await.unwrap();
+			assert_eq!(content, "new");
+		}
+	}
+}
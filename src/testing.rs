@@ -0,0 +1,629 @@
+//! Fake services and an in-memory server, for tests of services built with this crate
+//!
+//! The fakes cover the cases of the inner services of a [Multiplexer][crate::Multiplexer]:
+//! services and make services that are ready, fail, or are ready later.
+//! [ScriptedService] answers with queued responses, while its readiness is
+//! controlled with a [Readiness] handle.
+//!
+//! [TestServer] serves a make service over in-memory connections, for hyper and
+//! tonic clients.
+
+use std::{
+	collections::VecDeque,
+	fmt,
+	future::{ready, Future},
+	io,
+	pin::Pin,
+	sync::{Arc, Mutex},
+	task::{Context, Poll, Waker},
+	thread,
+	time::Instant,
+};
+
+use hyper::{
+	body::HttpBody,
+	client::connect::{Connected, Connection},
+	server::conn::Http,
+	Body, HeaderMap, Method, Request, Response, Uri,
+};
+use tokio::{
+	io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf},
+	sync::mpsc,
+};
+use tower::Service;
+
+use crate::BoxedError;
+
+type BoxFuture<T, E> = Pin<Box<dyn Future<Output = Result<T, E>> + Send>>;
+
+/// Service that fails in every poll_ready
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ErrorService;
+
+impl ErrorService {
+	/// Error returned by poll_ready
+	pub fn get_err_string() -> String {
+		"This service always error".into()
+	}
+}
+
+impl Service<Request<Body>> for ErrorService {
+	type Response = Response<Body>;
+	type Error = String;
+	type Future = BoxFuture<Self::Response, Self::Error>;
+
+	fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), Self::Error>> {
+		Poll::Ready(Err(Self::get_err_string()))
+	}
+
+	fn call(&mut self, _req: Request<Body>) -> Self::Future {
+		Box::pin(ready(Ok(Response::new(Body::empty()))))
+	}
+}
+
+/// Service that is always ready, and answers with an empty response
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReadyService;
+
+impl Service<Request<Body>> for ReadyService {
+	type Response = Response<Body>;
+	type Error = hyper::Error;
+	type Future = BoxFuture<Self::Response, Self::Error>;
+
+	fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), Self::Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn call(&mut self, _req: Request<Body>) -> Self::Future {
+		Box::pin(ready(Ok(Response::new(Body::empty()))))
+	}
+}
+
+/// Service that answers with a body type other than [Body]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HttpBodyService;
+
+impl Service<Request<Body>> for HttpBodyService {
+	type Response = Response<http_body::Empty<&'static [u8]>>;
+	type Error = hyper::Error;
+	type Future = BoxFuture<Self::Response, Self::Error>;
+
+	fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), Self::Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn call(&mut self, _req: Request<Body>) -> Self::Future {
+		Box::pin(ready(Ok(Response::new(http_body::Empty::new()))))
+	}
+}
+
+/// Service that is only ready after an instant
+#[derive(Clone, Copy, Debug)]
+pub struct DelayedService {
+	ready_after: Instant,
+}
+
+impl DelayedService {
+	/// Service that is ready after `ready_after`
+	pub fn new(ready_after: Instant) -> Self {
+		DelayedService { ready_after }
+	}
+}
+
+impl Service<Request<Body>> for DelayedService {
+	type Response = Response<Body>;
+	type Error = hyper::Error;
+	type Future = BoxFuture<Self::Response, Self::Error>;
+
+	fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+		poll_after(self.ready_after, cx).map(Ok)
+	}
+
+	fn call(&mut self, _req: Request<Body>) -> Self::Future {
+		Box::pin(ready(Ok(Response::new(Body::empty()))))
+	}
+}
+
+/// Make service that fails in every poll_ready
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FailingMakeService;
+
+impl FailingMakeService {
+	/// Error returned by poll_ready
+	pub fn get_err_string() -> String {
+		"This service fails!".into()
+	}
+}
+
+impl<T> Service<T> for FailingMakeService {
+	type Response = ReadyService;
+	type Error = String;
+	type Future = BoxFuture<Self::Response, Self::Error>;
+
+	fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), Self::Error>> {
+		Poll::Ready(Err(Self::get_err_string()))
+	}
+
+	fn call(&mut self, _req: T) -> Self::Future {
+		Box::pin(ready(Ok(ReadyService)))
+	}
+}
+
+/// Make service that is always ready, and makes [ReadyService]s
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReadyMakeService;
+
+impl<T> Service<T> for ReadyMakeService {
+	type Response = ReadyService;
+	type Error = String;
+	type Future = BoxFuture<Self::Response, Self::Error>;
+
+	fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), Self::Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn call(&mut self, _req: T) -> Self::Future {
+		Box::pin(ready(Ok(ReadyService)))
+	}
+}
+
+/// Make service that is ready, but whose futures fail
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FailingFutureMakeService;
+
+impl FailingFutureMakeService {
+	/// Error returned by the futures
+	pub fn get_err_string() -> String {
+		"This service future fails!".into()
+	}
+}
+
+impl<T> Service<T> for FailingFutureMakeService {
+	type Response = ReadyService;
+	type Error = String;
+	type Future = BoxFuture<Self::Response, Self::Error>;
+
+	fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), Self::Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn call(&mut self, _req: T) -> Self::Future {
+		Box::pin(ready(Err(Self::get_err_string())))
+	}
+}
+
+/// Make service that is only ready after an instant
+#[derive(Clone, Copy, Debug)]
+pub struct DelayedMakeService {
+	ready_after: Instant,
+}
+
+impl DelayedMakeService {
+	/// Make service that is ready after `ready_after`
+	pub fn new(ready_after: Instant) -> Self {
+		DelayedMakeService { ready_after }
+	}
+}
+
+impl<T> Service<T> for DelayedMakeService {
+	type Response = ReadyService;
+	type Error = String;
+	type Future = BoxFuture<Self::Response, Self::Error>;
+
+	fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+		poll_after(self.ready_after, cx).map(Ok)
+	}
+
+	fn call(&mut self, _req: T) -> Self::Future {
+		Box::pin(ready(Ok(ReadyService)))
+	}
+}
+
+/// Ready after `ready_after`, waking the task from another thread
+///
+/// A thread is used instead of a timer, so it works without a runtime.
+fn poll_after(ready_after: Instant, cx: &mut Context) -> Poll<()> {
+	if Instant::now() >= ready_after {
+		return Poll::Ready(());
+	}
+	let waker = cx.waker().clone();
+	thread::spawn(move || {
+		let now = Instant::now();
+		if now < ready_after {
+			thread::sleep(ready_after - now);
+		}
+		waker.wake();
+	});
+	Poll::Pending
+}
+
+/// Readiness of a service, controlled by the test
+///
+/// Clones share the same state. Tasks waiting for a pending service are woken
+/// when it becomes ready or fails.
+#[derive(Clone, Default)]
+pub struct Readiness {
+	shared: Arc<Mutex<ReadinessState>>,
+}
+
+#[derive(Default)]
+struct ReadinessState {
+	pending: bool,
+	error: Option<String>,
+	wakers: Vec<Waker>,
+}
+
+impl Readiness {
+	/// Readiness that starts ready
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Make poll_ready return ready
+	pub fn set_ready(&self) {
+		self.update(false, None);
+	}
+
+	/// Make poll_ready return pending, until the readiness changes
+	pub fn set_pending(&self) {
+		self.update(true, None);
+	}
+
+	/// Make poll_ready return an error with `message`
+	pub fn fail(&self, message: impl Into<String>) {
+		self.update(false, Some(message.into()));
+	}
+
+	/// Poll the readiness, like [Service::poll_ready]
+	pub fn poll(&self, cx: &mut Context<'_>) -> Poll<Result<(), String>> {
+		let mut state = self.shared.lock().unwrap();
+		if let Some(error) = &state.error {
+			return Poll::Ready(Err(error.clone()));
+		}
+		if state.pending {
+			//A task polling again only needs to be woken once
+			if !state.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+				state.wakers.push(cx.waker().clone());
+			}
+			return Poll::Pending;
+		}
+		Poll::Ready(Ok(()))
+	}
+
+	fn update(&self, pending: bool, error: Option<String>) {
+		let wakers = {
+			let mut state = self.shared.lock().unwrap();
+			state.pending = pending;
+			state.error = error;
+			std::mem::take(&mut state.wakers)
+		};
+		wakers.into_iter().for_each(Waker::wake);
+	}
+}
+
+impl fmt::Debug for Readiness {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let state = self.shared.lock().unwrap();
+		f.debug_struct("Readiness")
+			.field("pending", &state.pending)
+			.field("error", &state.error)
+			.finish()
+	}
+}
+
+/// Method, URI and headers of a request received by a [ScriptedService]
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+	/// Method of the request
+	pub method: Method,
+	/// URI of the request
+	pub uri: Uri,
+	/// Headers of the request
+	pub headers: HeaderMap,
+}
+
+/// Service that answers with scripted responses, in order
+///
+/// When the script runs out, it answers with empty `200 OK` responses. Clones
+/// share the script, the recorded requests and the [Readiness].
+///
+/// # Examples:
+/// ```
+/// # async fn run() {
+/// use multiplex_tonic_hyper::{testing::ScriptedService, Multiplexer};
+/// use hyper::{Body, Request, Response, StatusCode};
+/// use tower::ServiceExt;
+///
+/// let web = ScriptedService::new().with_response(
+/// 	Response::builder()
+/// 		.status(StatusCode::NOT_FOUND)
+/// 		.body(Body::empty())
+/// 		.unwrap(),
+/// );
+/// let multiplexer = Multiplexer::new(ScriptedService::new(), web.clone());
+///
+/// let request = Request::get("/missing").body(Body::empty()).unwrap();
+/// let response = multiplexer.oneshot(request).await.unwrap();
+/// assert_eq!(response.status(), StatusCode::NOT_FOUND);
+/// assert_eq!(web.requests()[0].uri, "/missing");
+/// # }
+/// # tokio_test::block_on(run());
+/// ```
+#[derive(Clone, Default)]
+pub struct ScriptedService {
+	script: Arc<Mutex<Script>>,
+	readiness: Readiness,
+}
+
+#[derive(Default)]
+struct Script {
+	responses: VecDeque<Result<Response<Body>, String>>,
+	requests: Vec<RecordedRequest>,
+}
+
+impl ScriptedService {
+	/// Service without responses in its script, that is ready
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Add a response to the end of the script
+	pub fn with_response(self, response: Response<Body>) -> Self {
+		self.push(Ok(response));
+		self
+	}
+
+	/// Add an error to the end of the script, returned by the future of the call
+	pub fn with_error(self, message: impl Into<String>) -> Self {
+		self.push(Err(message.into()));
+		self
+	}
+
+	/// Add a response to the end of the script, after the service is shared
+	pub fn push(&self, response: Result<Response<Body>, String>) {
+		self.script.lock().unwrap().responses.push_back(response);
+	}
+
+	/// Requests received so far
+	pub fn requests(&self) -> Vec<RecordedRequest> {
+		self.script.lock().unwrap().requests.clone()
+	}
+
+	/// Handle that controls the readiness of this service
+	pub fn readiness(&self) -> Readiness {
+		self.readiness.clone()
+	}
+}
+
+impl fmt::Debug for ScriptedService {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let script = self.script.lock().unwrap();
+		f.debug_struct("ScriptedService")
+			.field("responses", &script.responses.len())
+			.field("requests", &script.requests)
+			.field("readiness", &self.readiness)
+			.finish()
+	}
+}
+
+impl Service<Request<Body>> for ScriptedService {
+	type Response = Response<Body>;
+	type Error = String;
+	type Future = BoxFuture<Self::Response, Self::Error>;
+
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		self.readiness.poll(cx)
+	}
+
+	fn call(&mut self, req: Request<Body>) -> Self::Future {
+		let mut script = self.script.lock().unwrap();
+		script.requests.push(RecordedRequest {
+			method: req.method().clone(),
+			uri: req.uri().clone(),
+			headers: req.headers().clone(),
+		});
+		let response = script
+			.responses
+			.pop_front()
+			.unwrap_or_else(|| Ok(Response::new(Body::empty())));
+		Box::pin(ready(response))
+	}
+}
+
+/// Server of a make service over in-memory connections
+///
+/// Each connection calls the make service with `()` as the target, and serves
+/// HTTP/1.1 and HTTP/2 with prior knowledge. The server runs in tasks of the
+/// current tokio runtime, until the [TestServer] and its connectors are dropped.
+///
+/// # Examples:
+/// ```
+/// # async fn run() {
+/// use multiplex_tonic_hyper::{testing::{ReadyMakeService, TestServer}, MakeMultiplexer};
+///
+/// let server = TestServer::new(MakeMultiplexer::new(ReadyMakeService, ReadyMakeService));
+/// let response = server
+/// 	.client()
+/// 	.get("http://test/".parse().unwrap())
+/// 	.await
+/// 	.unwrap();
+/// assert_eq!(response.status(), 200);
+///
+/// //With tonic, create a channel with the connector:
+/// //Endpoint::from_static("http://test").connect_with_connector(server.connector())
+/// # }
+/// # tokio::runtime::Runtime::new().unwrap().block_on(run());
+/// ```
+#[derive(Clone, Debug)]
+pub struct TestServer {
+	connector: InMemoryConnector,
+}
+
+impl TestServer {
+	/// Serve the services made by `make_service`
+	///
+	/// Must be called inside a tokio runtime.
+	pub fn new<M, S, B>(mut make_service: M) -> Self
+	where
+		M: Service<(), Response = S> + Send + 'static,
+		M::Future: Send,
+		M::Error: Into<BoxedError>,
+		S: Service<Request<Body>, Response = Response<B>> + Send + 'static,
+		S::Future: Send + 'static,
+		S::Error: Into<BoxedError>,
+		B: HttpBody + Send + 'static,
+		B::Data: Send,
+		B::Error: Into<BoxedError>,
+	{
+		let (sender, mut receiver) = mpsc::unbounded_channel::<DuplexStream>();
+		tokio::spawn(async move {
+			while let Some(io) = receiver.recv().await {
+				let ready = futures::future::poll_fn(|cx| make_service.poll_ready(cx)).await;
+				let service = match ready.map_err(Into::<BoxedError>::into) {
+					Ok(()) => make_service.call(()).await.map_err(Into::into),
+					Err(error) => Err(error),
+				};
+				//Dropping the connection is the only way to report the error
+				let Ok(service) = service else { continue };
+				tokio::spawn(Http::new().serve_connection(io, service));
+			}
+		});
+		TestServer {
+			connector: InMemoryConnector { sender },
+		}
+	}
+
+	/// Connector that opens connections to this server
+	pub fn connector(&self) -> InMemoryConnector {
+		self.connector.clone()
+	}
+
+	/// HTTP/1.1 client connected to this server, for any host
+	pub fn client(&self) -> hyper::Client<InMemoryConnector> {
+		hyper::Client::builder().build(self.connector())
+	}
+
+	/// HTTP/2 client connected to this server, for any host
+	pub fn http2_client(&self) -> hyper::Client<InMemoryConnector> {
+		hyper::Client::builder()
+			.http2_only(true)
+			.build(self.connector())
+	}
+}
+
+/// Connector of a [TestServer], for hyper and tonic clients
+///
+/// It connects to the server for every URI.
+#[derive(Clone, Debug)]
+pub struct InMemoryConnector {
+	sender: mpsc::UnboundedSender<DuplexStream>,
+}
+
+/// Size of the buffers of each direction of a connection
+const BUFFER_SIZE: usize = 64 * 1024;
+
+impl Service<Uri> for InMemoryConnector {
+	type Response = InMemoryStream;
+	type Error = io::Error;
+	type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn call(&mut self, _uri: Uri) -> Self::Future {
+		let (client, server) = tokio::io::duplex(BUFFER_SIZE);
+		let result = match self.sender.send(server) {
+			Ok(()) => Ok(InMemoryStream { inner: client }),
+			Err(_) => Err(io::Error::new(
+				io::ErrorKind::ConnectionRefused,
+				"the test server stopped",
+			)),
+		};
+		futures::future::ready(result)
+	}
+}
+
+/// Client side of an in-memory connection
+#[derive(Debug)]
+pub struct InMemoryStream {
+	inner: DuplexStream,
+}
+
+impl AsyncRead for InMemoryStream {
+	fn poll_read(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &mut ReadBuf<'_>,
+	) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.inner).poll_read(cx, buf)
+	}
+}
+
+impl AsyncWrite for InMemoryStream {
+	fn poll_write(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &[u8],
+	) -> Poll<io::Result<usize>> {
+		Pin::new(&mut self.inner).poll_write(cx, buf)
+	}
+
+	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.inner).poll_flush(cx)
+	}
+
+	fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.inner).poll_shutdown(cx)
+	}
+}
+
+impl Connection for InMemoryStream {
+	fn connected(&self) -> Connected {
+		Connected::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::task::Poll;
+
+	use futures::task::noop_waker_ref;
+	use hyper::{Body, Request, Response, StatusCode};
+	use tower::Service;
+
+	use super::{Readiness, ScriptedService};
+
+	#[test]
+	fn readiness_follows_the_handle() {
+		let readiness = Readiness::new();
+		let mut cx = std::task::Context::from_waker(noop_waker_ref());
+		assert_eq!(readiness.poll(&mut cx), Poll::Ready(Ok(())));
+		readiness.set_pending();
+		assert_eq!(readiness.clone().poll(&mut cx), Poll::Pending);
+		readiness.fail("down");
+		assert_eq!(readiness.poll(&mut cx), Poll::Ready(Err("down".into())));
+		readiness.set_ready();
+		assert_eq!(readiness.poll(&mut cx), Poll::Ready(Ok(())));
+	}
+
+	#[tokio::test]
+	async fn scripted_service_answers_in_order_and_records() {
+		let mut service = ScriptedService::new()
+			.with_response(
+				Response::builder()
+					.status(StatusCode::CREATED)
+					.body(Body::empty())
+					.unwrap(),
+			)
+			.with_error("failed");
+
+		let request = || Request::post("/items").body(Body::empty()).unwrap();
+		assert_eq!(service.call(request()).await.unwrap().status(), 201);
+		assert_eq!(service.call(request()).await.unwrap_err(), "failed");
+		assert_eq!(service.call(request()).await.unwrap().status(), 200);
+		let requests = service.requests();
+		assert_eq!(requests.len(), 3);
+		assert_eq!(requests[0].method, "POST");
+	}
+}
@@ -0,0 +1,165 @@
+use std::{
+	pin::Pin,
+	task::{Context, Poll},
+	time::Duration,
+};
+
+use futures::Future;
+use hyper::{Body, HeaderMap, Request, Response, StatusCode};
+use tokio::time::Sleep;
+
+use crate::{
+	grpc::{self, code},
+	local::{self, LocalBody},
+	BoxedError, Branch,
+};
+
+/// Maximum time to answer a request on one branch of a [Multiplexer][crate::Multiplexer]
+///
+/// The time starts when the Multiplexer is called. When it expires before the
+/// inner service returns a response, gRPC calls get the `DEADLINE_EXCEEDED`
+/// status, and web requests get `504 Gateway Timeout`.
+///
+/// On the gRPC branch, the `grpc-timeout` header sent by the client is honored,
+/// and the timeout is its maximum.
+///
+/// With [include_body][Timeout::include_body], response bodies still streaming
+/// when the time expires are also ended: gRPC streams end with the
+/// `DEADLINE_EXCEEDED` status in the trailers, and web bodies return an error.
+///
+/// Timeouts use the timer of the tokio runtime.
+///
+/// # Examples:
+/// ```
+/// use std::time::Duration;
+/// use multiplex_tonic_hyper::{Branch, Multiplexer, Timeout};
+/// # use hyper::{service::service_fn, Body, Request, Response};
+/// # async fn handle(_: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+/// # 	Ok(Response::new(Body::empty()))
+/// # }
+/// # let grpc = service_fn(handle);
+/// # let web = service_fn(handle);
+///
+/// let multiplexer = Multiplexer::builder()
+/// 	.timeout(Branch::Grpc, Timeout::new(Duration::from_secs(60)).include_body(true))
+/// 	.timeout(Branch::Web, Timeout::new(Duration::from_secs(10)))
+/// 	.build(grpc, web);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timeout {
+	duration: Duration,
+	include_body: bool,
+}
+
+impl Timeout {
+	/// Time out requests whose response takes longer than `duration`
+	pub fn new(duration: Duration) -> Self {
+		Timeout {
+			duration,
+			include_body: false,
+		}
+	}
+
+	/// Also end the response bodies still streaming when the time expires
+	pub fn include_body(mut self, include_body: bool) -> Self {
+		self.include_body = include_body;
+		self
+	}
+
+	pub(crate) fn deadline(&self, branch: Branch, request: &Request<Body>) -> Deadline {
+		let mut duration = self.duration;
+		if branch == Branch::Grpc {
+			let requested = request
+				.headers()
+				.get("grpc-timeout")
+				.and_then(|value| value.to_str().ok())
+				.and_then(grpc::parse_timeout);
+			if let Some(requested) = requested {
+				duration = duration.min(requested);
+			}
+		}
+		Deadline {
+			sleep: Box::pin(tokio::time::sleep(duration)),
+			branch,
+			include_body: self.include_body,
+		}
+	}
+}
+
+/// Timer of a request with a [Timeout]
+pub(crate) struct Deadline {
+	sleep: Pin<Box<Sleep>>,
+	branch: Branch,
+	include_body: bool,
+}
+
+impl Deadline {
+	/// Branch of the request if it expired, otherwise wake the task when it expires
+	///
+	/// While streaming the body, only expires if the body is included.
+	pub(crate) fn poll_expired(&mut self, cx: &mut Context<'_>, in_body: bool) -> Option<Branch> {
+		if in_body && !self.include_body {
+			return None;
+		}
+		match self.sleep.as_mut().poll(cx) {
+			Poll::Ready(()) => Some(self.branch),
+			Poll::Pending => None,
+		}
+	}
+}
+
+/// Response to a request that expired before the inner service answered
+pub(crate) fn response(branch: Branch) -> Response<LocalBody> {
+	match branch {
+		Branch::Grpc => grpc::status_response(code::DEADLINE_EXCEEDED, "request timed out"),
+		Branch::Web => local::text(StatusCode::GATEWAY_TIMEOUT, "request timed out\n"),
+	}
+}
+
+/// Trailers that end a gRPC stream that expired
+pub(crate) fn trailers() -> HeaderMap {
+	grpc::status_trailers(code::DEADLINE_EXCEEDED, "request timed out")
+}
+
+/// Error that ends a web body that expired
+pub(crate) fn error() -> BoxedError {
+	"response body timed out".into()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use hyper::{header::CONTENT_TYPE, Body, Request};
+
+	use super::Timeout;
+	use crate::Branch;
+
+	fn grpc_request(timeout: &str) -> Request<Body> {
+		Request::post("/helloworld.Greeter/SayHello")
+			.header(CONTENT_TYPE, "application/grpc")
+			.header("grpc-timeout", timeout)
+			.body(Body::empty())
+			.unwrap()
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn grpc_timeout_header_is_capped_by_the_maximum() {
+		let timeout = Timeout::new(Duration::from_secs(1));
+		let start = tokio::time::Instant::now();
+		let mut shorter = timeout.deadline(Branch::Grpc, &grpc_request("100m"));
+		let mut longer = timeout.deadline(Branch::Grpc, &grpc_request("5S"));
+		let mut web = timeout.deadline(Branch::Web, &grpc_request("100m"));
+
+		(&mut shorter.sleep).await;
+		assert_eq!(start.elapsed(), Duration::from_millis(100));
+		(&mut longer.sleep).await;
+		assert_eq!(start.elapsed(), Duration::from_secs(1));
+		(&mut web.sleep).await;
+		assert_eq!(
+			start.elapsed(),
+			Duration::from_secs(1),
+			"web ignores the header"
+		);
+	}
+}
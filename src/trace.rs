@@ -0,0 +1,62 @@
+use hyper::{Body, Request, StatusCode};
+use tracing::{field, span::EnteredSpan, Span};
+
+use crate::{grpc, lifecycle::Outcome, BoxedError, Branch};
+
+/// Span that follows one request through the multiplexer
+///
+/// Records the selected branch, the HTTP method and version, and the gRPC
+/// service and method parsed from the path.
+pub(crate) struct RequestSpan {
+	span: Span,
+}
+
+impl RequestSpan {
+	pub(crate) fn new(branch: Branch, request: &Request<Body>) -> Self {
+		let span = tracing::info_span!(
+			"multiplexer",
+			branch = branch.as_str(),
+			http.method = %request.method(),
+			http.version = ?request.version(),
+			http.status = field::Empty,
+			grpc.service = field::Empty,
+			grpc.method = field::Empty,
+			grpc.status = field::Empty,
+		);
+		if branch == Branch::Grpc {
+			if let Some((service, method)) = grpc::split_path(request.uri().path()) {
+				span.record("grpc.service", service);
+				span.record("grpc.method", method);
+			}
+		}
+		RequestSpan { span }
+	}
+
+	pub(crate) fn enter(&self) -> EnteredSpan {
+		self.span.clone().entered()
+	}
+
+	pub(crate) fn on_response(&self, status: StatusCode, grpc_status: Option<i32>) {
+		self.span.record("http.status", status.as_u16());
+		if let Some(grpc_status) = grpc_status {
+			self.span.record("grpc.status", grpc_status);
+		}
+	}
+
+	pub(crate) fn on_error(&self, error: &BoxedError) {
+		let _entered = self.span.enter();
+		tracing::warn!(error = %error, "inner service failed");
+	}
+
+	pub(crate) fn on_end(&self, outcome: Outcome, grpc_status: Option<i32>) {
+		let _entered = self.span.enter();
+		if let Some(grpc_status) = grpc_status {
+			self.span.record("grpc.status", grpc_status);
+		}
+		match outcome {
+			Outcome::Completed => tracing::debug!(grpc.status = grpc_status, "stream completed"),
+			Outcome::Cancelled => tracing::debug!("stream cancelled"),
+			Outcome::Failed => {}
+		}
+	}
+}
@@ -0,0 +1,631 @@
+use std::{
+	fmt,
+	sync::{Arc, Mutex},
+};
+
+use hyper::{
+	body::Bytes,
+	header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE},
+	http::response::Parts,
+	Body, HeaderMap, Method, Request, Response,
+};
+use prost::Message;
+use prost_reflect::{
+	DescriptorPool, DynamicMessage, FieldDescriptor, Kind, MessageDescriptor, MethodDescriptor,
+	Value,
+};
+use serde_json::{Map, Value as Json};
+
+use crate::{
+	adapter::{Adapted, Adapter, Converted},
+	grpc::{self, code, UnaryBody},
+	local::{LocalBody, Once},
+	BodyLimit, BoxedError,
+};
+
+/// Serves unary gRPC methods as JSON, on the routes of their `google.api.http` annotations
+///
+/// Web requests that match a route are converted to a gRPC request, and sent to
+/// the gRPC inner service. Requests that match no route go to the web service.
+///
+/// The request message is built from the JSON body, the variables of the path
+/// and the query parameters, as described in `google/api/http.proto`. The
+/// response message, or its `response_body` field, is returned as JSON once the
+/// call ended. Failed calls get the HTTP status that matches their gRPC status,
+/// also when it is in the trailers, and a body like `{"code":5,"message":"not found"}`.
+///
+/// Transcoded requests are on the gRPC [Branch][crate::Branch], and use its
+/// limits and timeouts. Streaming methods and compressed messages are not supported.
+///
+/// # Examples:
+/// ```
+/// use multiplex_tonic_hyper::{Multiplexer, Transcoder};
+/// # use hyper::{service::service_fn, Body, Request, Response};
+/// # async fn handle(_: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+/// # 	Ok(Response::new(Body::empty()))
+/// # }
+/// # let grpc = service_fn(handle);
+/// # let web = service_fn(handle);
+/// use hello_world_tonic::hello_world::FILE_DESCRIPTOR_SET;
+///
+/// let transcoder = Transcoder::from_descriptor_set(FILE_DESCRIPTOR_SET).unwrap();
+/// let multiplexer = Multiplexer::builder()
+/// 	.transcoding(transcoder)
+/// 	.build(grpc, web);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Transcoder {
+	routes: Vec<Route>,
+}
+
+/// Error returned when the descriptors or their annotations are invalid
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TranscoderError(String);
+
+impl fmt::Display for TranscoderError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
+impl std::error::Error for TranscoderError {}
+
+#[derive(Clone, Debug)]
+struct Route {
+	method: Method,
+	template: Template,
+	//None ignores the request body, "*" maps it to the whole message
+	body: Option<String>,
+	response_body: Option<String>,
+	//The path of the gRPC request, like /package.Service/Method
+	path: String,
+	descriptor: MethodDescriptor,
+}
+
+impl Transcoder {
+	/// Routes of the unary methods annotated in an encoded `FileDescriptorSet`
+	///
+	/// The set must include `google/api/annotations.proto` and its imports, like
+	/// the descriptor sets written by `tonic_build` with `file_descriptor_set_path`.
+	pub fn from_descriptor_set(bytes: &[u8]) -> Result<Self, TranscoderError> {
+		let pool =
+			DescriptorPool::decode(bytes).map_err(|error| TranscoderError(error.to_string()))?;
+		let http = pool
+			.get_extension_by_name("google.api.http")
+			.ok_or_else(|| {
+				TranscoderError("google.api.http is not in the descriptor set".into())
+			})?;
+		let mut routes = Vec::new();
+		for service in pool.services() {
+			for method in service.methods() {
+				if method.is_client_streaming() || method.is_server_streaming() {
+					continue;
+				}
+				let options = method.options();
+				if !options.has_extension(&http) {
+					continue;
+				}
+				let Value::Message(rule) = options.get_extension(&http).into_owned() else {
+					continue;
+				};
+				let bindings = match rule.get_field_by_name("additional_bindings").as_deref() {
+					Some(Value::List(bindings)) => bindings.clone(),
+					_ => Vec::new(),
+				};
+				let rules = std::iter::once(Value::Message(rule)).chain(bindings);
+				for rule in rules {
+					if let Value::Message(rule) = rule {
+						routes.extend(Route::new(&method, &rule)?);
+					}
+				}
+			}
+		}
+		Ok(Transcoder { routes })
+	}
+}
+
+impl Adapter for Transcoder {
+	/// Convert a request that matches a route to a gRPC request, reading at most `limit` of its body
+	fn adapt(
+		&self,
+		request: Request<Body>,
+		limit: Option<&BodyLimit>,
+	) -> Result<Converted, Request<Body>> {
+		let path = request.uri().path();
+		let Some((route, variables)) = self.routes.iter().find_map(|route| {
+			if route.method != request.method() {
+				return None;
+			}
+			Some((route, route.template.matches(path)?))
+		}) else {
+			return Err(request);
+		};
+		let query = request.uri().query().map(parse_query).unwrap_or_default();
+		let error = Arc::new(Mutex::new(None));
+		let (mut parts, body) = request.into_parts();
+		let input = route.descriptor.input();
+		let body_field = route.body.clone();
+		let slot = error.clone();
+		let max_bytes = limit.map_or(u64::MAX, |limit| limit.max_bytes(&route.path));
+		let message = async move {
+			let result = match grpc::read_limited(body, max_bytes).await {
+				Ok(body) => request_message(input, body_field.as_deref(), &body, variables, query)
+					.map_err(|error| (code::INVALID_ARGUMENT, error.to_string())),
+				Err(status) => Err(status),
+			};
+			match result {
+				Ok(message) => Ok(grpc::encode_frame(0, &message)),
+				Err((code, message)) => {
+					let error: BoxedError = message.clone().into();
+					*slot.lock().unwrap() = Some((code, message));
+					Err(error)
+				}
+			}
+		};
+		parts.method = Method::POST;
+		parts.uri = route.path.parse().expect("method paths are valid URIs");
+		parts.headers.remove(CONTENT_LENGTH);
+		parts
+			.headers
+			.insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+		parts
+			.headers
+			.insert("te", HeaderValue::from_static("trailers"));
+		let body = Body::wrap_stream(futures::stream::once(message));
+		let transcoded = Transcoded {
+			output: route.descriptor.output(),
+			response_body: route.response_body.clone(),
+			error,
+			unary: Default::default(),
+		};
+		Ok((Request::from_parts(parts, body), Box::new(transcoded)))
+	}
+}
+
+impl Route {
+	fn new(
+		method: &MethodDescriptor,
+		rule: &DynamicMessage,
+	) -> Result<Option<Self>, TranscoderError> {
+		let error = |message: String| TranscoderError(format!("{}: {message}", method.full_name()));
+		let string = |name: &str| match rule.get_field_by_name(name).as_deref() {
+			Some(Value::String(value)) if !value.is_empty() => Some(value.clone()),
+			_ => None,
+		};
+		let custom = match rule.get_field_by_name("custom").as_deref() {
+			Some(Value::Message(custom)) => {
+				let field = |name| match custom.get_field_by_name(name).as_deref() {
+					Some(Value::String(value)) => value.clone(),
+					_ => String::new(),
+				};
+				Some((field("kind"), field("path")))
+			}
+			_ => None,
+		};
+		let pattern = [
+			("get", Method::GET),
+			("put", Method::PUT),
+			("post", Method::POST),
+			("delete", Method::DELETE),
+			("patch", Method::PATCH),
+		]
+		.into_iter()
+		.find_map(|(name, http_method)| Some((http_method, string(name)?)));
+		let (http_method, path) = match (pattern, custom) {
+			(Some(pattern), _) => pattern,
+			(None, Some((kind, path))) if !path.is_empty() => {
+				let kind = Method::from_bytes(kind.as_bytes())
+					.map_err(|_| error(format!("invalid method {kind:?}")))?;
+				(kind, path)
+			}
+			_ => return Ok(None),
+		};
+		let template =
+			Template::parse(&path).map_err(|message| error(format!("{path:?}: {message}")))?;
+		let input = method.input();
+		for variable in &template.variables {
+			if find_field(&input, &variable.field).is_none() {
+				return Err(error(format!("unknown field {}", variable.field)));
+			}
+		}
+		let body = string("body");
+		if let Some(field) = body.as_deref().filter(|field| *field != "*") {
+			if input.get_field_by_name(field).is_none() {
+				return Err(error(format!("unknown body field {field}")));
+			}
+		}
+		let response_body = string("response_body");
+		if let Some(field) = &response_body {
+			if method.output().get_field_by_name(field).is_none() {
+				return Err(error(format!("unknown response field {field}")));
+			}
+		}
+		Ok(Some(Route {
+			method: http_method,
+			template,
+			body,
+			response_body,
+			path: format!("/{}/{}", method.parent_service().full_name(), method.name()),
+			descriptor: method.clone(),
+		}))
+	}
+}
+
+/// Path template of a route, like `/v1/{name=shelves/*}/books:list`
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Template {
+	segments: Vec<Segment>,
+	variables: Vec<Variable>,
+	verb: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+	Literal(String),
+	//Matches one segment
+	Any,
+	//Matches the remaining segments
+	Rest,
+}
+
+/// Field set by the segments `start..end` of a template
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Variable {
+	field: String,
+	start: usize,
+	end: usize,
+}
+
+impl Segment {
+	fn parse(segment: &str) -> Result<Self, &'static str> {
+		match segment {
+			"" => Err("empty segment"),
+			"*" => Ok(Segment::Any),
+			"**" => Ok(Segment::Rest),
+			_ if segment.contains(['{', '}', '=', '*']) => Err("invalid segment"),
+			_ => Ok(Segment::Literal(segment.to_owned())),
+		}
+	}
+}
+
+impl Template {
+	fn parse(template: &str) -> Result<Self, &'static str> {
+		let path = template.strip_prefix('/').ok_or("expected a leading /")?;
+		let (mut path, verb) = match path.rfind(':') {
+			Some(index) if !path[index..].contains(['/', '}']) => {
+				(&path[..index], Some(path[index + 1..].to_owned()))
+			}
+			_ => (path, None),
+		};
+		let mut segments = Vec::new();
+		let mut variables = Vec::new();
+		while !path.is_empty() {
+			if let Some(variable) = path.strip_prefix('{') {
+				let end = variable.find('}').ok_or("unclosed variable")?;
+				let (field, pattern) = variable[..end]
+					.split_once('=')
+					.unwrap_or((&variable[..end], "*"));
+				if field.is_empty() {
+					return Err("empty variable");
+				}
+				let start = segments.len();
+				for segment in pattern.split('/') {
+					segments.push(Segment::parse(segment)?);
+				}
+				variables.push(Variable {
+					field: field.to_owned(),
+					start,
+					end: segments.len(),
+				});
+				path = &variable[end + 1..];
+			} else {
+				let end = path.find('/').unwrap_or(path.len());
+				segments.push(Segment::parse(&path[..end])?);
+				path = &path[end..];
+			}
+			path = match path.strip_prefix('/') {
+				Some("") => return Err("empty segment"),
+				Some(next) => next,
+				None if path.is_empty() => path,
+				None => return Err("expected a / after a variable"),
+			};
+		}
+		if let Some((_, init)) = segments.split_last() {
+			if init.contains(&Segment::Rest) {
+				return Err("** must be the last segment");
+			}
+		}
+		Ok(Template {
+			segments,
+			variables,
+			verb,
+		})
+	}
+
+	/// Fields and values of the variables, if `path` matches
+	fn matches(&self, path: &str) -> Option<Vec<(String, String)>> {
+		let path = path.strip_prefix('/')?;
+		let path = match &self.verb {
+			Some(verb) => path.strip_suffix(verb.as_str())?.strip_suffix(':')?,
+			None => path,
+		};
+		let parts: Vec<&str> = match path {
+			"" => Vec::new(),
+			path => path.split('/').collect(),
+		};
+		//Index of the first part matched by each segment
+		let mut bounds = Vec::with_capacity(self.segments.len() + 1);
+		let mut index = 0;
+		for segment in &self.segments {
+			bounds.push(index);
+			match segment {
+				Segment::Literal(literal) if *parts.get(index)? == literal.as_str() => index += 1,
+				Segment::Literal(_) => return None,
+				Segment::Any if !parts.get(index)?.is_empty() => index += 1,
+				Segment::Any => return None,
+				Segment::Rest => index = parts.len(),
+			}
+		}
+		bounds.push(index);
+		if index != parts.len() {
+			return None;
+		}
+		let variables = self.variables.iter().map(|variable| {
+			let value = parts[bounds[variable.start]..bounds[variable.end]].join("/");
+			//Variables with more than one segment keep their reserved characters
+			let single = self.segments[variable.start..variable.end] == [Segment::Any];
+			(
+				variable.field.clone(),
+				grpc::percent_decode(&value, !single, false),
+			)
+		});
+		Some(variables.collect())
+	}
+}
+
+fn parse_query(query: &str) -> Vec<(String, String)> {
+	query
+		.split('&')
+		.filter(|pair| !pair.is_empty())
+		.map(|pair| {
+			let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+			(
+				grpc::percent_decode(key, false, true),
+				grpc::percent_decode(value, false, true),
+			)
+		})
+		.collect()
+}
+
+/// Field of `message` at a dotted path, like `book.author`
+fn find_field(message: &MessageDescriptor, path: &str) -> Option<FieldDescriptor> {
+	let (parents, name) = match path.rsplit_once('.') {
+		Some((parents, name)) => (Some(parents), name),
+		None => (None, path),
+	};
+	let message = match parents {
+		Some(parents) => match find_field(message, parents)?.kind() {
+			Kind::Message(message) => message,
+			_ => return None,
+		},
+		None => message.clone(),
+	};
+	message.get_field_by_name(name)
+}
+
+/// Encoded request message, from the body, the path variables and the query parameters
+fn request_message(
+	input: MessageDescriptor,
+	body_field: Option<&str>,
+	body: &[u8],
+	variables: Vec<(String, String)>,
+	query: Vec<(String, String)>,
+) -> Result<Vec<u8>, BoxedError> {
+	let mut json = Json::Object(Map::new());
+	match body_field {
+		Some(_) if body.is_empty() => {}
+		Some("*") => json = serde_json::from_slice(body)?,
+		Some(field) => {
+			object(&mut json)?.insert(field.to_owned(), serde_json::from_slice(body)?);
+		}
+		None => {}
+	}
+	for (path, value) in variables {
+		let field = find_field(&input, &path).ok_or("unknown field")?;
+		set_field(&mut json, &path, &field, value, false)?;
+	}
+	//With the whole message in the body, there are no query parameters
+	if body_field != Some("*") {
+		for (path, value) in query {
+			//Unknown parameters are ignored
+			if let Some(field) = find_field(&input, &path) {
+				set_field(&mut json, &path, &field, value, true)?;
+			}
+		}
+	}
+	let message = DynamicMessage::deserialize(input, json)?;
+	Ok(message.encode_to_vec())
+}
+
+fn object(json: &mut Json) -> Result<&mut Map<String, Json>, BoxedError> {
+	match json {
+		Json::Object(object) => Ok(object),
+		_ => Err("expected a JSON object".into()),
+	}
+}
+
+/// Set the JSON value of `field` at `path`, or append it to a repeated field
+fn set_field(
+	json: &mut Json,
+	path: &str,
+	field: &FieldDescriptor,
+	value: String,
+	append: bool,
+) -> Result<(), BoxedError> {
+	let mut target = json;
+	let mut names = path.split('.').peekable();
+	while let Some(name) = names.next() {
+		let object = object(target)?;
+		if names.peek().is_some() {
+			target = object
+				.entry(name)
+				.or_insert_with(|| Json::Object(Map::new()));
+			continue;
+		}
+		let value = match field.kind() {
+			Kind::Bool => Json::Bool(value.parse()?),
+			Kind::Enum(_) => match value.parse::<i32>() {
+				Ok(number) => number.into(),
+				Err(_) => Json::String(value),
+			},
+			//Proto3 JSON accepts numbers and well known types as strings
+			_ => Json::String(value),
+		};
+		if append && field.is_list() {
+			match object
+				.entry(name)
+				.or_insert_with(|| Json::Array(Vec::new()))
+			{
+				Json::Array(items) => items.push(value),
+				_ => return Err("expected a JSON array".into()),
+			}
+		} else {
+			object.insert(name.to_owned(), value);
+		}
+		break;
+	}
+	Ok(())
+}
+
+/// State of a transcoded request, to convert its response to JSON
+struct Transcoded {
+	output: MessageDescriptor,
+	response_body: Option<String>,
+	//Status of the request, set when it could not be converted
+	error: Arc<Mutex<Option<(i32, String)>>>,
+	unary: UnaryBody,
+}
+
+impl Adapted for Transcoded {
+	fn head(&mut self, parts: &mut Parts) -> Option<Response<LocalBody>> {
+		//Failed calls have no messages
+		if let Some((status, message)) = self.error.lock().unwrap().take() {
+			return Some(error_response(status, &message));
+		}
+		match grpc::parse_status(&parts.headers) {
+			Some((code::OK, _)) | None => {}
+			Some((status, message)) => return Some(error_response(status, &message)),
+		}
+		json_headers(&mut parts.headers);
+		None
+	}
+
+	fn data(&mut self, data: Bytes) -> Result<Option<Bytes>, BoxedError> {
+		self.unary.push(&data)?;
+		Ok(None)
+	}
+
+	fn end(&mut self, _trailers: Option<&HeaderMap>) -> Result<Option<Bytes>, BoxedError> {
+		let message = self.unary.message()?;
+		self.json(&message).map(Some)
+	}
+
+	fn buffered(&self) -> bool {
+		true
+	}
+}
+
+impl Transcoded {
+	fn json(&self, message: &[u8]) -> Result<Bytes, BoxedError> {
+		let message = DynamicMessage::decode(self.output.clone(), message)?;
+		let mut json = serde_json::to_value(&message)?;
+		if let Some(field) = &self.response_body {
+			let field = self
+				.output
+				.get_field_by_name(field)
+				.ok_or("unknown field")?;
+			json = match json {
+				Json::Object(mut object) => object.remove(field.json_name()).unwrap_or_default(),
+				_ => Json::Null,
+			};
+		}
+		Ok(serde_json::to_vec(&json)?.into())
+	}
+}
+
+/// JSON error with the HTTP status that matches the gRPC status
+fn error_response(status: i32, message: &str) -> Response<LocalBody> {
+	let body = serde_json::json!({ "code": status, "message": message });
+	let mut response = Response::new(Once::new(body.to_string(), None).boxed());
+	*response.status_mut() = grpc::http_status(status);
+	response
+		.headers_mut()
+		.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+	response
+}
+
+/// Mark a response of the gRPC service as JSON
+fn json_headers(headers: &mut HeaderMap) {
+	headers.remove(CONTENT_LENGTH);
+	headers.remove("grpc-encoding");
+	headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{parse_query, Segment, Template};
+
+	#[test]
+	fn template_parses_variables_and_verb() {
+		let template = Template::parse("/v1/{name=shelves/*/books/*}:archive").unwrap();
+		assert_eq!(template.verb.as_deref(), Some("archive"));
+		assert_eq!(
+			template.segments,
+			[
+				Segment::Literal("v1".into()),
+				Segment::Literal("shelves".into()),
+				Segment::Any,
+				Segment::Literal("books".into()),
+				Segment::Any,
+			]
+		);
+		assert_eq!(
+			template.matches("/v1/shelves/1/books/2:archive"),
+			Some(vec![("name".into(), "shelves/1/books/2".into())])
+		);
+		assert_eq!(template.matches("/v1/shelves/1/books/2"), None);
+		assert_eq!(template.matches("/v1/shelves/1:archive"), None);
+	}
+
+	#[test]
+	fn template_matches_rest_and_decodes_single_segments() {
+		let template = Template::parse("/files/{id}/{path=**}").unwrap();
+		assert_eq!(
+			template.matches("/files/a%2Fb/dir/file%2Fname"),
+			Some(vec![
+				("id".into(), "a/b".into()),
+				("path".into(), "dir/file%2Fname".into())
+			])
+		);
+		assert_eq!(template.matches("/files//x"), None);
+	}
+
+	#[test]
+	fn template_rejects_invalid_syntax() {
+		for template in ["v1", "/v1/{name", "/v1//a", "/v1/**/a", "/v1/{}", "/{a}b"] {
+			assert!(Template::parse(template).is_err(), "{template:?}");
+		}
+	}
+
+	#[test]
+	fn query_decodes_plus_and_escapes() {
+		assert_eq!(
+			parse_query("a=1+2&b.c=%C3%A9&d"),
+			[
+				("a".to_owned(), "1 2".to_owned()),
+				("b.c".to_owned(), "\u{e9}".to_owned()),
+				("d".to_owned(), String::new())
+			]
+		);
+	}
+}
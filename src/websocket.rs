@@ -0,0 +1,153 @@
+use std::task::{Context, Poll};
+
+use futures::future::Either;
+use hyper::{
+	ext::Protocol,
+	header::{CONNECTION, UPGRADE},
+	Body, HeaderMap, Method, Request, Version,
+};
+use tower::Service;
+
+/// Service that routes WebSocket upgrades to one service, and other requests to another
+///
+/// Use it as the web service of a [Multiplexer][crate::Multiplexer], to serve
+/// WebSockets with a dedicated handler. Both HTTP/1.1 upgrades and HTTP/2
+/// extended CONNECT requests ([RFC 8441](https://www.rfc-editor.org/rfc/rfc8441))
+/// are WebSocket upgrades. The handler gets the request with its
+/// `OnUpgrade` extension, so it can call [hyper::upgrade::on].
+///
+/// Both services must have the same response and error types.
+///
+/// The Multiplexer passes upgrades to the web service without this router too.
+/// The request ends with its response: upgraded connections are not counted by
+/// limits and drains, and are not timed out.
+///
+/// # Examples:
+/// ```
+/// use multiplex_tonic_hyper::{Multiplexer, WebSocketRouter};
+/// # use hyper::{service::service_fn, Body, Request, Response};
+/// # async fn handle(_: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+/// # 	Ok(Response::new(Body::empty()))
+/// # }
+/// # let grpc = service_fn(handle);
+/// # let websocket = service_fn(handle);
+/// # let web = service_fn(handle);
+///
+/// let multiplexer = Multiplexer::new(grpc, WebSocketRouter::new(websocket, web));
+/// ```
+///
+/// HTTP/2 clients only send extended CONNECT requests to servers that enable
+/// them, with `http2_enable_connect_protocol` on the hyper server.
+#[derive(Clone, Debug)]
+pub struct WebSocketRouter<WebSocket, Web> {
+	websocket: WebSocket,
+	web: Web,
+}
+
+impl<WebSocket, Web> WebSocketRouter<WebSocket, Web> {
+	/// Send WebSocket upgrades to `websocket`, and other requests to `web`
+	pub fn new(websocket: WebSocket, web: Web) -> Self {
+		WebSocketRouter { websocket, web }
+	}
+}
+
+impl<WebSocket, Web> Service<Request<Body>> for WebSocketRouter<WebSocket, Web>
+where
+	WebSocket: Service<Request<Body>, Response = Web::Response, Error = Web::Error>,
+	Web: Service<Request<Body>>,
+{
+	type Response = Web::Response;
+	type Error = Web::Error;
+	type Future = Either<WebSocket::Future, Web::Future>;
+
+	///Only is ready if both services are ready
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		let websocket = self.websocket.poll_ready(cx)?;
+		let web = self.web.poll_ready(cx)?;
+		match (websocket, web) {
+			(Poll::Ready(_), Poll::Ready(_)) => Poll::Ready(Ok(())),
+			_ => Poll::Pending,
+		}
+	}
+
+	fn call(&mut self, req: Request<Body>) -> Self::Future {
+		if is_websocket_upgrade(&req) {
+			Either::Left(self.websocket.call(req))
+		} else {
+			Either::Right(self.web.call(req))
+		}
+	}
+}
+
+/// If the request opens a WebSocket, with an HTTP/1.1 upgrade or an HTTP/2 extended CONNECT
+fn is_websocket_upgrade<B>(req: &Request<B>) -> bool {
+	match req.version() {
+		Version::HTTP_2 => {
+			req.method() == Method::CONNECT
+				&& req
+					.extensions()
+					.get::<Protocol>()
+					.is_some_and(|protocol| protocol.as_str().eq_ignore_ascii_case("websocket"))
+		}
+		_ => {
+			req.method() == Method::GET
+				&& has_token(req.headers(), CONNECTION, "upgrade")
+				&& has_token(req.headers(), UPGRADE, "websocket")
+		}
+	}
+}
+
+/// If a comma separated header has `token`, ignoring the case
+fn has_token(headers: &HeaderMap, name: hyper::header::HeaderName, token: &str) -> bool {
+	headers
+		.get_all(name)
+		.iter()
+		.filter_map(|value| value.to_str().ok())
+		.flat_map(|value| value.split(','))
+		.any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+#[cfg(test)]
+mod tests {
+	use hyper::{ext::Protocol, Body, Method, Request, Version};
+
+	use super::is_websocket_upgrade;
+
+	#[test]
+	fn http1_upgrade_needs_both_headers() {
+		let upgrade = Request::get("/ws")
+			.header("connection", "keep-alive, Upgrade")
+			.header("upgrade", "WebSocket")
+			.body(Body::empty())
+			.unwrap();
+		assert!(is_websocket_upgrade(&upgrade));
+
+		let other = Request::get("/ws")
+			.header("upgrade", "websocket")
+			.body(Body::empty())
+			.unwrap();
+		assert!(!is_websocket_upgrade(&other));
+
+		let h2c = Request::get("/")
+			.header("connection", "upgrade")
+			.header("upgrade", "h2c")
+			.body(Body::empty())
+			.unwrap();
+		assert!(!is_websocket_upgrade(&h2c));
+	}
+
+	#[test]
+	fn http2_extended_connect_needs_the_protocol() {
+		let mut connect = Request::builder()
+			.method(Method::CONNECT)
+			.version(Version::HTTP_2)
+			.uri("https://example.com/ws")
+			.body(Body::empty())
+			.unwrap();
+		assert!(!is_websocket_upgrade(&connect));
+		connect
+			.extensions_mut()
+			.insert(Protocol::from_static("websocket"));
+		assert!(is_websocket_upgrade(&connect));
+	}
+}
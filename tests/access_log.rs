@@ -0,0 +1,124 @@
+use std::{
+	convert::Infallible,
+	net::SocketAddr,
+	sync::{Arc, Mutex},
+};
+
+use hyper::{
+	body::HttpBody, header::CONTENT_TYPE, server::conn::AddrStream, service::service_fn, Body,
+	HeaderMap, Request, Response,
+};
+use tower::{make::Shared, Service, ServiceExt};
+
+use multiplex_tonic_hyper::{AccessLog, AccessRecord, JsonFormat, Multiplexer};
+
+type Lines = Arc<Mutex<Vec<String>>>;
+
+fn capture() -> (Lines, impl Fn(&str) + Send + Sync + 'static) {
+	let lines = Lines::default();
+	let writer = {
+		let lines = lines.clone();
+		move |line: &str| lines.lock().unwrap().push(line.to_owned())
+	};
+	(lines, writer)
+}
+
+async fn grpc(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	let (mut sender, body) = Body::channel();
+	tokio::spawn(async move {
+		sender.send_data("1234".into()).await.unwrap();
+		let mut trailers = HeaderMap::new();
+		trailers.insert("grpc-status", "3".parse().unwrap());
+		sender.send_trailers(trailers).await.unwrap();
+	});
+	Ok(Response::new(body))
+}
+
+async fn web(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	Ok(Response::new(Body::from("Hello World")))
+}
+
+#[tokio::test]
+async fn one_record_per_request_with_custom_format() {
+	let (lines, writer) = capture();
+	let format = |record: &AccessRecord| {
+		format!(
+			"{} {} {:?} {:?} {}",
+			record.branch,
+			record.path,
+			record.status.map(|status| status.as_u16()),
+			record.grpc_status,
+			record.bytes_sent
+		)
+	};
+	let mut multiplexer = Multiplexer::builder()
+		.access_log(AccessLog::new(format, writer))
+		.build(service_fn(grpc), service_fn(web));
+
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(Request::get("/index.html").body(Body::empty()).unwrap())
+		.await
+		.unwrap();
+	assert!(
+		lines.lock().unwrap().is_empty(),
+		"logged before the body ends"
+	);
+	hyper::body::to_bytes(response.into_body()).await.unwrap();
+	multiplexer.ready().await.unwrap();
+
+	let request = Request::post("/helloworld.Greeter/SayHello")
+		.header(CONTENT_TYPE, "application/grpc")
+		.body(Body::empty())
+		.unwrap();
+	let response = multiplexer.call(request).await.unwrap();
+	let mut body = response.into_body();
+	while body.data().await.is_some() {}
+	body.trailers().await.unwrap();
+	drop(body);
+
+	let lines = lines.lock().unwrap();
+	assert_eq!(
+		*lines,
+		[
+			"web /index.html Some(200) None 11",
+			"grpc /helloworld.Greeter/SayHello Some(200) Some(3) 4"
+		]
+	);
+}
+
+#[tokio::test]
+async fn make_multiplexer_logs_remote_addr() {
+	let (lines, writer) = capture();
+	let make_multiplexer = Multiplexer::builder()
+		.access_log(AccessLog::new(JsonFormat, writer))
+		.build_make(Shared::new(service_fn(grpc)), Shared::new(service_fn(web)))
+		.with_remote_addr(|conn: &&AddrStream| conn.remote_addr());
+
+	let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+	let server = hyper::Server::bind(&addr).serve(make_multiplexer);
+	let addr = server.local_addr();
+	tokio::spawn(server);
+
+	let response = hyper::Client::new()
+		.get(format!("http://{addr}/path?query").parse().unwrap())
+		.await
+		.unwrap();
+	hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+	//The record is written when the server drops the body, after the client got it
+	let line = loop {
+		if let Some(line) = lines.lock().unwrap().pop() {
+			break line;
+		}
+		tokio::task::yield_now().await;
+	};
+	assert!(line.contains("\"remote_addr\":\"127.0.0.1:"), "{line}");
+	assert!(line.contains("\"branch\":\"web\""), "{line}");
+	assert!(line.contains("\"path\":\"/path?query\""), "{line}");
+	assert!(line.contains("\"status\":200"), "{line}");
+	assert!(line.contains("\"bytes_sent\":11"), "{line}");
+	assert!(line.contains("\"completed\":true"), "{line}");
+}
@@ -0,0 +1,133 @@
+use std::sync::{
+	atomic::{AtomicUsize, Ordering},
+	Arc,
+};
+
+use hyper::{body::HttpBody, header::CONTENT_TYPE, service::service_fn, Body, Request, Response};
+use tower::{Service, ServiceExt};
+
+use multiplex_tonic_hyper::{BodyLimit, Branch, Multiplexer};
+
+type BoxedError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Service that reads the whole request body, counting its calls
+fn reader(
+	calls: Arc<AtomicUsize>,
+) -> impl Service<Request<Body>, Response = Response<Body>, Error = BoxedError> {
+	service_fn(move |req: Request<Body>| {
+		calls.fetch_add(1, Ordering::SeqCst);
+		async move {
+			let body = hyper::body::to_bytes(req.into_body()).await?;
+			Ok::<_, BoxedError>(Response::new(Body::from(body)))
+		}
+	})
+}
+
+fn chunked(chunks: &'static [&'static str]) -> Body {
+	let (mut sender, body) = Body::channel();
+	tokio::spawn(async move {
+		for chunk in chunks {
+			if sender.send_data((*chunk).into()).await.is_err() {
+				break;
+			}
+		}
+	});
+	body
+}
+
+#[tokio::test]
+async fn rejects_by_content_length_without_calling_the_inner_service() {
+	let calls = Arc::new(AtomicUsize::new(0));
+	let mut multiplexer = Multiplexer::builder()
+		.body_limit(Branch::Grpc, BodyLimit::new(4))
+		.body_limit(Branch::Web, BodyLimit::new(4).path("/upload/", 8))
+		.build(reader(calls.clone()), reader(calls.clone()));
+
+	let request = Request::post("/helloworld.Greeter/SayHello")
+		.header(CONTENT_TYPE, "application/grpc")
+		.header("content-length", "5")
+		.body(Body::from("12345"))
+		.unwrap();
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(request)
+		.await
+		.unwrap();
+	assert_eq!(response.headers()["grpc-status"], "8");
+
+	let request = Request::post("/index.html")
+		.header("content-length", "5")
+		.body(Body::from("12345"))
+		.unwrap();
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(request)
+		.await
+		.unwrap();
+	assert_eq!(response.status(), 413);
+	assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+	let request = Request::post("/upload/file")
+		.header("content-length", "5")
+		.body(Body::from("12345"))
+		.unwrap();
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(request)
+		.await
+		.unwrap();
+	assert_eq!(response.status(), 200);
+	let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+	assert_eq!(body, "12345");
+}
+
+#[tokio::test]
+async fn streamed_bodies_over_the_limit_get_generated_responses() {
+	let calls = Arc::new(AtomicUsize::new(0));
+	let mut multiplexer = Multiplexer::builder()
+		.body_limit(Branch::Grpc, BodyLimit::new(4))
+		.body_limit(Branch::Web, BodyLimit::new(4))
+		.build(reader(calls.clone()), reader(calls.clone()));
+
+	let request = Request::post("/helloworld.Greeter/SayHello")
+		.header(CONTENT_TYPE, "application/grpc")
+		.body(chunked(&["123", "45"]))
+		.unwrap();
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(request)
+		.await
+		.unwrap();
+	assert_eq!(response.headers()["grpc-status"], "8");
+	let mut body = response.into_body();
+	assert!(body.data().await.is_none());
+
+	let request = Request::post("/").body(chunked(&["123", "45"])).unwrap();
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(request)
+		.await
+		.unwrap();
+	assert_eq!(response.status(), 413);
+
+	let request = Request::post("/").body(chunked(&["12", "34"])).unwrap();
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(request)
+		.await
+		.unwrap();
+	assert_eq!(response.status(), 200);
+	assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
@@ -0,0 +1,74 @@
+use std::{convert::Infallible, time::Duration};
+
+use hello_world_tonic::{
+	hello_world::{greeter_client::GreeterClient, greeter_server::GreeterServer, HelloRequest},
+	server::MyGreeter,
+};
+use hyper::{service::service_fn, Body, Request, Response};
+use tonic::transport::Endpoint;
+use tower::{make::Shared, ServiceExt};
+
+use multiplex_tonic_hyper::{
+	testing::TestServer, BoxMultiplexer, Branch, Multiplexer, Timeout, WebSocketRouter,
+};
+
+async fn web(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	Ok(Response::new(Body::from("web")))
+}
+
+async fn websocket(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	Ok(Response::new(Body::from("websocket")))
+}
+
+/// Multiplexers with different inner types, stored in the same field
+struct App {
+	multiplexer: BoxMultiplexer,
+}
+
+impl App {
+	fn new(websockets: bool) -> Self {
+		let grpc = GreeterServer::new(MyGreeter::default());
+		let multiplexer = if websockets {
+			let web = WebSocketRouter::new(service_fn(websocket), service_fn(web));
+			Multiplexer::new(grpc, web).boxed()
+		} else {
+			Multiplexer::builder()
+				.timeout(Branch::Web, Timeout::new(Duration::from_secs(5)))
+				.build(grpc, service_fn(web))
+				.boxed()
+		};
+		App { multiplexer }
+	}
+}
+
+fn assert_send<T: Send + 'static>(_: &T) {}
+fn assert_sync<T: Sync>(_: &T) {}
+
+#[tokio::test]
+async fn boxed_multiplexers_route_like_the_original() {
+	for websockets in [false, true] {
+		let app = App::new(websockets);
+		assert_send(&app.multiplexer);
+		assert_sync(&app.multiplexer);
+		let request = Request::get("/").body(Body::empty()).unwrap();
+		let response = app.multiplexer.clone().oneshot(request).await.unwrap();
+		let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		assert_eq!(body, "web");
+	}
+}
+
+#[tokio::test]
+async fn boxed_multiplexers_are_served_by_hyper() {
+	let app = App::new(false);
+	let server = TestServer::new(Shared::new(app.multiplexer));
+
+	let channel = Endpoint::from_static("http://test")
+		.connect_with_connector(server.connector())
+		.await
+		.unwrap();
+	let reply = GreeterClient::new(channel)
+		.say_hello(HelloRequest { name: "Ana".into() })
+		.await
+		.unwrap();
+	assert_eq!(reply.into_inner().message, "Hello Ana!");
+}
@@ -0,0 +1,61 @@
+use std::convert::Infallible;
+
+use hyper::{
+	header::CONTENT_TYPE, service::service_fn, Body, Client, Method, Request, Response, Version,
+};
+use tower::make::Shared;
+
+use multiplex_tonic_hyper::{
+	testing::{InMemoryConnector, TestServer},
+	Classifier, GrpcContentType, Multiplexer,
+};
+
+async fn grpc(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	Ok(Response::new(Body::from("grpc")))
+}
+
+async fn web(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	Ok(Response::new(Body::from("web")))
+}
+
+fn serve() -> TestServer {
+	//Only HTTP/2 POST requests can be gRPC, some callers send `te: trailers` without the content type
+	let classifier = Classifier::version(Version::HTTP_2)
+		.and(Classifier::method(Method::POST))
+		.and(Classifier::content_type(GrpcContentType::strict()).or(Classifier::te_trailers()));
+	TestServer::new(
+		Multiplexer::builder()
+			.classifier(classifier)
+			.build_make(Shared::new(service_fn(grpc)), Shared::new(service_fn(web))),
+	)
+}
+
+async fn call(client: &Client<InMemoryConnector>, request: Request<Body>) -> String {
+	let response = client.request(request).await.unwrap();
+	let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+	String::from_utf8(body.to_vec()).unwrap()
+}
+
+fn post(content_type: &str) -> Request<Body> {
+	Request::post("http://test/internal.Service/Call")
+		.header(CONTENT_TYPE, content_type)
+		.header("te", "trailers")
+		.body(Body::empty())
+		.unwrap()
+}
+
+#[tokio::test]
+async fn http2_callers_with_other_content_types_reach_grpc() {
+	let client = serve().http2_client();
+	assert_eq!(call(&client, post("application/x-internal")).await, "grpc");
+	assert_eq!(call(&client, post("application/grpc")).await, "grpc");
+
+	let get = Request::get("http://test/").body(Body::empty()).unwrap();
+	assert_eq!(call(&client, get).await, "web");
+}
+
+#[tokio::test]
+async fn http1_requests_never_reach_grpc() {
+	let client = serve().client();
+	assert_eq!(call(&client, post("application/grpc")).await, "web");
+}
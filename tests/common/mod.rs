@@ -1,3 +1,2 @@
 #[rustfmt::skip]
 pub mod hello_world;
-pub mod svc;
@@ -0,0 +1,155 @@
+#![cfg(feature = "compression")]
+
+use std::{convert::Infallible, io::Read};
+
+use hyper::{
+	body::{Bytes, HttpBody},
+	header::{
+		ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, VARY,
+	},
+	service::service_fn,
+	Body, Request, Response, StatusCode,
+};
+use tower::{Service, ServiceExt};
+
+use multiplex_tonic_hyper::{Compression, Multiplexer};
+
+fn page() -> String {
+	"<p>multiplexed</p>\n".repeat(200)
+}
+
+async fn grpc(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	Ok(Response::new(Body::from(page())))
+}
+
+async fn web(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	let body = match req.uri().path() {
+		"/small" => "small".to_string(),
+		_ => page(),
+	};
+	let mut response = Response::builder()
+		.header(CONTENT_TYPE, "text/html")
+		.header(CONTENT_LENGTH, body.len());
+	match req.uri().path() {
+		"/partial" => {
+			response = response
+				.status(StatusCode::PARTIAL_CONTENT)
+				.header(CONTENT_RANGE, format!("bytes 0-{}/*", body.len() - 1));
+		}
+		"/range" => {
+			response = response.header(CONTENT_RANGE, format!("bytes */{}", body.len()));
+		}
+		_ => {}
+	}
+	Ok(response.body(Body::from(body)).unwrap())
+}
+
+fn request(path: &str, accept_encoding: &str) -> Request<Body> {
+	Request::get(path)
+		.header(ACCEPT_ENCODING, accept_encoding)
+		.body(Body::empty())
+		.unwrap()
+}
+
+fn decode(encoding: &str, data: &[u8]) -> String {
+	let mut decoded = String::new();
+	match encoding {
+		"gzip" => flate2::read::GzDecoder::new(data)
+			.read_to_string(&mut decoded)
+			.unwrap(),
+		"br" => brotli::Decompressor::new(data, 4096)
+			.read_to_string(&mut decoded)
+			.unwrap(),
+		"zstd" => zstd::stream::read::Decoder::new(data)
+			.unwrap()
+			.read_to_string(&mut decoded)
+			.unwrap(),
+		_ => panic!("unknown encoding {encoding}"),
+	};
+	decoded
+}
+
+fn multiplexer() -> Multiplexer<
+	impl Service<Request<Body>, Response = Response<Body>, Error = Infallible>,
+	impl Service<Request<Body>, Response = Response<Body>, Error = Infallible>,
+> {
+	Multiplexer::builder()
+		.compression(Compression::new())
+		.build(service_fn(grpc), service_fn(web))
+}
+
+#[tokio::test]
+async fn web_responses_use_the_accepted_encoding() {
+	for encoding in ["gzip", "br", "zstd"] {
+		let accept_encoding = format!("{encoding}, identity;q=0.5");
+		let response = multiplexer()
+			.oneshot(request("/", &accept_encoding))
+			.await
+			.unwrap();
+		let headers = response.headers();
+		assert_eq!(headers[CONTENT_ENCODING], encoding);
+		assert_eq!(headers[VARY], "accept-encoding");
+		assert!(headers.get(CONTENT_LENGTH).is_none());
+		let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		assert!(body.len() < page().len());
+		assert_eq!(decode(encoding, &body), page());
+	}
+}
+
+#[tokio::test]
+async fn small_and_grpc_responses_are_not_compressed() {
+	let response = multiplexer()
+		.oneshot(request("/small", "gzip"))
+		.await
+		.unwrap();
+	assert!(response.headers().get(CONTENT_ENCODING).is_none());
+	let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+	assert_eq!(body, "small");
+
+	let mut grpc_request = request("/pkg.Service/Method", "gzip");
+	grpc_request
+		.headers_mut()
+		.insert(CONTENT_TYPE, "application/grpc".parse().unwrap());
+	let response = multiplexer().oneshot(grpc_request).await.unwrap();
+	assert!(response.headers().get(CONTENT_ENCODING).is_none());
+	let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+	assert_eq!(body, page());
+}
+
+#[tokio::test]
+async fn partial_content_is_not_compressed() {
+	for path in ["/partial", "/range"] {
+		let response = multiplexer().oneshot(request(path, "gzip")).await.unwrap();
+		assert!(response.headers().get(CONTENT_ENCODING).is_none(), "{path}");
+		let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		assert_eq!(body, page());
+	}
+}
+
+#[tokio::test]
+async fn streamed_parts_are_flushed_without_waiting_for_the_next() {
+	let (mut sender, body) = Body::channel();
+	let mut body = Some(body);
+	let web = service_fn(move |_req: Request<Body>| {
+		let body = body.take().unwrap();
+		async move { Ok::<_, Infallible>(Response::new(body)) }
+	});
+	let multiplexer = Multiplexer::builder()
+		.compression(Compression::new())
+		.build(service_fn(grpc), web);
+	let response = multiplexer.oneshot(request("/", "gzip")).await.unwrap();
+	assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+	let mut response = response.into_body();
+
+	let mut compressed = Vec::new();
+	for part in ["first part\n", "second part\n"] {
+		sender.send_data(Bytes::from(part)).await.unwrap();
+		let data = response.data().await.unwrap().unwrap();
+		compressed.extend_from_slice(&data);
+	}
+	drop(sender);
+	while let Some(data) = response.data().await {
+		compressed.extend_from_slice(&data.unwrap());
+	}
+	assert_eq!(decode("gzip", &compressed), "first part\nsecond part\n");
+}
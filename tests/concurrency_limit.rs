@@ -0,0 +1,168 @@
+use std::{
+	convert::Infallible,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc,
+	},
+	time::Duration,
+};
+
+use futures::FutureExt;
+use hyper::{body::HttpBody, header::CONTENT_TYPE, service::service_fn, Body, Request, Response};
+use tower::{Service, ServiceExt};
+
+use multiplex_tonic_hyper::{Branch, ConcurrencyLimit, Multiplexer};
+
+async fn streaming(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	//The body never ends, it is only dropped
+	let (mut sender, body) = Body::channel();
+	tokio::spawn(async move {
+		sender.send_data("first".into()).await.ok();
+		futures::future::pending::<()>().await;
+		drop(sender);
+	});
+	Ok(Response::new(body))
+}
+
+fn grpc_request() -> Request<Body> {
+	Request::post("/helloworld.Greeter/SayHello")
+		.header(CONTENT_TYPE, "application/grpc")
+		.body(Body::empty())
+		.unwrap()
+}
+
+#[tokio::test]
+async fn sheds_requests_over_the_limit_until_bodies_end() {
+	let mut multiplexer = Multiplexer::builder()
+		.concurrency_limit(Branch::Web, ConcurrencyLimit::new(1))
+		.concurrency_limit(Branch::Grpc, ConcurrencyLimit::new(1))
+		.build(service_fn(streaming), service_fn(streaming));
+
+	let first = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(Request::new(Body::empty()))
+		.await
+		.unwrap();
+	//The response future resolved, but the body is still streaming
+	let shed = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(Request::new(Body::empty()))
+		.await
+		.unwrap();
+	assert_eq!(shed.status(), 503);
+
+	//Each branch has its own limit
+	let grpc = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(grpc_request())
+		.await
+		.unwrap();
+	assert!(grpc.headers().get("grpc-status").is_none());
+	let shed = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(grpc_request())
+		.await
+		.unwrap();
+	assert_eq!(shed.headers()["grpc-status"], "8");
+
+	drop(first);
+	let accepted = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(Request::new(Body::empty()))
+		.await
+		.unwrap();
+	assert_eq!(accepted.status(), 200);
+}
+
+#[tokio::test]
+async fn queued_requests_wait_for_a_slot() {
+	let mut multiplexer = Multiplexer::builder()
+		.concurrency_limit(Branch::Web, ConcurrencyLimit::new(1).queue(1))
+		.build(service_fn(streaming), service_fn(streaming));
+	//The queue is shared with the other connections
+	let mut other = multiplexer.clone();
+
+	let first = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(Request::new(Body::empty()))
+		.await
+		.unwrap();
+	let mut first = first.into_body();
+	assert_eq!(first.data().await.unwrap().unwrap(), "first");
+
+	multiplexer.ready().await.unwrap();
+	let mut queued = Box::pin(multiplexer.call(Request::new(Body::empty())));
+	assert!(
+		multiplexer.ready().now_or_never().is_none(),
+		"the connection waits with its request"
+	);
+	let shed = other
+		.ready()
+		.await
+		.unwrap()
+		.call(Request::new(Body::empty()))
+		.await
+		.unwrap();
+	assert_eq!(shed.status(), 503, "the queue is full");
+	assert!(queued.as_mut().now_or_never().is_none());
+
+	drop(first);
+	let response = tokio::time::timeout(Duration::from_secs(1), queued)
+		.await
+		.expect("got the slot released by the first body")
+		.unwrap();
+	assert_eq!(response.status(), 200);
+	tokio::time::timeout(Duration::from_secs(1), multiplexer.ready())
+		.await
+		.expect("the service is given back once called")
+		.unwrap();
+}
+
+#[tokio::test]
+async fn queued_requests_call_the_service_after_getting_a_slot() {
+	let calls = Arc::new(AtomicUsize::new(0));
+	let web = {
+		let calls = calls.clone();
+		service_fn(move |req| {
+			calls.fetch_add(1, Ordering::SeqCst);
+			streaming(req)
+		})
+	};
+	let mut multiplexer = Multiplexer::builder()
+		.concurrency_limit(Branch::Web, ConcurrencyLimit::new(1).queue(1))
+		.build(service_fn(streaming), web);
+
+	let first = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(Request::new(Body::empty()))
+		.await
+		.unwrap();
+	assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+	multiplexer.ready().await.unwrap();
+	let mut queued = Box::pin(multiplexer.call(Request::new(Body::empty())));
+	assert!(queued.as_mut().now_or_never().is_none());
+	assert_eq!(calls.load(Ordering::SeqCst), 1, "called while queued");
+
+	drop(first);
+	let response = tokio::time::timeout(Duration::from_secs(1), queued)
+		.await
+		.expect("got the slot released by the first body")
+		.unwrap();
+	assert_eq!(response.status(), 200);
+	assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
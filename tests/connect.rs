@@ -0,0 +1,215 @@
+#![cfg(feature = "connect")]
+
+use std::convert::Infallible;
+
+use futures::StreamExt;
+use hello_world_tonic::{
+	hello_world::{greeter_server::GreeterServer, HelloReply, HelloRequest, FILE_DESCRIPTOR_SET},
+	server::MyGreeter,
+};
+use hyper::{body::Bytes, service::service_fn, Body, HeaderMap, Request, Response, StatusCode};
+use prost::Message;
+use serde_json::{json, Value};
+use tower::{Service, ServiceExt};
+
+use multiplex_tonic_hyper::{BodyLimit, Branch, ConnectProtocol, Multiplexer};
+
+async fn web(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	Ok(Response::new(Body::from("web")))
+}
+
+async fn call<S, B>(multiplexer: &mut S, request: Request<Body>) -> (StatusCode, HeaderMap, Bytes)
+where
+	S: Service<Request<Body>, Response = Response<B>>,
+	S::Error: std::fmt::Debug,
+	B: hyper::body::HttpBody,
+	B::Error: std::fmt::Debug,
+{
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(request)
+		.await
+		.unwrap();
+	let (parts, body) = response.into_parts();
+	let body = hyper::body::to_bytes(body).await.unwrap();
+	(parts.status, parts.headers, body)
+}
+
+fn envelope(flags: u8, message: &[u8]) -> Vec<u8> {
+	let mut envelope = vec![flags];
+	envelope.extend_from_slice(&(message.len() as u32).to_be_bytes());
+	envelope.extend_from_slice(message);
+	envelope
+}
+
+fn hello(name: &str) -> Vec<u8> {
+	HelloRequest { name: name.into() }.encode_to_vec()
+}
+
+#[tokio::test]
+async fn unary_proto_calls_the_grpc_service() {
+	let mut multiplexer = Multiplexer::builder()
+		.connect(ConnectProtocol::new())
+		.build(GreeterServer::new(MyGreeter::default()), service_fn(web));
+
+	let request = Request::post("/helloworld.Greeter/SayHello")
+		.header("content-type", "application/proto")
+		.header("connect-protocol-version", "1")
+		.header("connect-timeout-ms", "5000")
+		.body(Body::from(hello("Ana")))
+		.unwrap();
+	let (status, headers, body) = call(&mut multiplexer, request).await;
+	assert_eq!(status, 200);
+	assert_eq!(headers["content-type"], "application/proto");
+	let reply = HelloReply::decode(body).unwrap();
+	assert_eq!(reply.message, "Hello Ana!");
+}
+
+#[tokio::test]
+async fn unary_json_needs_the_descriptors() {
+	let connect = ConnectProtocol::from_descriptor_set(FILE_DESCRIPTOR_SET).unwrap();
+	let mut multiplexer = Multiplexer::builder()
+		.connect(connect)
+		.build(GreeterServer::new(MyGreeter::default()), service_fn(web));
+	let request = Request::post("/helloworld.Greeter/SayHello")
+		.header("content-type", "application/json")
+		.body(Body::from(r#"{"name":"Ana"}"#))
+		.unwrap();
+	let (status, headers, body) = call(&mut multiplexer, request).await;
+	assert_eq!(status, 200);
+	assert_eq!(headers["content-type"], "application/json");
+	let body: Value = serde_json::from_slice(&body).unwrap();
+	assert_eq!(body, json!({"message": "Hello Ana!"}));
+
+	let mut multiplexer = Multiplexer::builder()
+		.connect(ConnectProtocol::new())
+		.build(GreeterServer::new(MyGreeter::default()), service_fn(web));
+	let request = Request::post("/helloworld.Greeter/SayHello")
+		.header("content-type", "application/json")
+		.header("connect-protocol-version", "1")
+		.body(Body::from(r#"{"name":"Ana"}"#))
+		.unwrap();
+	let (_, _, body) = call(&mut multiplexer, request).await;
+	assert_eq!(body, "web");
+}
+
+#[tokio::test]
+async fn unary_errors_are_connect_errors() {
+	let mut multiplexer = Multiplexer::builder()
+		.connect(ConnectProtocol::new())
+		.build(GreeterServer::new(MyGreeter::default()), service_fn(web));
+	let request = Request::post("/helloworld.Greeter/SayGoodbye")
+		.header("content-type", "application/proto")
+		.header("connect-protocol-version", "1")
+		.body(Body::from(hello("Ana")))
+		.unwrap();
+	let (status, headers, body) = call(&mut multiplexer, request).await;
+	assert_eq!(status, 501);
+	assert_eq!(headers["content-type"], "application/json");
+	let body: Value = serde_json::from_slice(&body).unwrap();
+	assert_eq!(body["code"], "unimplemented");
+}
+
+#[tokio::test]
+async fn unary_errors_after_the_headers_are_connect_errors() {
+	let grpc = service_fn(|_req: Request<Body>| async {
+		let (mut sender, body) = Body::channel();
+		tokio::spawn(async move {
+			let mut trailers = HeaderMap::new();
+			trailers.insert("grpc-status", "7".parse().unwrap());
+			trailers.insert("grpc-message", "not%20allowed".parse().unwrap());
+			sender.send_trailers(trailers).await.unwrap();
+		});
+		Ok::<_, Infallible>(
+			Response::builder()
+				.header("content-type", "application/grpc")
+				.body(body)
+				.unwrap(),
+		)
+	});
+	let mut multiplexer = Multiplexer::builder()
+		.connect(ConnectProtocol::new())
+		.build(grpc, service_fn(web));
+	let request = Request::post("/helloworld.Greeter/SayHello")
+		.header("content-type", "application/proto")
+		.header("connect-protocol-version", "1")
+		.body(Body::from(hello("Ana")))
+		.unwrap();
+	let (status, headers, body) = call(&mut multiplexer, request).await;
+	assert_eq!(status, StatusCode::FORBIDDEN);
+	assert_eq!(headers["content-type"], "application/json");
+	let body: Value = serde_json::from_slice(&body).unwrap();
+	assert_eq!(
+		body,
+		json!({"code": "permission_denied", "message": "not allowed"})
+	);
+}
+
+#[tokio::test]
+async fn streaming_ends_with_the_end_of_stream_message() {
+	let mut multiplexer = Multiplexer::builder()
+		.connect(ConnectProtocol::new())
+		.build(GreeterServer::new(MyGreeter::default()), service_fn(web));
+	let request = Request::post("/helloworld.Greeter/SayHello")
+		.header("content-type", "application/connect+proto")
+		.body(Body::from(envelope(0, &hello("Ana"))))
+		.unwrap();
+	let (status, headers, body) = call(&mut multiplexer, request).await;
+	assert_eq!(status, 200);
+	assert_eq!(headers["content-type"], "application/connect+proto");
+	let reply = HelloReply {
+		message: "Hello Ana!".into(),
+	};
+	let mut expected = envelope(0, &reply.encode_to_vec());
+	expected.extend(envelope(2, b"{}"));
+	assert_eq!(body, expected);
+}
+
+#[tokio::test]
+async fn plain_requests_go_to_web() {
+	let mut multiplexer = Multiplexer::builder()
+		.connect(ConnectProtocol::new())
+		.build(GreeterServer::new(MyGreeter::default()), service_fn(web));
+	let request = Request::post("/helloworld.Greeter/SayHello")
+		.header("content-type", "application/proto")
+		.body(Body::from(hello("Ana")))
+		.unwrap();
+	let (_, _, body) = call(&mut multiplexer, request).await;
+	assert_eq!(body, "web");
+}
+
+#[tokio::test]
+async fn large_unary_bodies_use_the_grpc_body_limit() {
+	let mut multiplexer = Multiplexer::builder()
+		.connect(ConnectProtocol::new())
+		.body_limit(Branch::Grpc, BodyLimit::new(32))
+		.build(GreeterServer::new(MyGreeter::default()), service_fn(web));
+	let message = hello(&"a".repeat(100));
+
+	let with_length = Request::post("/helloworld.Greeter/SayHello")
+		.header("content-type", "application/proto")
+		.header("connect-protocol-version", "1")
+		.header("content-length", message.len())
+		.body(Body::from(message.clone()))
+		.unwrap();
+	let chunks = message
+		.chunks(10)
+		.map(|chunk| Ok::<_, Infallible>(chunk.to_vec()))
+		.collect::<Vec<_>>();
+	let streamed = Request::post("/helloworld.Greeter/SayHello")
+		.header("content-type", "application/proto")
+		.header("connect-protocol-version", "1")
+		//Only the start of the body is read, it never ends
+		.body(Body::wrap_stream(
+			futures::stream::iter(chunks).chain(futures::stream::pending()),
+		))
+		.unwrap();
+	for request in [with_length, streamed] {
+		let (status, _, body) = call(&mut multiplexer, request).await;
+		assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+		let body: Value = serde_json::from_slice(&body).unwrap();
+		assert_eq!(body["code"], "resource_exhausted");
+	}
+}
@@ -0,0 +1,51 @@
+use std::convert::Infallible;
+
+use hyper::{header::CONTENT_TYPE, service::service_fn, Body, Request, Response, StatusCode};
+use tower::ServiceExt;
+
+use multiplex_tonic_hyper::{GrpcContentType, Multiplexer};
+
+async fn grpc(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	Ok(Response::new(Body::from("grpc")))
+}
+
+async fn web(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	Ok(Response::new(Body::from("web")))
+}
+
+async fn call(content_type: GrpcContentType, value: &str) -> (StatusCode, String) {
+	let multiplexer = Multiplexer::builder()
+		.grpc_content_type(content_type)
+		.build(service_fn(grpc), service_fn(web));
+	let request = Request::post("/helloworld.Greeter/SayHello")
+		.header(CONTENT_TYPE, value)
+		.body(Body::empty())
+		.unwrap();
+	let response = multiplexer.oneshot(request).await.unwrap();
+	let status = response.status();
+	let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+	(status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn default_mode_keeps_the_prefix_match() {
+	let (_, body) = call(GrpcContentType::default(), "application/grpcfoo").await;
+	assert_eq!(body, "grpc");
+}
+
+#[tokio::test]
+async fn strict_mode_sends_other_values_to_the_web_branch() {
+	let (_, body) = call(GrpcContentType::strict(), "Application/gRPC+proto; x=y").await;
+	assert_eq!(body, "grpc");
+	let (_, body) = call(GrpcContentType::strict(), "application/grpcfoo").await;
+	assert_eq!(body, "web");
+}
+
+#[tokio::test]
+async fn mismatched_values_are_rejected_when_configured() {
+	let codecs = GrpcContentType::codecs(["proto"]).reject_mismatched(true);
+	let (status, _) = call(codecs.clone(), "application/grpc+json").await;
+	assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+	let (_, body) = call(codecs, "text/plain").await;
+	assert_eq!(body, "web");
+}
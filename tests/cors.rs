@@ -0,0 +1,127 @@
+use std::convert::Infallible;
+
+use hyper::{header::CONTENT_TYPE, service::service_fn, Body, Request, Response};
+use tower::{Service, ServiceExt};
+
+use multiplex_tonic_hyper::{Branch, CorsPolicy, Multiplexer};
+
+async fn grpc(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	Ok(Response::builder()
+		.header("vary", "accept-encoding")
+		.body(Body::from("grpc"))
+		.unwrap())
+}
+
+async fn web(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	Ok(Response::new(Body::from(format!("web {}", req.method()))))
+}
+
+fn multiplexer() -> impl Service<
+	Request<Body>,
+	Response = Response<impl hyper::body::HttpBody<Error = impl std::fmt::Debug>>,
+	Error = impl std::fmt::Debug,
+> {
+	Multiplexer::builder()
+		.cors(
+			Branch::Grpc,
+			CorsPolicy::grpc_web().allow_origin("https://app.example.com"),
+		)
+		.build(service_fn(grpc), service_fn(web))
+}
+
+#[tokio::test]
+async fn grpc_web_preflight_is_answered_by_the_multiplexer() {
+	let mut multiplexer = multiplexer();
+	let request = Request::options("/helloworld.Greeter/SayHello")
+		.header("origin", "https://app.example.com")
+		.header("access-control-request-method", "POST")
+		.header("access-control-request-headers", "content-type,x-grpc-web")
+		.body(Body::empty())
+		.unwrap();
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(request)
+		.await
+		.unwrap();
+	assert_eq!(response.status(), 204);
+	let headers = response.headers();
+	assert_eq!(
+		headers["access-control-allow-origin"],
+		"https://app.example.com"
+	);
+	assert_eq!(headers["access-control-allow-methods"], "POST");
+	assert_eq!(
+		headers["access-control-allow-headers"],
+		"content-type,x-grpc-web"
+	);
+}
+
+#[tokio::test]
+async fn preflight_without_policy_goes_to_the_inner_service() {
+	let mut multiplexer = multiplexer();
+	let request = Request::options("/index.html")
+		.header("origin", "https://app.example.com")
+		.header("access-control-request-method", "GET")
+		.body(Body::empty())
+		.unwrap();
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(request)
+		.await
+		.unwrap();
+	assert!(response
+		.headers()
+		.get("access-control-allow-origin")
+		.is_none());
+	let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+	assert_eq!(body, "web OPTIONS");
+}
+
+#[tokio::test]
+async fn responses_from_allowed_origins_get_cors_headers() {
+	let mut multiplexer = multiplexer();
+	let request = Request::post("/helloworld.Greeter/SayHello")
+		.header(CONTENT_TYPE, "application/grpc-web+proto")
+		.header("origin", "https://app.example.com")
+		.body(Body::empty())
+		.unwrap();
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(request)
+		.await
+		.unwrap();
+	let headers = response.headers();
+	assert_eq!(
+		headers["access-control-allow-origin"],
+		"https://app.example.com"
+	);
+	assert_eq!(
+		headers["access-control-expose-headers"],
+		"grpc-status, grpc-message, grpc-status-details-bin"
+	);
+	let vary: Vec<_> = headers.get_all("vary").iter().collect();
+	assert_eq!(vary, ["accept-encoding", "origin"]);
+
+	let request = Request::post("/helloworld.Greeter/SayHello")
+		.header(CONTENT_TYPE, "application/grpc-web+proto")
+		.header("origin", "https://evil.example.com")
+		.body(Body::empty())
+		.unwrap();
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(request)
+		.await
+		.unwrap();
+	assert!(response
+		.headers()
+		.get("access-control-allow-origin")
+		.is_none());
+}
@@ -0,0 +1,116 @@
+use std::{
+	convert::Infallible,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+use futures::FutureExt;
+use hyper::{
+	body::{HttpBody, Sender},
+	header::CONTENT_TYPE,
+	service::service_fn,
+	Body, Request, Response, StatusCode,
+};
+use tower::{Service, ServiceExt};
+
+use multiplex_tonic_hyper::{DrainHandle, Multiplexer};
+
+type Senders = Arc<Mutex<Vec<Sender>>>;
+
+/// Service that streams its response body through the returned senders
+fn streaming() -> (
+	Senders,
+	impl Service<Request<Body>, Response = Response<Body>, Error = Infallible>,
+) {
+	let senders = Senders::default();
+	let service = {
+		let senders = senders.clone();
+		service_fn(move |_req: Request<Body>| {
+			let (sender, body) = Body::channel();
+			senders.lock().unwrap().push(sender);
+			async move { Ok::<_, Infallible>(Response::new(body)) }
+		})
+	};
+	(senders, service)
+}
+
+fn grpc_request() -> Request<Body> {
+	Request::post("/helloworld.Greeter/SayHello")
+		.header(CONTENT_TYPE, "application/grpc")
+		.body(Body::empty())
+		.unwrap()
+}
+
+#[tokio::test]
+async fn rejects_new_requests_and_finishes_streams_in_flight() {
+	let drain = DrainHandle::new();
+	let (grpc_senders, grpc) = streaming();
+	let (_, web) = streaming();
+	let mut multiplexer = Multiplexer::builder().drain(drain.clone()).build(grpc, web);
+
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(grpc_request())
+		.await
+		.unwrap();
+	let mut body = response.into_body();
+	assert_eq!(drain.in_flight(), 1);
+
+	let mut drained = drain.drain();
+	let rejected = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(grpc_request())
+		.await
+		.unwrap();
+	assert_eq!(rejected.headers()["grpc-status"], "14");
+	let rejected = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(Request::new(Body::empty()))
+		.await
+		.unwrap();
+	assert_eq!(rejected.status(), StatusCode::SERVICE_UNAVAILABLE);
+	assert_eq!(rejected.headers()["connection"], "close");
+	assert!((&mut drained).now_or_never().is_none());
+
+	//The stream accepted before draining continues
+	let mut sender = grpc_senders.lock().unwrap().pop().unwrap();
+	sender.send_data("message".into()).await.unwrap();
+	assert_eq!(body.data().await.unwrap().unwrap(), "message");
+	drop(sender);
+	assert!(body.data().await.is_none());
+	drop(body);
+
+	tokio::time::timeout(Duration::from_secs(1), drained)
+		.await
+		.expect("drained after the last body ended");
+	assert_eq!(drain.in_flight(), 0);
+}
+
+#[tokio::test]
+async fn shutdown_aborts_streams_after_the_deadline() {
+	let drain = DrainHandle::new();
+	let (_, grpc) = streaming();
+	let (_web_senders, web) = streaming();
+	let mut multiplexer = Multiplexer::builder().drain(drain.clone()).build(grpc, web);
+
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(Request::new(Body::empty()))
+		.await
+		.unwrap();
+	let mut body = response.into_body();
+	let reader = tokio::spawn(async move { body.data().await.unwrap().is_err() });
+
+	let deadline = tokio::time::sleep(Duration::from_millis(10));
+	assert!(!drain.shutdown(deadline).await, "the stream never ends");
+	assert!(reader.await.unwrap(), "the body returned an error");
+	assert_eq!(drain.in_flight(), 0);
+}
@@ -0,0 +1,140 @@
+use std::{convert::Infallible, net::SocketAddr};
+
+use hello_world_tonic::{
+	hello_world::{
+		greeter_client::GreeterClient, greeter_server::GreeterServer, HelloReply, HelloRequest,
+	},
+	server::MyGreeter,
+};
+use hyper::{
+	body::{Buf, HttpBody},
+	header::CONTENT_TYPE,
+	service::service_fn,
+	Body, HeaderMap, Request, Response, StatusCode, Version,
+};
+use prost::Message;
+use tower::make::Shared;
+
+use multiplex_tonic_hyper::Multiplexer;
+
+/// Answers with the path, a header and a trailer
+async fn web(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	let (mut sender, body) = Body::channel();
+	let path = req.uri().path().to_owned();
+	tokio::spawn(async move {
+		sender.send_data(format!("web {path}").into()).await.ok();
+		let mut trailers = HeaderMap::new();
+		trailers.insert("x-checksum", "42".parse().unwrap());
+		sender.send_trailers(trailers).await.ok();
+	});
+	let response = Response::builder()
+		.status(StatusCode::ACCEPTED)
+		.header("x-web", "yes")
+		.body(body)
+		.unwrap();
+	Ok(response)
+}
+
+fn serve() -> SocketAddr {
+	let make_multiplexer = Multiplexer::builder().build_make(
+		Shared::new(GreeterServer::new(MyGreeter::default())),
+		Shared::new(service_fn(web)),
+	);
+	let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+	let server = hyper::Server::bind(&addr).serve(make_multiplexer);
+	let addr = server.local_addr();
+	tokio::spawn(server);
+	addr
+}
+
+fn hello(name: &str) -> HelloRequest {
+	HelloRequest { name: name.into() }
+}
+
+#[tokio::test]
+async fn tonic_client_calls_say_hello() {
+	let addr = serve();
+	let mut client = GreeterClient::connect(format!("http://{addr}"))
+		.await
+		.unwrap();
+	let reply = client.say_hello(hello("Ana")).await.unwrap();
+	assert_eq!(reply.into_inner().message, "Hello Ana!");
+}
+
+#[tokio::test]
+async fn tonic_client_chats_in_both_directions() {
+	let addr = serve();
+	let mut client = GreeterClient::connect(format!("http://{addr}"))
+		.await
+		.unwrap();
+	let requests = futures::stream::iter([hello("Ana"), hello("Bob"), hello("Eve")]);
+	let mut replies = client.chat(requests).await.unwrap().into_inner();
+	let mut messages = Vec::new();
+	while let Some(reply) = replies.message().await.unwrap() {
+		messages.push(reply.message);
+	}
+	assert_eq!(messages, ["Hello Ana!", "Hello Bob!", "Hello Eve!"]);
+}
+
+#[tokio::test]
+async fn grpc_responses_end_with_trailers() {
+	let addr = serve();
+	let client = hyper::Client::builder().http2_only(true).build_http();
+	let message = hello("Ana").encode_to_vec();
+	let mut frame = vec![0];
+	frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+	frame.extend_from_slice(&message);
+	let request = Request::post(format!("http://{addr}/helloworld.Greeter/SayHello"))
+		.header(CONTENT_TYPE, "application/grpc")
+		.header("te", "trailers")
+		.body(Body::from(frame))
+		.unwrap();
+	let response = client.request(request).await.unwrap();
+	assert_eq!(response.status(), StatusCode::OK);
+	assert_eq!(response.headers()[CONTENT_TYPE], "application/grpc");
+
+	let mut body = response.into_body();
+	let mut data = Vec::new();
+	while let Some(chunk) = body.data().await {
+		data.extend_from_slice(&chunk.unwrap());
+	}
+	let trailers = body.trailers().await.unwrap().unwrap();
+	assert_eq!(trailers["grpc-status"], "0");
+	let mut data = &data[..];
+	assert_eq!(data.get_u8(), 0);
+	let len = data.get_u32() as usize;
+	let reply = HelloReply::decode(&data[..len]).unwrap();
+	assert_eq!(reply.message, "Hello Ana!");
+}
+
+#[tokio::test]
+async fn http1_web_requests_keep_status_and_headers() {
+	let addr = serve();
+	let uri = format!("http://{addr}/page").parse().unwrap();
+	let response = hyper::Client::new().get(uri).await.unwrap();
+	assert_eq!(response.version(), Version::HTTP_11);
+	assert_eq!(response.status(), StatusCode::ACCEPTED);
+	assert_eq!(response.headers()["x-web"], "yes");
+	let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+	assert_eq!(body, "web /page");
+}
+
+#[tokio::test]
+async fn http2_web_requests_keep_trailers() {
+	let addr = serve();
+	let client = hyper::Client::builder()
+		.http2_only(true)
+		.build_http::<Body>();
+	let uri = format!("http://{addr}/page").parse().unwrap();
+	let response = client.get(uri).await.unwrap();
+	assert_eq!(response.version(), Version::HTTP_2);
+	assert_eq!(response.status(), StatusCode::ACCEPTED);
+	assert_eq!(response.headers()["x-web"], "yes");
+
+	let mut body = response.into_body();
+	let data = body.data().await.unwrap().unwrap();
+	assert_eq!(data, "web /page");
+	assert!(body.data().await.is_none());
+	let trailers = body.trailers().await.unwrap().unwrap();
+	assert_eq!(trailers["x-checksum"], "42");
+}
@@ -0,0 +1,70 @@
+use std::convert::Infallible;
+
+use hello_world_tonic::{
+	hello_world::{greeter_client::GreeterClient, greeter_server::GreeterServer, HelloRequest},
+	server::MyGreeter,
+};
+use hyper::{
+	header::CONTENT_TYPE, service::service_fn, Body, HeaderMap, Request, Response, StatusCode,
+};
+use tonic::transport::Endpoint;
+use tower::make::Shared;
+
+use multiplex_tonic_hyper::{testing::TestServer, GrpcFallback, Multiplexer};
+
+/// Legacy handler speaking gRPC framing, echoing the request messages
+async fn legacy(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	if req.uri().path() != "/helloworld.Greeter/Legacy" {
+		return Ok(Response::builder()
+			.status(StatusCode::NOT_FOUND)
+			.body(Body::empty())
+			.unwrap());
+	}
+	let messages = hyper::body::to_bytes(req.into_body()).await.unwrap();
+	let (mut sender, body) = Body::channel();
+	tokio::spawn(async move {
+		sender.send_data(messages).await.ok();
+		let mut trailers = HeaderMap::new();
+		trailers.insert("grpc-status", "0".parse().unwrap());
+		sender.send_trailers(trailers).await.ok();
+	});
+	Ok(Response::builder()
+		.header(CONTENT_TYPE, "application/grpc")
+		.body(body)
+		.unwrap())
+}
+
+fn serve() -> TestServer {
+	let web = service_fn(legacy);
+	let grpc = GrpcFallback::new(GreeterServer::new(MyGreeter::default()), web);
+	TestServer::new(Multiplexer::builder().build_make(Shared::new(grpc), Shared::new(web)))
+}
+
+#[tokio::test]
+async fn unimplemented_methods_are_answered_by_the_legacy_handler() {
+	let client = serve().http2_client();
+	let frame = b"\0\0\0\0\x03abc".to_vec();
+	let request = Request::post("http://test/helloworld.Greeter/Legacy")
+		.header(CONTENT_TYPE, "application/grpc")
+		.header("te", "trailers")
+		.body(Body::from(frame.clone()))
+		.unwrap();
+	let response = client.request(request).await.unwrap();
+	assert_eq!(response.status(), StatusCode::OK);
+	assert!(response.headers().get("grpc-status").is_none());
+	let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+	assert_eq!(body, frame);
+}
+
+#[tokio::test]
+async fn implemented_methods_stay_on_the_grpc_service() {
+	let channel = Endpoint::from_static("http://test")
+		.connect_with_connector(serve().connector())
+		.await
+		.unwrap();
+	let reply = GreeterClient::new(channel)
+		.say_hello_to_all(futures::stream::iter([HelloRequest { name: "Ana".into() }]))
+		.await
+		.unwrap();
+	assert_eq!(reply.into_inner().message, "Hello Ana!");
+}
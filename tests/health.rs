@@ -0,0 +1,240 @@
+#![cfg(feature = "health")]
+
+use std::{
+	convert::Infallible,
+	fmt::Debug,
+	sync::{
+		atomic::{AtomicU8, AtomicUsize, Ordering},
+		Arc,
+	},
+	task::{Context, Poll},
+};
+
+use futures::future::{self, Ready};
+use hyper::{
+	body::HttpBody, header::CONTENT_TYPE, service::service_fn, Body, Request, Response, StatusCode,
+};
+use tonic::{
+	transport::{Channel, Endpoint},
+	Code,
+};
+use tonic_health::proto::{
+	health_check_response::ServingStatus as Status, health_client::HealthClient, HealthCheckRequest,
+};
+use tower::{make::Shared, Service, ServiceExt};
+
+use multiplex_tonic_hyper::{testing::TestServer, HealthHandle, Multiplexer, ServingStatus};
+
+async fn inner(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	Ok(Response::new(Body::from("inner")))
+}
+
+fn serve(health: HealthHandle) -> TestServer {
+	TestServer::new(Multiplexer::builder().health(health).build_make(
+		Shared::new(service_fn(inner)),
+		Shared::new(service_fn(inner)),
+	))
+}
+
+async fn client(server: &TestServer) -> HealthClient<Channel> {
+	let channel = Endpoint::from_static("http://test")
+		.connect_with_connector(server.connector())
+		.await
+		.unwrap();
+	HealthClient::new(channel)
+}
+
+fn check(service: &str) -> HealthCheckRequest {
+	HealthCheckRequest {
+		service: service.to_owned(),
+	}
+}
+
+async fn get(server: &TestServer, path: &str) -> (StatusCode, String) {
+	let uri = format!("http://test{path}").parse().unwrap();
+	let response = server.client().get(uri).await.unwrap();
+	let status = response.status();
+	let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+	(status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn grpc_check_reports_ready_inner_services() {
+	let server = serve(HealthHandle::new().with_service("helloworld.Greeter"));
+	let mut client = client(&server).await;
+
+	for service in ["", "helloworld.Greeter"] {
+		let response = client.check(check(service)).await.unwrap().into_inner();
+		assert_eq!(response.status, Status::Serving as i32, "{service:?}");
+	}
+	let status = client.check(check("other.Service")).await.unwrap_err();
+	assert_eq!(status.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn grpc_watch_streams_overrides() {
+	let health = HealthHandle::new();
+	let server = serve(health.clone());
+	let mut client = client(&server).await;
+
+	let mut stream = client.watch(check("")).await.unwrap().into_inner();
+	let first = stream.message().await.unwrap().unwrap();
+	assert_eq!(first.status, Status::Serving as i32);
+
+	health.set_override(ServingStatus::NotServing);
+	let second = stream.message().await.unwrap().unwrap();
+	assert_eq!(second.status, Status::NotServing as i32);
+
+	health.clear_override();
+	let third = stream.message().await.unwrap().unwrap();
+	assert_eq!(third.status, Status::Serving as i32);
+}
+
+#[tokio::test]
+async fn grpc_watch_follows_services_added_later() {
+	let health = HealthHandle::new();
+	let server = serve(health.clone());
+	let mut client = client(&server).await;
+
+	let mut stream = client
+		.watch(check("helloworld.Greeter"))
+		.await
+		.unwrap()
+		.into_inner();
+	let first = stream.message().await.unwrap().unwrap();
+	assert_eq!(first.status, Status::ServiceUnknown as i32);
+
+	let _health = health.with_service("helloworld.Greeter");
+	let second = stream.message().await.unwrap().unwrap();
+	assert_eq!(second.status, Status::Serving as i32);
+	let response = client.check(check("helloworld.Greeter")).await.unwrap();
+	assert_eq!(response.into_inner().status, Status::Serving as i32);
+}
+
+#[tokio::test]
+async fn healthz_follows_status_and_other_paths_reach_web() {
+	let health = HealthHandle::new();
+	let server = serve(health.clone());
+
+	assert_eq!(
+		get(&server, "/healthz").await,
+		(StatusCode::OK, "SERVING\n".to_owned())
+	);
+	health.set_override(ServingStatus::NotServing);
+	assert_eq!(
+		get(&server, "/healthz").await,
+		(StatusCode::SERVICE_UNAVAILABLE, "NOT_SERVING\n".to_owned())
+	);
+	assert_eq!(
+		get(&server, "/healthz/other").await,
+		(StatusCode::OK, "inner".to_owned())
+	);
+}
+
+const PENDING: u8 = 0;
+const READY: u8 = 1;
+const FAILED: u8 = 2;
+
+/// Inner service whose readiness is set by the test
+#[derive(Clone, Default)]
+struct Switch {
+	state: Arc<AtomicU8>,
+	calls: Arc<AtomicUsize>,
+}
+
+impl Switch {
+	fn set(&self, state: u8) {
+		self.state.store(state, Ordering::SeqCst);
+	}
+
+	fn calls(&self) -> usize {
+		self.calls.load(Ordering::SeqCst)
+	}
+}
+
+impl Service<Request<Body>> for Switch {
+	type Response = Response<Body>;
+	type Error = &'static str;
+	type Future = Ready<Result<Response<Body>, &'static str>>;
+
+	fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		match self.state.load(Ordering::SeqCst) {
+			PENDING => Poll::Pending,
+			READY => Poll::Ready(Ok(())),
+			_ => Poll::Ready(Err("switched off")),
+		}
+	}
+
+	fn call(&mut self, _req: Request<Body>) -> Self::Future {
+		self.calls.fetch_add(1, Ordering::SeqCst);
+		future::ready(Ok(Response::new(Body::from("switched"))))
+	}
+}
+
+async fn body<B>(response: Response<B>) -> (StatusCode, String)
+where
+	B: HttpBody,
+	B::Error: Debug,
+{
+	let status = response.status();
+	let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+	(status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn probes_are_answered_while_an_inner_service_is_not_ready() {
+	let web = Switch::default();
+	let mut multiplexer = Multiplexer::builder()
+		.health(HealthHandle::new())
+		.build(service_fn(inner), web.clone());
+	let get = |path: &str| Request::get(path).body(Body::empty()).unwrap();
+	let unavailable = (
+		StatusCode::SERVICE_UNAVAILABLE,
+		"service is not serving\n".to_owned(),
+	);
+
+	let healthz = multiplexer.ready().await.unwrap().call(get("/healthz"));
+	assert_eq!(
+		body(healthz.await.unwrap()).await,
+		(StatusCode::SERVICE_UNAVAILABLE, "UNKNOWN\n".to_owned())
+	);
+	let pending = multiplexer.ready().await.unwrap().call(get("/"));
+	assert_eq!(body(pending.await.unwrap()).await, unavailable);
+	assert_eq!(web.calls(), 0);
+
+	web.set(FAILED);
+	let healthz = multiplexer.ready().await.unwrap().call(get("/healthz"));
+	assert_eq!(
+		body(healthz.await.unwrap()).await,
+		(StatusCode::SERVICE_UNAVAILABLE, "NOT_SERVING\n".to_owned())
+	);
+	let failed = multiplexer.ready().await.unwrap().call(get("/"));
+	assert_eq!(body(failed.await.unwrap()).await, unavailable);
+	assert_eq!(web.calls(), 0);
+
+	web.set(READY);
+	let response = multiplexer.ready().await.unwrap().call(get("/"));
+	assert_eq!(
+		body(response.await.unwrap()).await,
+		(StatusCode::OK, "switched".to_owned())
+	);
+	assert_eq!(web.calls(), 1);
+	let healthz = multiplexer.ready().await.unwrap().call(get("/healthz"));
+	assert_eq!(
+		body(healthz.await.unwrap()).await,
+		(StatusCode::OK, "SERVING\n".to_owned())
+	);
+}
+
+#[tokio::test]
+async fn large_check_requests_are_rejected() {
+	let multiplexer = Multiplexer::builder()
+		.health(HealthHandle::new())
+		.build(service_fn(inner), service_fn(inner));
+	let request = Request::post("/grpc.health.v1.Health/Check")
+		.header(CONTENT_TYPE, "application/grpc")
+		.body(Body::from(vec![0; 64 * 1024]))
+		.unwrap();
+	let response = multiplexer.oneshot(request).await.unwrap();
+	assert_eq!(response.headers()["grpc-status"], "8");
+}
@@ -0,0 +1,155 @@
+use std::{
+	convert::Infallible,
+	future::{ready, Ready},
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc,
+	},
+	task::{Context, Poll},
+};
+
+use hyper::{header::CONTENT_TYPE, Body, Request, Response};
+use tower::{make::Shared, Service, ServiceExt};
+
+use multiplex_tonic_hyper::{
+	testing::TestServer, HostRouter, MakeHostRouter, MakeMultiplexer, Multiplexer,
+};
+
+/// Answers every request with its text
+#[derive(Clone)]
+struct Text(&'static str);
+
+impl Service<Request<Body>> for Text {
+	type Response = Response<Body>;
+	type Error = Infallible;
+	type Future = Ready<Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn call(&mut self, _req: Request<Body>) -> Self::Future {
+		ready(Ok(Response::new(Body::from(self.0))))
+	}
+}
+
+fn tenant(name: &'static str) -> Multiplexer<Text, Text> {
+	Multiplexer::new(Text(name), Text(name))
+}
+
+/// Makes the Multiplexer of a tenant, counting how many were made
+#[derive(Clone)]
+struct MakeTenant {
+	name: &'static str,
+	made: Arc<AtomicUsize>,
+}
+
+impl Service<()> for MakeTenant {
+	type Response = Multiplexer<Text, Text>;
+	type Error = Infallible;
+	type Future = Ready<Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn call(&mut self, _target: ()) -> Self::Future {
+		self.made.fetch_add(1, Ordering::SeqCst);
+		ready(Ok(tenant(self.name)))
+	}
+}
+
+async fn call<S, B>(service: S, request: Request<Body>) -> String
+where
+	S: Service<Request<Body>, Response = Response<B>>,
+	S::Error: std::fmt::Debug,
+	B: hyper::body::HttpBody,
+	B::Error: std::fmt::Debug,
+{
+	let response = service.oneshot(request).await.unwrap();
+	let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+	String::from_utf8(body.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn routes_each_host_to_its_multiplexer() {
+	let router = || {
+		HostRouter::new(tenant("default"))
+			.host("a.example.com", tenant("a"))
+			.host("*.b.example.com", tenant("b"))
+	};
+
+	let request = Request::get("/")
+		.header("host", "a.example.com:8080")
+		.body(Body::empty())
+		.unwrap();
+	assert_eq!(call(router(), request).await, "a");
+
+	let request = Request::get("http://x.b.example.com/")
+		.header(CONTENT_TYPE, "application/grpc")
+		.body(Body::empty())
+		.unwrap();
+	assert_eq!(call(router(), request).await, "b");
+
+	let request = Request::get("/").body(Body::empty()).unwrap();
+	assert_eq!(call(router(), request).await, "default");
+}
+
+#[tokio::test]
+async fn make_host_router_makes_services_on_their_first_request() {
+	let made_a = Arc::new(AtomicUsize::new(0));
+	let made_b = Arc::new(AtomicUsize::new(0));
+	let make_router = MakeHostRouter::new(MakeTenant {
+		name: "default",
+		made: Arc::new(AtomicUsize::new(0)),
+	})
+	.host(
+		"a.example.com",
+		MakeTenant {
+			name: "a",
+			made: made_a.clone(),
+		},
+	)
+	.host(
+		"b.example.com",
+		MakeTenant {
+			name: "b",
+			made: made_b.clone(),
+		},
+	);
+	//The client keeps one connection open for all requests
+	let client = TestServer::new(make_router).client();
+	for _ in 0..3 {
+		let request = Request::get("http://test/")
+			.header("host", "a.example.com")
+			.body(Body::empty())
+			.unwrap();
+		let response = client.request(request).await.unwrap();
+		let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		assert_eq!(body, "a");
+	}
+	assert_eq!(made_a.load(Ordering::SeqCst), 1);
+	assert_eq!(made_b.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn make_host_router_routes_to_make_multiplexers() {
+	let tenant = |name| MakeMultiplexer::new(Shared::new(Text("gRPC")), Shared::new(Text(name)));
+	let make_router = MakeHostRouter::new(tenant("default")).host("a.example.com", tenant("a"));
+	let client = TestServer::new(make_router).client();
+	let get = |host: &str, content_type: &str| {
+		let request = Request::get("http://test/")
+			.header("host", host)
+			.header(CONTENT_TYPE, content_type)
+			.body(Body::empty())
+			.unwrap();
+		let response = client.request(request);
+		async move {
+			let response = response.await.unwrap();
+			hyper::body::to_bytes(response.into_body()).await.unwrap()
+		}
+	};
+	assert_eq!(get("a.example.com", "text/plain").await, "a");
+	assert_eq!(get("a.example.com", "application/grpc").await, "gRPC");
+	assert_eq!(get("other.example.com", "text/plain").await, "default");
+}
@@ -0,0 +1,173 @@
+#![cfg(feature = "metrics")]
+
+use std::{
+	convert::Infallible,
+	time::{Duration, Instant},
+};
+
+use hyper::{
+	body::HttpBody, header::CONTENT_TYPE, service::service_fn, Body, HeaderMap, Request, Response,
+};
+use metrics::{SharedString, Unit};
+use metrics_util::{
+	debugging::{DebugValue, DebuggingRecorder, Snapshotter},
+	CompositeKey, MetricKind,
+};
+use tower::{Service, ServiceExt};
+
+use multiplex_tonic_hyper::testing::{DelayedService, ReadyService};
+use multiplex_tonic_hyper::Multiplexer;
+
+/// Taking a snapshot drains the histograms, so all values are read at once
+struct Snapshot(Vec<(CompositeKey, Option<Unit>, Option<SharedString>, DebugValue)>);
+
+impl Snapshot {
+	fn take(snapshotter: &Snapshotter) -> Self {
+		Snapshot(snapshotter.snapshot().into_vec())
+	}
+
+	fn find(&self, kind: MetricKind, name: &str, labels: &[(&str, &str)]) -> Option<&DebugValue> {
+		self.0
+			.iter()
+			.find(|(key, _, _, _)| {
+				let key_labels: Vec<_> = key.key().labels().map(|l| (l.key(), l.value())).collect();
+				key.kind() == kind
+					&& key.key().name() == name
+					&& labels.len() == key_labels.len()
+					&& labels.iter().all(|label| key_labels.contains(label))
+			})
+			.map(|(_, _, _, value)| value)
+	}
+
+	fn counter(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+		match self.find(MetricKind::Counter, name, labels) {
+			Some(DebugValue::Counter(value)) => *value,
+			_ => 0,
+		}
+	}
+
+	fn histogram(&self, name: &str, labels: &[(&str, &str)]) -> Vec<f64> {
+		match self.find(MetricKind::Histogram, name, labels) {
+			Some(DebugValue::Histogram(values)) => values.iter().map(|v| v.into_inner()).collect(),
+			_ => Vec::new(),
+		}
+	}
+}
+
+async fn grpc_ok(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	let (mut sender, body) = Body::channel();
+	tokio::spawn(async move {
+		sender.send_data("12345".into()).await.unwrap();
+		let mut trailers = HeaderMap::new();
+		trailers.insert("grpc-status", "0".parse().unwrap());
+		sender.send_trailers(trailers).await.unwrap();
+	});
+	Ok(Response::new(body))
+}
+
+async fn web(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	Ok(Response::new(Body::from("web")))
+}
+
+async fn grpc_request<S>(multiplexer: &mut S)
+where
+	S: Service<
+		Request<Body>,
+		Response = Response<
+			multiplex_tonic_hyper::EncapsulatedBody<
+				multiplex_tonic_hyper::BranchBody<Body>,
+				multiplex_tonic_hyper::BranchBody<Body>,
+			>,
+		>,
+	>,
+	S::Error: std::fmt::Debug,
+{
+	let request = Request::post("/helloworld.Greeter/SayHello")
+		.header(CONTENT_TYPE, "application/grpc")
+		.body(Body::empty())
+		.unwrap();
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(request)
+		.await
+		.unwrap();
+	let mut body = response.into_body();
+	while body.data().await.is_some() {}
+	body.trailers().await.unwrap();
+}
+
+#[tokio::test]
+async fn counts_requests_bytes_and_grpc_status() {
+	let recorder = DebuggingRecorder::new();
+	let snapshotter = recorder.snapshotter();
+	let _guard = metrics::set_default_local_recorder(&recorder);
+
+	let mut multiplexer = Multiplexer::new(service_fn(grpc_ok), service_fn(web));
+	for _ in 0..2 {
+		let response = multiplexer
+			.ready()
+			.await
+			.unwrap()
+			.call(Request::new(Body::empty()))
+			.await
+			.unwrap();
+		hyper::body::to_bytes(response.into_body()).await.unwrap();
+	}
+	grpc_request(&mut multiplexer).await;
+
+	let snapshot = Snapshot::take(&snapshotter);
+	let grpc = [("branch", "grpc")];
+	let web = [("branch", "web")];
+	assert_eq!(snapshot.counter("multiplexer_requests_total", &web), 2);
+	assert_eq!(snapshot.counter("multiplexer_requests_total", &grpc), 1);
+	assert_eq!(snapshot.counter("multiplexer_body_bytes_total", &web), 6);
+	assert_eq!(snapshot.counter("multiplexer_body_bytes_total", &grpc), 5);
+	assert_eq!(
+		snapshot
+			.histogram("multiplexer_response_latency_seconds", &web)
+			.len(),
+		2
+	);
+	assert_eq!(
+		snapshot
+			.histogram("multiplexer_response_latency_seconds", &grpc)
+			.len(),
+		1
+	);
+	let ok = [("branch", "grpc"), ("code", "0")];
+	assert_eq!(snapshot.counter("multiplexer_grpc_status_total", &ok), 1);
+	assert_eq!(
+		snapshot.counter("multiplexer_grpc_status_total", &web),
+		0,
+		"web responses without trailers have no gRPC status"
+	);
+}
+
+#[tokio::test]
+async fn measures_time_pending_in_poll_ready() {
+	let recorder = DebuggingRecorder::new();
+	let snapshotter = recorder.snapshotter();
+	let _guard = metrics::set_default_local_recorder(&recorder);
+
+	let delay = Duration::from_millis(10);
+	let delayed = DelayedService::new(Instant::now() + delay);
+	let mut multiplexer = Multiplexer::new(ReadyService, delayed);
+	multiplexer.ready().await.unwrap();
+
+	let snapshot = Snapshot::take(&snapshotter);
+	let pending = snapshot.histogram("multiplexer_ready_pending_seconds", &[("branch", "web")]);
+	assert_eq!(pending.len(), 1);
+	assert!(
+		pending[0] >= delay.as_secs_f64() / 2.0,
+		"pending for {}s",
+		pending[0]
+	);
+	assert!(
+		snapshot
+			.histogram("multiplexer_ready_pending_seconds", &[("branch", "grpc")])
+			.is_empty(),
+		"grpc was always ready"
+	);
+}
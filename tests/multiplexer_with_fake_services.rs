@@ -4,13 +4,16 @@ use hyper::{Body, Request};
 use tower::{make::Shared, Service, ServiceExt};
 
 mod common;
-use common::svc;
+use multiplex_tonic_hyper::testing::{
+	DelayedMakeService, DelayedService, ErrorService, FailingFutureMakeService, FailingMakeService,
+	HttpBodyService, ReadyMakeService, ReadyService,
+};
 use multiplex_tonic_hyper::{MakeMultiplexer, Multiplexer};
 
 #[tokio::test]
 async fn multiplexer_propagate_inner_error() {
-	let ready = svc::ReadyService {};
-	let error = svc::ErrorService {};
+	let ready = ReadyService;
+	let error = ErrorService;
 
 	assert!(Multiplexer::new(ready, error).ready().await.is_err());
 	assert!(Multiplexer::new(error, ready).ready().await.is_err());
@@ -19,8 +22,8 @@ async fn multiplexer_propagate_inner_error() {
 #[tokio::test]
 async fn multiplexer_wait_until_all_inners_are_ready() {
 	let until = Instant::now() + Duration::from_millis(10); //10ms should be enough
-	let delayed = svc::DelayedService::new(until);
-	let ready = svc::ReadyService {};
+	let delayed = DelayedService::new(until);
+	let ready = ReadyService;
 
 	let grpc_delayed = tokio::spawn(async move {
 		let before = Instant::now();
@@ -55,9 +58,9 @@ async fn multiplexer_wait_until_all_inners_are_ready() {
 
 #[test]
 fn multiplexer_accepts_any_http_body_as_web_body() {
-	let grpc = svc::ReadyService {};
+	let grpc = ReadyService;
 	// Web has a Response with a body different than hyper::Body
-	let web = svc::HttpBodyService {};
+	let web = HttpBodyService;
 	let service = Multiplexer::new(grpc, web);
 
 	fn impl_service<S: Service<Request<Body>>>(_service: S) {}
@@ -70,8 +73,8 @@ fn multiplexer_accepts_any_http_body_as_web_body() {
 #[test]
 fn multiplexer_accepts_any_http_body_as_grpc_body() {
 	// grpc has a Response with a body different than hyper::Body
-	let grpc = svc::HttpBodyService {};
-	let web = svc::ReadyService {};
+	let grpc = HttpBodyService;
+	let web = ReadyService;
 	let service = Multiplexer::new(grpc, web);
 
 	fn impl_service<S: Service<Request<Body>>>(_service: S) {}
@@ -83,8 +86,8 @@ fn multiplexer_accepts_any_http_body_as_grpc_body() {
 
 #[tokio::test]
 async fn make_multiplexer_service() {
-	let grpc = Shared::new(svc::HttpBodyService {});
-	let web = Shared::new(svc::ReadyService {});
+	let grpc = Shared::new(HttpBodyService);
+	let web = Shared::new(ReadyService);
 
 	let make_service = MakeMultiplexer::new(grpc, web);
 
@@ -96,8 +99,8 @@ async fn make_multiplexer_service() {
 
 #[tokio::test]
 async fn make_multiplexer_propagate_grpc_poll_fail() {
-	let make_grpc = svc::FailingMakeService {};
-	let make_web = svc::ReadyMakeService {};
+	let make_grpc = FailingMakeService;
+	let make_web = ReadyMakeService;
 
 	let mut make_service = MakeMultiplexer::new(make_grpc, make_web);
 	let res = ServiceExt::<()>::ready(&mut make_service).await;
@@ -105,15 +108,15 @@ async fn make_multiplexer_propagate_grpc_poll_fail() {
 	let err = res.err().unwrap().to_string();
 	assert_eq!(
 		err,
-		svc::FailingMakeService::get_err_string(),
+		FailingMakeService::get_err_string(),
 		"Should return same error"
 	);
 }
 
 #[tokio::test]
 async fn make_multiplexer_inner_web_poll_fail() {
-	let make_grpc = svc::ReadyMakeService {};
-	let make_web = svc::FailingMakeService {};
+	let make_grpc = ReadyMakeService;
+	let make_web = FailingMakeService;
 
 	let mut make_service = MakeMultiplexer::new(make_grpc, make_web);
 	let res = ServiceExt::<()>::ready(&mut make_service).await;
@@ -121,15 +124,15 @@ async fn make_multiplexer_inner_web_poll_fail() {
 	let err = res.err().unwrap().to_string();
 	assert_eq!(
 		err,
-		svc::FailingMakeService::get_err_string(),
+		FailingMakeService::get_err_string(),
 		"Should return same error"
 	);
 }
 
 #[tokio::test]
 async fn make_multiplexer_both_poll_fail() {
-	let make_grpc = svc::ReadyMakeService {};
-	let make_web = svc::FailingMakeService {};
+	let make_grpc = ReadyMakeService;
+	let make_web = FailingMakeService;
 
 	let mut make_service = MakeMultiplexer::new(make_grpc, make_web);
 	let res = ServiceExt::<()>::ready(&mut make_service).await;
@@ -139,8 +142,8 @@ async fn make_multiplexer_both_poll_fail() {
 #[tokio::test]
 async fn make_multiplexer_delayed_grpc() {
 	let until = Instant::now() + Duration::from_millis(10); //10ms should be enough
-	let make_grpc = svc::DelayedMakeService::new(until);
-	let make_web = svc::ReadyMakeService {};
+	let make_grpc = DelayedMakeService::new(until);
+	let make_web = ReadyMakeService;
 
 	let mut make_service = MakeMultiplexer::new(make_grpc, make_web);
 
@@ -154,8 +157,8 @@ async fn make_multiplexer_delayed_grpc() {
 #[tokio::test]
 async fn make_multiplexer_delayed_web() {
 	let until = Instant::now() + Duration::from_millis(10); //10ms should be enough
-	let make_grpc = svc::ReadyMakeService {};
-	let make_web = svc::DelayedMakeService::new(until);
+	let make_grpc = ReadyMakeService;
+	let make_web = DelayedMakeService::new(until);
 
 	let mut make_service = MakeMultiplexer::new(make_grpc, make_web);
 
@@ -169,8 +172,8 @@ async fn make_multiplexer_delayed_web() {
 #[tokio::test]
 async fn make_multiplexer_delayed_both() {
 	let until = Instant::now() + Duration::from_millis(10); //10ms should be enough
-	let make_grpc = svc::DelayedMakeService::new(until);
-	let make_web = svc::DelayedMakeService::new(until);
+	let make_grpc = DelayedMakeService::new(until);
+	let make_web = DelayedMakeService::new(until);
 
 	let mut make_service = MakeMultiplexer::new(make_grpc, make_web);
 
@@ -183,8 +186,8 @@ async fn make_multiplexer_delayed_both() {
 
 #[tokio::test]
 async fn make_multiplexer_failing_grpc() {
-	let make_grpc = svc::FailingFutureMakeService {};
-	let make_web = svc::ReadyMakeService {};
+	let make_grpc = FailingFutureMakeService;
+	let make_web = ReadyMakeService;
 	let mut make_service = MakeMultiplexer::new(make_grpc, make_web);
 
 	ServiceExt::<()>::ready(&mut make_service).await.unwrap();
@@ -194,8 +197,8 @@ async fn make_multiplexer_failing_grpc() {
 
 #[tokio::test]
 async fn make_multiplexer_failing_web() {
-	let make_grpc = svc::ReadyMakeService {};
-	let make_web = svc::FailingFutureMakeService {};
+	let make_grpc = ReadyMakeService;
+	let make_web = FailingFutureMakeService;
 	let mut make_service = MakeMultiplexer::new(make_grpc, make_web);
 
 	ServiceExt::<()>::ready(&mut make_service).await.unwrap();
@@ -204,8 +207,8 @@ async fn make_multiplexer_failing_web() {
 }
 #[tokio::test]
 async fn make_multiplexer_failing_both() {
-	let make_grpc = svc::FailingFutureMakeService {};
-	let make_web = svc::FailingFutureMakeService {};
+	let make_grpc = FailingFutureMakeService;
+	let make_web = FailingFutureMakeService;
 	let mut make_service = MakeMultiplexer::new(make_grpc, make_web);
 
 	ServiceExt::<()>::ready(&mut make_service).await.unwrap();
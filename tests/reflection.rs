@@ -0,0 +1,118 @@
+#![cfg(feature = "reflection")]
+
+use std::convert::Infallible;
+
+use hello_world_tonic::hello_world::FILE_DESCRIPTOR_SET;
+use hyper::{body::HttpBody, service::service_fn, Body, Request, Response};
+use tonic::transport::{Channel, Endpoint};
+use tonic_reflection::proto::{
+	server_reflection_client::ServerReflectionClient, server_reflection_request::MessageRequest,
+	server_reflection_response::MessageResponse, ServerReflectionRequest,
+};
+use tower::{make::Shared, ServiceExt};
+
+use multiplex_tonic_hyper::{testing::TestServer, Multiplexer, Reflection};
+
+async fn inner(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	Ok(Response::new(Body::from("inner")))
+}
+
+async fn client() -> ServerReflectionClient<Channel> {
+	let reflection = Reflection::from_descriptor_set(FILE_DESCRIPTOR_SET)
+		.unwrap()
+		.with_descriptor_set(tonic_reflection::proto::FILE_DESCRIPTOR_SET)
+		.unwrap();
+	let server = TestServer::new(Multiplexer::builder().reflection(reflection).build_make(
+		Shared::new(service_fn(inner)),
+		Shared::new(service_fn(inner)),
+	));
+	let channel = Endpoint::from_static("http://test")
+		.connect_with_connector(server.connector())
+		.await
+		.unwrap();
+	ServerReflectionClient::new(channel)
+}
+
+fn request(message_request: MessageRequest) -> ServerReflectionRequest {
+	ServerReflectionRequest {
+		host: String::new(),
+		message_request: Some(message_request),
+	}
+}
+
+#[tokio::test]
+async fn answers_each_request_of_the_stream() {
+	let mut client = client().await;
+	let requests = futures::stream::iter([
+		request(MessageRequest::ListServices(String::new())),
+		request(MessageRequest::FileContainingSymbol(
+			"helloworld.Greeter".into(),
+		)),
+		request(MessageRequest::FileByFilename("missing.proto".into())),
+	]);
+	let mut responses = client
+		.server_reflection_info(requests)
+		.await
+		.unwrap()
+		.into_inner();
+
+	let response = responses.message().await.unwrap().unwrap();
+	let Some(MessageResponse::ListServicesResponse(list)) = response.message_response else {
+		panic!("expected a list of services");
+	};
+	let mut names: Vec<_> = list.service.into_iter().map(|s| s.name).collect();
+	names.sort();
+	assert_eq!(
+		names,
+		[
+			"grpc.reflection.v1alpha.ServerReflection",
+			"helloworld.Greeter"
+		]
+	);
+
+	let response = responses.message().await.unwrap().unwrap();
+	let Some(MessageResponse::FileDescriptorResponse(files)) = response.message_response else {
+		panic!("expected files");
+	};
+	assert!(!files.file_descriptor_proto.is_empty());
+
+	let response = responses.message().await.unwrap().unwrap();
+	let Some(MessageResponse::ErrorResponse(error)) = response.message_response else {
+		panic!("expected an error");
+	};
+	assert_eq!(error.error_code, tonic::Code::NotFound as i32);
+
+	assert!(responses.message().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn other_grpc_calls_reach_the_grpc_service() {
+	let reflection = Reflection::from_descriptor_set(FILE_DESCRIPTOR_SET).unwrap();
+	let multiplexer = Multiplexer::builder()
+		.reflection(reflection)
+		.build(service_fn(inner), service_fn(inner));
+	let request = Request::post("/helloworld.Greeter/SayHello")
+		.header("content-type", "application/grpc")
+		.body(Body::empty())
+		.unwrap();
+	let response = multiplexer.oneshot(request).await.unwrap();
+	let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+	assert_eq!(body, "inner");
+}
+
+#[tokio::test]
+async fn large_messages_are_rejected() {
+	let reflection = Reflection::from_descriptor_set(FILE_DESCRIPTOR_SET).unwrap();
+	let multiplexer = Multiplexer::builder()
+		.reflection(reflection)
+		.build(service_fn(inner), service_fn(inner));
+	//The length prefix is read before the message
+	let request = Request::post("/grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo")
+		.header("content-type", "application/grpc")
+		.body(Body::from(vec![0, 0xff, 0xff, 0xff, 0xff]))
+		.unwrap();
+	let mut body = multiplexer.oneshot(request).await.unwrap().into_body();
+	assert!(body.data().await.is_none());
+	let trailers = body.trailers().await.unwrap().unwrap();
+	assert_eq!(trailers["grpc-status"], "8");
+}
@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use hello_world_tonic::{
+	hello_world::{
+		greeter_client::GreeterClient, greeter_server::GreeterServer, HelloRequest,
+		HelloStreamRequest,
+	},
+	server::{MyGreeter, StreamStats},
+};
+use hyper::{service::service_fn, Body, Request, Response};
+use tonic::transport::{Channel, Endpoint};
+use tower::make::Shared;
+
+use multiplex_tonic_hyper::{testing::TestServer, Multiplexer};
+
+async fn web(_req: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+	Ok(Response::new(Body::from("web")))
+}
+
+async fn serve() -> (GreeterClient<Channel>, StreamStats) {
+	let greeter = MyGreeter::default();
+	let stats = greeter.stats();
+	let server = TestServer::new(Multiplexer::builder().build_make(
+		Shared::new(GreeterServer::new(greeter)),
+		Shared::new(service_fn(web)),
+	));
+	let channel = Endpoint::from_static("http://test")
+		.connect_with_connector(server.connector())
+		.await
+		.unwrap();
+	(GreeterClient::new(channel), stats)
+}
+
+fn stream_request(count: u32) -> HelloStreamRequest {
+	HelloStreamRequest {
+		name: "Ana".into(),
+		count,
+	}
+}
+
+/// Wait until `condition` is true, failing after a second
+async fn eventually(condition: impl Fn() -> bool) {
+	for _ in 0..100 {
+		if condition() {
+			return;
+		}
+		tokio::time::sleep(Duration::from_millis(10)).await;
+	}
+	panic!("condition not reached");
+}
+
+#[tokio::test]
+async fn server_stream_delivers_every_reply_and_the_trailers() {
+	let (mut client, stats) = serve().await;
+	let mut replies = client
+		.say_hello_stream(stream_request(1000))
+		.await
+		.unwrap()
+		.into_inner();
+	let mut count = 0;
+	while let Some(reply) = replies.message().await.unwrap() {
+		count += 1;
+		assert_eq!(reply.message, format!("Hello Ana #{count}!"));
+	}
+	assert_eq!(count, 1000);
+	assert!(replies.trailers().await.unwrap().is_some());
+	eventually(|| stats.open() == 0).await;
+}
+
+#[tokio::test]
+async fn client_stream_is_forwarded_until_its_end() {
+	let (mut client, _) = serve().await;
+	let names = ["Ana", "Bob", "Eve"].map(|name| HelloRequest { name: name.into() });
+	let reply = client
+		.say_hello_to_all(futures::stream::iter(names))
+		.await
+		.unwrap();
+	assert_eq!(reply.into_inner().message, "Hello Ana, Bob, Eve!");
+}
+
+#[tokio::test]
+async fn slow_readers_stop_the_server_stream() {
+	let (mut client, stats) = serve().await;
+	let count = 10_000_000;
+	let mut replies = client
+		.say_hello_stream(stream_request(count))
+		.await
+		.unwrap()
+		.into_inner();
+	assert!(replies.message().await.unwrap().is_some());
+
+	//Without reads, the flow control windows fill and the stream stops being polled
+	let mut sent = stats.sent();
+	loop {
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		let now = stats.sent();
+		if now == sent {
+			break;
+		}
+		sent = now;
+	}
+	assert!(sent < count as usize / 10, "{sent} replies were buffered");
+
+	//Reading again resumes the stream
+	for _ in 0..sent {
+		replies.message().await.unwrap().unwrap();
+	}
+	eventually(|| stats.sent() > sent).await;
+}
+
+#[tokio::test]
+async fn cancelling_the_call_drops_the_server_stream() {
+	let (mut client, stats) = serve().await;
+	let mut replies = client
+		.say_hello_stream(stream_request(u32::MAX))
+		.await
+		.unwrap()
+		.into_inner();
+	replies.message().await.unwrap().unwrap();
+	replies.message().await.unwrap().unwrap();
+	assert_eq!(stats.open(), 1);
+
+	drop(replies);
+	eventually(|| stats.open() == 0).await;
+}
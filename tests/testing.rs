@@ -0,0 +1,71 @@
+#![cfg(feature = "testing")]
+
+use std::time::Duration;
+
+use hello_world_tonic::{
+	hello_world::{greeter_client::GreeterClient, greeter_server::GreeterServer, HelloRequest},
+	server::MyGreeter,
+};
+use hyper::{Body, Request, Response, StatusCode};
+use tonic::transport::Endpoint;
+use tower::make::Shared;
+
+use multiplex_tonic_hyper::{
+	testing::{ScriptedService, TestServer},
+	MakeMultiplexer,
+};
+
+fn serve(web: ScriptedService) -> TestServer {
+	TestServer::new(MakeMultiplexer::new(
+		Shared::new(GreeterServer::new(MyGreeter::default())),
+		Shared::new(web),
+	))
+}
+
+#[tokio::test]
+async fn tonic_and_hyper_clients_share_the_in_memory_server() {
+	let web = ScriptedService::new().with_response(
+		Response::builder()
+			.status(StatusCode::IM_A_TEAPOT)
+			.body(Body::from("teapot"))
+			.unwrap(),
+	);
+	let server = serve(web.clone());
+
+	let channel = Endpoint::from_static("http://test")
+		.connect_with_connector(server.connector())
+		.await
+		.unwrap();
+	let reply = GreeterClient::new(channel)
+		.say_hello(HelloRequest { name: "Ana".into() })
+		.await
+		.unwrap();
+	assert_eq!(reply.into_inner().message, "Hello Ana!");
+
+	let request = Request::get("http://test/tea").body(Body::empty()).unwrap();
+	let response = server.client().request(request).await.unwrap();
+	assert_eq!(response.status(), StatusCode::IM_A_TEAPOT);
+	let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+	assert_eq!(body, "teapot");
+	assert_eq!(web.requests()[0].uri, "/tea");
+}
+
+#[tokio::test]
+async fn requests_wait_for_the_readiness_handle() {
+	let web = ScriptedService::new();
+	let readiness = web.readiness();
+	readiness.set_pending();
+	let server = serve(web.clone());
+
+	let client = server.http2_client();
+	let request = client.get("http://test/".parse().unwrap());
+	let waiting = tokio::spawn(request);
+	tokio::time::sleep(Duration::from_millis(50)).await;
+	assert!(!waiting.is_finished());
+	assert!(web.requests().is_empty());
+
+	readiness.set_ready();
+	let response = waiting.await.unwrap().unwrap();
+	assert_eq!(response.status(), StatusCode::OK);
+	assert_eq!(web.requests().len(), 1);
+}
@@ -0,0 +1,130 @@
+use std::{convert::Infallible, time::Duration};
+
+use hyper::{body::HttpBody, header::CONTENT_TYPE, service::service_fn, Body, Request, Response};
+use tokio::time::{sleep, Instant};
+use tower::{Service, ServiceExt};
+
+use multiplex_tonic_hyper::{Branch, Multiplexer, Timeout};
+
+async fn slow(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	sleep(Duration::from_secs(10)).await;
+	Ok(Response::new(Body::empty()))
+}
+
+async fn endless(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	let (mut sender, body) = Body::channel();
+	tokio::spawn(async move {
+		sender.send_data("message".into()).await.ok();
+		futures::future::pending::<()>().await;
+		drop(sender);
+	});
+	Ok(Response::new(body))
+}
+
+fn grpc_request(timeout: Option<&str>) -> Request<Body> {
+	let mut request =
+		Request::post("/helloworld.Greeter/SayHello").header(CONTENT_TYPE, "application/grpc");
+	if let Some(timeout) = timeout {
+		request = request.header("grpc-timeout", timeout);
+	}
+	request.body(Body::empty()).unwrap()
+}
+
+#[tokio::test(start_paused = true)]
+async fn slow_responses_time_out_per_branch() {
+	let timeout = Timeout::new(Duration::from_secs(1));
+	let mut multiplexer = Multiplexer::builder()
+		.timeout(Branch::Grpc, timeout)
+		.timeout(Branch::Web, timeout)
+		.build(service_fn(slow), service_fn(slow));
+
+	let start = Instant::now();
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(grpc_request(Some("100m")))
+		.await
+		.unwrap();
+	assert_eq!(response.headers()["grpc-status"], "4");
+	assert_eq!(start.elapsed(), Duration::from_millis(100));
+
+	let start = Instant::now();
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(grpc_request(None))
+		.await
+		.unwrap();
+	assert_eq!(response.headers()["grpc-status"], "4");
+	assert_eq!(start.elapsed(), Duration::from_secs(1));
+
+	let start = Instant::now();
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(Request::new(Body::empty()))
+		.await
+		.unwrap();
+	assert_eq!(response.status(), 504);
+	assert_eq!(start.elapsed(), Duration::from_secs(1));
+}
+
+#[tokio::test(start_paused = true)]
+async fn grpc_streams_end_with_deadline_exceeded() {
+	let mut multiplexer = Multiplexer::builder()
+		.timeout(
+			Branch::Grpc,
+			Timeout::new(Duration::from_secs(1)).include_body(true),
+		)
+		.build(service_fn(endless), service_fn(endless));
+
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(grpc_request(Some("5S")))
+		.await
+		.unwrap();
+	let mut body = response.into_body();
+	assert_eq!(body.data().await.unwrap().unwrap(), "message");
+	assert!(body.data().await.is_none());
+	let trailers = body.trailers().await.unwrap().unwrap();
+	assert_eq!(trailers["grpc-status"], "4");
+}
+
+#[tokio::test(start_paused = true)]
+async fn web_bodies_time_out_only_if_included() {
+	let timeout = Timeout::new(Duration::from_secs(1));
+	let mut excluded = Multiplexer::builder()
+		.timeout(Branch::Web, timeout)
+		.build(service_fn(endless), service_fn(endless));
+	let mut included = Multiplexer::builder()
+		.timeout(Branch::Web, timeout.include_body(true))
+		.build(service_fn(endless), service_fn(endless));
+
+	let response = excluded
+		.ready()
+		.await
+		.unwrap()
+		.call(Request::new(Body::empty()))
+		.await
+		.unwrap();
+	let mut body = response.into_body();
+	body.data().await.unwrap().unwrap();
+	let next = tokio::time::timeout(Duration::from_secs(5), body.data()).await;
+	assert!(next.is_err(), "still streaming after the timeout");
+
+	let response = included
+		.ready()
+		.await
+		.unwrap()
+		.call(Request::new(Body::empty()))
+		.await
+		.unwrap();
+	let mut body = response.into_body();
+	body.data().await.unwrap().unwrap();
+	assert!(body.data().await.unwrap().is_err());
+}
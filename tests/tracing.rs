@@ -0,0 +1,205 @@
+#![cfg(feature = "tracing")]
+
+use std::{
+	collections::HashMap,
+	convert::Infallible,
+	sync::{Arc, Mutex},
+};
+
+use hyper::{
+	body::HttpBody, header::CONTENT_TYPE, service::service_fn, Body, HeaderMap, Request, Response,
+};
+use tower::{Service, ServiceExt};
+use tracing::{field::Field, span, Subscriber};
+use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+use multiplex_tonic_hyper::Multiplexer;
+
+type Fields = HashMap<String, String>;
+
+/// Event with its fields, and the fields of the span where it happened
+#[derive(Debug)]
+struct Event {
+	fields: Fields,
+	span: Option<Fields>,
+}
+
+/// Layer that stores the fields of every span and event
+#[derive(Clone, Default)]
+struct Capture {
+	spans: Arc<Mutex<HashMap<span::Id, Fields>>>,
+	events: Arc<Mutex<Vec<Event>>>,
+}
+
+impl Capture {
+	fn multiplexer_spans(&self) -> Vec<Fields> {
+		let spans = self.spans.lock().unwrap();
+		spans.values().cloned().collect()
+	}
+	fn event(&self, message: &str) -> Option<Event> {
+		let mut events = self.events.lock().unwrap();
+		let index = events
+			.iter()
+			.position(|event| event.fields.get("message").map(String::as_str) == Some(message))?;
+		Some(events.remove(index))
+	}
+}
+
+struct Visitor<'a>(&'a mut Fields);
+impl tracing::field::Visit for Visitor<'_> {
+	fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+		self.0.insert(field.name().into(), format!("{value:?}"));
+	}
+	fn record_str(&mut self, field: &Field, value: &str) {
+		self.0.insert(field.name().into(), value.into());
+	}
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Capture {
+	fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, _ctx: Context<'_, S>) {
+		if attrs.metadata().name() != "multiplexer" {
+			return;
+		}
+		let mut fields = Fields::new();
+		attrs.record(&mut Visitor(&mut fields));
+		self.spans.lock().unwrap().insert(id.clone(), fields);
+	}
+
+	fn on_record(&self, id: &span::Id, values: &span::Record<'_>, _ctx: Context<'_, S>) {
+		if let Some(fields) = self.spans.lock().unwrap().get_mut(id) {
+			values.record(&mut Visitor(fields));
+		}
+	}
+
+	fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+		let mut fields = Fields::new();
+		event.record(&mut Visitor(&mut fields));
+		let span = ctx
+			.event_span(event)
+			.and_then(|span| self.spans.lock().unwrap().get(&span.id()).cloned());
+		self.events.lock().unwrap().push(Event { fields, span });
+	}
+}
+
+fn capture() -> (Capture, tracing::subscriber::DefaultGuard) {
+	let capture = Capture::default();
+	let subscriber = tracing_subscriber::registry().with(capture.clone());
+	let guard = tracing::subscriber::set_default(subscriber);
+	(capture, guard)
+}
+
+async fn grpc_with_trailers(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	tracing::info!("inside grpc service");
+	let (mut sender, body) = Body::channel();
+	tokio::spawn(async move {
+		sender.send_data("message".into()).await.unwrap();
+		let mut trailers = HeaderMap::new();
+		trailers.insert("grpc-status", "5".parse().unwrap());
+		sender.send_trailers(trailers).await.unwrap();
+	});
+	Ok(Response::new(body))
+}
+
+async fn web(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	Ok(Response::new(Body::from("web")))
+}
+
+async fn failing(_req: Request<Body>) -> Result<Response<Body>, String> {
+	Err("web failed".into())
+}
+
+#[tokio::test]
+async fn grpc_span_records_method_and_status() {
+	let (capture, _guard) = capture();
+	let mut multiplexer = Multiplexer::new(service_fn(grpc_with_trailers), service_fn(web));
+
+	let request = Request::post("/helloworld.Greeter/SayHello")
+		.header(CONTENT_TYPE, "application/grpc")
+		.body(Body::empty())
+		.unwrap();
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(request)
+		.await
+		.unwrap();
+	let mut body = response.into_body();
+	while body.data().await.is_some() {}
+	body.trailers().await.unwrap();
+
+	let spans = capture.multiplexer_spans();
+	assert_eq!(spans.len(), 1);
+	let span = &spans[0];
+	assert_eq!(span["branch"], "grpc");
+	assert_eq!(span["http.method"], "POST");
+	assert_eq!(span["http.version"], "HTTP/1.1");
+	assert_eq!(span["grpc.service"], "helloworld.Greeter");
+	assert_eq!(span["grpc.method"], "SayHello");
+	assert_eq!(span["grpc.status"], "5");
+
+	let inside = capture.event("inside grpc service").unwrap();
+	assert_eq!(
+		inside.span.unwrap()["branch"],
+		"grpc",
+		"inner future runs in the span"
+	);
+	let completed = capture.event("stream completed").unwrap();
+	assert_eq!(completed.fields["grpc.status"], "5");
+}
+
+#[tokio::test]
+async fn web_span_has_no_grpc_fields() {
+	let (capture, _guard) = capture();
+	let mut multiplexer = Multiplexer::new(service_fn(grpc_with_trailers), service_fn(web));
+
+	let request = Request::get("/index.html").body(Body::empty()).unwrap();
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(request)
+		.await
+		.unwrap();
+	hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+	let spans = capture.multiplexer_spans();
+	assert_eq!(spans.len(), 1);
+	assert_eq!(spans[0]["branch"], "web");
+	assert_eq!(spans[0]["http.method"], "GET");
+	assert_eq!(spans[0]["http.status"], "200");
+	assert!(!spans[0].contains_key("grpc.service"));
+}
+
+#[tokio::test]
+async fn branch_error_emits_event() {
+	let (capture, _guard) = capture();
+	let mut multiplexer = Multiplexer::new(service_fn(grpc_with_trailers), service_fn(failing));
+
+	let request = Request::new(Body::empty());
+	let result = multiplexer.ready().await.unwrap().call(request).await;
+	assert!(result.is_err());
+
+	let event = capture.event("inner service failed").unwrap();
+	assert_eq!(event.fields["error"], "web failed");
+	assert_eq!(event.span.unwrap()["branch"], "web");
+}
+
+#[tokio::test]
+async fn dropped_body_emits_cancelled_event() {
+	let (capture, _guard) = capture();
+	let mut multiplexer = Multiplexer::new(service_fn(grpc_with_trailers), service_fn(web));
+
+	let request = Request::new(Body::empty());
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(request)
+		.await
+		.unwrap();
+	drop(response);
+
+	assert!(capture.event("stream cancelled").is_some());
+	assert!(capture.event("stream completed").is_none());
+}
@@ -0,0 +1,188 @@
+#![cfg(feature = "transcoding")]
+
+use std::convert::Infallible;
+
+use futures::StreamExt;
+use hello_world_tonic::{
+	hello_world::{greeter_server::GreeterServer, FILE_DESCRIPTOR_SET},
+	server::MyGreeter,
+};
+use hyper::{service::service_fn, Body, HeaderMap, Request, Response, StatusCode};
+use serde_json::{json, Value};
+use tower::{Service, ServiceExt};
+
+use multiplex_tonic_hyper::{BodyLimit, Branch, Multiplexer, Transcoder};
+
+async fn web(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	Ok(Response::new(Body::from(format!(
+		"web {}",
+		req.uri().path()
+	))))
+}
+
+async fn call<S, B>(multiplexer: &mut S, request: Request<Body>) -> (StatusCode, Vec<u8>)
+where
+	S: Service<Request<Body>, Response = Response<B>>,
+	S::Error: std::fmt::Debug,
+	B: hyper::body::HttpBody,
+	B::Error: std::fmt::Debug,
+{
+	let response = multiplexer
+		.ready()
+		.await
+		.unwrap()
+		.call(request)
+		.await
+		.unwrap();
+	let status = response.status();
+	let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+	(status, body.to_vec())
+}
+
+fn transcoder() -> Transcoder {
+	Transcoder::from_descriptor_set(FILE_DESCRIPTOR_SET).unwrap()
+}
+
+#[tokio::test]
+async fn json_requests_call_the_grpc_service() {
+	let mut multiplexer = Multiplexer::builder()
+		.transcoding(transcoder())
+		.build(GreeterServer::new(MyGreeter::default()), service_fn(web));
+
+	let request = Request::post("/v1/greeter/hello")
+		.header("content-type", "application/json")
+		.body(Body::from(r#"{"name":"Ana"}"#))
+		.unwrap();
+	let (status, body) = call(&mut multiplexer, request).await;
+	assert_eq!(status, 200);
+	let body: Value = serde_json::from_slice(&body).unwrap();
+	assert_eq!(body, json!({"message": "Hello Ana!"}));
+
+	let request = Request::get("/v1/greeter/hello/Bo%20b")
+		.body(Body::empty())
+		.unwrap();
+	let (status, body) = call(&mut multiplexer, request).await;
+	assert_eq!(status, 200);
+	let body: Value = serde_json::from_slice(&body).unwrap();
+	assert_eq!(body, json!({"message": "Hello Bo b!"}));
+}
+
+#[tokio::test]
+async fn other_requests_fall_through_to_web() {
+	let mut multiplexer = Multiplexer::builder()
+		.transcoding(transcoder())
+		.build(GreeterServer::new(MyGreeter::default()), service_fn(web));
+
+	for request in [
+		Request::get("/v1/greeter/hello").body(Body::empty()),
+		Request::get("/v1/greeter/hello/a/b").body(Body::empty()),
+		Request::get("/index.html").body(Body::empty()),
+	] {
+		let request = request.unwrap();
+		let path = request.uri().path().to_owned();
+		let (status, body) = call(&mut multiplexer, request).await;
+		assert_eq!(status, 200);
+		assert_eq!(body, format!("web {path}").as_bytes());
+	}
+}
+
+#[tokio::test]
+async fn invalid_json_is_a_bad_request() {
+	let mut multiplexer = Multiplexer::builder()
+		.transcoding(transcoder())
+		.build(GreeterServer::new(MyGreeter::default()), service_fn(web));
+
+	let request = Request::post("/v1/greeter/hello")
+		.body(Body::from(r#"{"name":1}"#))
+		.unwrap();
+	let (status, body) = call(&mut multiplexer, request).await;
+	assert_eq!(status, 400);
+	let body: Value = serde_json::from_slice(&body).unwrap();
+	assert_eq!(body["code"], 3);
+}
+
+#[tokio::test]
+async fn grpc_errors_map_to_http_statuses() {
+	let grpc = service_fn(|_req: Request<Body>| async {
+		Ok::<_, Infallible>(
+			Response::builder()
+				.header("content-type", "application/grpc")
+				.header("grpc-status", "5")
+				.header("grpc-message", "no%20greeter")
+				.body(Body::empty())
+				.unwrap(),
+		)
+	});
+	let mut multiplexer = Multiplexer::builder()
+		.transcoding(transcoder())
+		.build(grpc, service_fn(web));
+
+	let request = Request::get("/v1/greeter/hello/Ana")
+		.body(Body::empty())
+		.unwrap();
+	let (status, body) = call(&mut multiplexer, request).await;
+	assert_eq!(status, 404);
+	let body: Value = serde_json::from_slice(&body).unwrap();
+	assert_eq!(body, json!({"code": 5, "message": "no greeter"}));
+}
+
+#[tokio::test]
+async fn errors_after_the_headers_map_to_http_statuses() {
+	let grpc = service_fn(|_req: Request<Body>| async {
+		let (mut sender, body) = Body::channel();
+		tokio::spawn(async move {
+			let mut trailers = HeaderMap::new();
+			trailers.insert("grpc-status", "7".parse().unwrap());
+			trailers.insert("grpc-message", "not%20allowed".parse().unwrap());
+			sender.send_trailers(trailers).await.unwrap();
+		});
+		Ok::<_, Infallible>(
+			Response::builder()
+				.header("content-type", "application/grpc")
+				.body(body)
+				.unwrap(),
+		)
+	});
+	let mut multiplexer = Multiplexer::builder()
+		.transcoding(transcoder())
+		.build(grpc, service_fn(web));
+
+	let request = Request::get("/v1/greeter/hello/Ana")
+		.body(Body::empty())
+		.unwrap();
+	let (status, body) = call(&mut multiplexer, request).await;
+	assert_eq!(status, StatusCode::FORBIDDEN);
+	let body: Value = serde_json::from_slice(&body).unwrap();
+	assert_eq!(body, json!({"code": 7, "message": "not allowed"}));
+}
+
+#[tokio::test]
+async fn large_bodies_use_the_grpc_body_limit() {
+	let mut multiplexer = Multiplexer::builder()
+		.transcoding(transcoder())
+		.body_limit(Branch::Grpc, BodyLimit::new(32))
+		.build(GreeterServer::new(MyGreeter::default()), service_fn(web));
+	let json = format!(r#"{{"name":"{}"}}"#, "a".repeat(100));
+
+	let with_length = Request::post("/v1/greeter/hello")
+		.header("content-length", json.len())
+		.body(Body::from(json.clone()))
+		.unwrap();
+	let chunks = json
+		.into_bytes()
+		.chunks(10)
+		.map(|chunk| Ok::<_, Infallible>(chunk.to_vec()))
+		.collect::<Vec<_>>();
+	let streamed = Request::post("/v1/greeter/hello")
+		//Only the start of the body is read, it never ends
+		.body(Body::wrap_stream(
+			futures::stream::iter(chunks).chain(futures::stream::pending()),
+		))
+		.unwrap();
+	for request in [with_length, streamed] {
+		let (status, body) = call(&mut multiplexer, request).await;
+		assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+		let body: Value = serde_json::from_slice(&body).unwrap();
+		assert_eq!(body["code"], 8);
+	}
+}
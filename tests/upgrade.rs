@@ -0,0 +1,132 @@
+use std::{convert::Infallible, net::SocketAddr, time::Duration};
+
+use hyper::{
+	body::Bytes,
+	header::{CONNECTION, UPGRADE},
+	service::service_fn,
+	Body, Method, Request, Response, StatusCode,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tower::{make::Shared, Service, ServiceExt};
+
+use multiplex_tonic_hyper::{BodyLimit, Branch, Multiplexer, WebSocketRouter};
+
+async fn text(text: &'static str) -> Result<Response<Body>, Infallible> {
+	Ok(Response::new(Body::from(text)))
+}
+
+/// Answers the upgrade, and echoes the upgraded connection
+async fn echo(mut req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	let status = match req.method() {
+		&Method::CONNECT => StatusCode::OK,
+		_ => StatusCode::SWITCHING_PROTOCOLS,
+	};
+	tokio::spawn(async move {
+		let upgraded = hyper::upgrade::on(&mut req).await.unwrap();
+		let (mut reader, mut writer) = tokio::io::split(upgraded);
+		tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+	});
+	let mut response = Response::builder().status(status);
+	if status == StatusCode::SWITCHING_PROTOCOLS {
+		response = response
+			.header(CONNECTION, "upgrade")
+			.header(UPGRADE, "websocket");
+	}
+	Ok(response.body(Body::empty()).unwrap())
+}
+
+#[tokio::test]
+async fn http1_upgrades_reach_the_web_service() {
+	let make_multiplexer = Multiplexer::builder()
+		.body_limit(Branch::Web, BodyLimit::new(1024))
+		.build_make(
+			Shared::new(service_fn(|_| text("grpc"))),
+			Shared::new(service_fn(echo)),
+		);
+	let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+	let server = hyper::Server::bind(&addr).serve(make_multiplexer);
+	let addr = server.local_addr();
+	tokio::spawn(server);
+
+	let request = Request::get(format!("http://{addr}/ws"))
+		.header(CONNECTION, "upgrade")
+		.header(UPGRADE, "websocket")
+		.body(Body::empty())
+		.unwrap();
+	let response = hyper::Client::new().request(request).await.unwrap();
+	assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+	let mut upgraded = hyper::upgrade::on(response).await.unwrap();
+	upgraded.write_all(b"ping").await.unwrap();
+	let mut echoed = [0; 4];
+	upgraded.read_exact(&mut echoed).await.unwrap();
+	assert_eq!(&echoed, b"ping");
+}
+
+#[tokio::test]
+async fn http2_extended_connect_reaches_the_websocket_service() {
+	let make_multiplexer = Multiplexer::builder().build_make(
+		Shared::new(service_fn(|_| text("grpc"))),
+		Shared::new(WebSocketRouter::new(
+			service_fn(echo),
+			service_fn(|_| text("web")),
+		)),
+	);
+	let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+	let server = hyper::Server::bind(&addr)
+		.http2_only(true)
+		.http2_enable_connect_protocol()
+		.serve(make_multiplexer);
+	let addr = server.local_addr();
+	tokio::spawn(server);
+
+	let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+	let (send_request, connection) = h2::client::handshake(tcp).await.unwrap();
+	tokio::spawn(connection);
+	let mut send_request = send_request.ready().await.unwrap();
+	//The server enables the protocol in its first settings
+	for _ in 0..100 {
+		if send_request.is_extended_connect_protocol_enabled() {
+			break;
+		}
+		tokio::time::sleep(Duration::from_millis(10)).await;
+	}
+	let request = Request::builder()
+		.method(Method::CONNECT)
+		.uri(format!("http://{addr}/ws"))
+		.extension(h2::ext::Protocol::from_static("websocket"))
+		.body(())
+		.unwrap();
+	let (response, mut stream) = send_request.send_request(request, false).unwrap();
+	let response = response.await.unwrap();
+	assert_eq!(response.status(), StatusCode::OK);
+	stream.send_data(Bytes::from("ping"), false).unwrap();
+	let echoed = response.into_body().data().await.unwrap().unwrap();
+	assert_eq!(echoed, "ping");
+}
+
+#[tokio::test]
+async fn router_sends_other_requests_to_web() {
+	let router = WebSocketRouter::new(
+		service_fn(|_| text("websocket")),
+		service_fn(|_| text("web")),
+	);
+	let mut multiplexer = Multiplexer::new(service_fn(|_| text("grpc")), router);
+
+	let upgrade = Request::get("/ws")
+		.header(CONNECTION, "upgrade")
+		.header(UPGRADE, "websocket")
+		.body(Body::empty())
+		.unwrap();
+	let plain = Request::get("/ws").body(Body::empty()).unwrap();
+	for (request, expected) in [(upgrade, "websocket"), (plain, "web")] {
+		let response = multiplexer
+			.ready()
+			.await
+			.unwrap()
+			.call(request)
+			.await
+			.unwrap();
+		let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		assert_eq!(body, expected);
+	}
+}